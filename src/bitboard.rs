@@ -1,5 +1,4 @@
 use super::attacks;
-use super::piece::*;
 use super::square::*;
 use super::types::*;
 use std::fmt;
@@ -27,6 +26,14 @@ impl Bitboard {
         SQ::from((63 - self.0.leading_zeros()) as u8)
     }
 
+    pub fn first(&self) -> Option<SQ> {
+        (self.0 != 0).then(|| self.lsb())
+    }
+
+    pub fn last(&self) -> Option<SQ> {
+        (self.0 != 0).then(|| self.msb())
+    }
+
     pub fn pop_lsb(&mut self) -> SQ {
         let s = self.lsb();
         self.0 &= self.0 - 1;
@@ -60,6 +67,11 @@ impl Bitboard {
         }
     }
 
+    pub fn shift_by(self, dir: Direction, n: u32) -> Self {
+        // Shift one step at a time so the file masks are applied at every step.
+        (0..n).fold(self, |bb, _| bb.shift(dir))
+    }
+
     pub fn reverse(self) -> Self {
         Self(self.0.reverse_bits())
     }
@@ -87,41 +99,6 @@ impl Bitboard {
     pub fn between(sq1: SQ, sq2: SQ) -> Self {
         unsafe { BETWEEN_BB[sq1][sq2] }
     }
-
-    pub fn oo_mask(c: Color) -> Self {
-        match c {
-            Color::White => Self::WHITE_OO_MASK,
-            Color::Black => Self::BLACK_OO_MASK,
-        }
-    }
-
-    pub fn ooo_mask(c: Color) -> Self {
-        match c {
-            Color::White => Self::WHITE_OOO_MASK,
-            Color::Black => Self::BLACK_OOO_MASK,
-        }
-    }
-
-    pub fn oo_blockers_mask(c: Color) -> Self {
-        match c {
-            Color::White => Self::WHITE_OO_BLOCKERS_AND_ATTACKERS_MASK,
-            Color::Black => Self::BLACK_OO_BLOCKERS_AND_ATTACKERS_MASK,
-        }
-    }
-
-    pub fn ooo_blockers_mask(c: Color) -> Self {
-        match c {
-            Color::White => Self::WHITE_OOO_BLOCKERS_AND_ATTACKERS_MASK,
-            Color::Black => Self::BLACK_OOO_BLOCKERS_AND_ATTACKERS_MASK,
-        }
-    }
-
-    pub fn ignore_ooo_danger(c: Color) -> Self {
-        match c {
-            Color::White => Self::WHITE_OOO_DANGER,
-            Color::Black => Self::BLACK_OOO_DANGER,
-        }
-    }
 }
 
 impl From<Hash> for Bitboard {
@@ -256,10 +233,9 @@ impl Iterator for Bitboard {
     type Item = SQ;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.0 == 0 {
-            return None;
-        }
-        Some(self.pop_lsb())
+        let sq = self.first()?;
+        self.0 &= self.0 - 1;
+        Some(sq)
     }
 }
 
@@ -298,21 +274,6 @@ impl Bitboard {
     pub const LIGHT_SQUARES: Self = B!(0x55AA55AA55AA55AA);
     pub const DARK_SQUARES: Self = B!(0xAA55AA55AA55AA55);
 
-    pub const WHITE_OO_MASK: Self = B!(0x90);
-    pub const WHITE_OOO_MASK: Self = B!(0x11);
-    pub const WHITE_OO_BLOCKERS_AND_ATTACKERS_MASK: Self = B!(0x60);
-    pub const WHITE_OOO_BLOCKERS_AND_ATTACKERS_MASK: Self = B!(0xe);
-
-    pub const BLACK_OO_MASK: Self = B!(0x9000000000000000);
-    pub const BLACK_OOO_MASK: Self = B!(0x1100000000000000);
-    pub const BLACK_OO_BLOCKERS_AND_ATTACKERS_MASK: Self = B!(0x6000000000000000);
-    pub const BLACK_OOO_BLOCKERS_AND_ATTACKERS_MASK: Self = B!(0xE00000000000000);
-
-    pub const ALL_CASTLING_MASK: Self = B!(0x9100000000000091);
-
-    pub const WHITE_OOO_DANGER: Self = B!(0x2);
-    pub const BLACK_OOO_DANGER: Self = B!(0x200000000000000);
-
     pub const CENTER: Self = B!(0x1818000000);
 }
 
@@ -364,3 +325,60 @@ pub fn init_bb() {
         LINES_BB = init_lines();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::bitboard::*;
+
+    #[test]
+    fn first_and_last() {
+        assert_eq!(Bitboard::ZERO.first(), None);
+        assert_eq!(Bitboard::ZERO.last(), None);
+
+        assert_eq!(SQ::E4.bb().first(), Some(SQ::E4));
+        assert_eq!(SQ::E4.bb().last(), Some(SQ::E4));
+
+        let bb = SQ::B2.bb() | SQ::G7.bb();
+        assert_eq!(bb.first(), Some(SQ::B2));
+        assert_eq!(bb.last(), Some(SQ::G7));
+
+        assert_eq!(Bitboard::ALL.first(), Some(SQ::A1));
+        assert_eq!(Bitboard::ALL.last(), Some(SQ::H8));
+    }
+
+    #[test]
+    fn iterate_and_count() {
+        assert_eq!(Bitboard::ZERO.count(), 0);
+        assert_eq!(Bitboard::ALL.count(), SQ::N_SQUARES);
+
+        let bb = SQ::A1.bb() | SQ::D5.bb() | SQ::H8.bb();
+        assert_eq!(bb.count(), bb.pop_count() as usize);
+        assert_eq!(bb.collect::<Vec<SQ>>(), vec![SQ::A1, SQ::D5, SQ::H8]);
+    }
+
+    #[test]
+    fn shift_by() {
+        assert_eq!(SQ::D4.bb().shift_by(Direction::North, 0), SQ::D4.bb());
+        assert_eq!(SQ::D4.bb().shift_by(Direction::North, 3), SQ::D7.bb());
+        assert_eq!(SQ::D4.bb().shift_by(Direction::SouthWest, 2), SQ::B2.bb());
+        assert_eq!(SQ::D4.bb().shift_by(Direction::NorthNorth, 2), SQ::D8.bb());
+        assert_eq!(SQ::D4.bb().shift_by(Direction::South, 4), Bitboard::ZERO);
+
+        // Pieces falling off the east or west edge must not wrap onto the next rank.
+        let h_file = File::H.bb();
+        for n in 1..8 {
+            assert_eq!(h_file.shift_by(Direction::East, n), Bitboard::ZERO);
+            assert_eq!(File::A.bb().shift_by(Direction::West, n), Bitboard::ZERO);
+            assert_eq!(h_file.shift_by(Direction::NorthEast, n), Bitboard::ZERO);
+            assert_eq!(
+                File::A.bb().shift_by(Direction::SouthWest, n),
+                Bitboard::ZERO
+            );
+        }
+
+        assert_eq!(SQ::F3.bb().shift_by(Direction::East, 3), Bitboard::ZERO);
+        assert_eq!(SQ::F3.bb().shift_by(Direction::East, 2), SQ::H3.bb());
+        assert_eq!(SQ::C6.bb().shift_by(Direction::West, 2), SQ::A6.bb());
+        assert_eq!(SQ::C6.bb().shift_by(Direction::West, 3), Bitboard::ZERO);
+    }
+}
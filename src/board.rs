@@ -1,5 +1,6 @@
 use super::attacks;
 use super::bitboard::*;
+use super::castling::*;
 use super::moov::*;
 use super::move_list::*;
 use super::nnue::*;
@@ -8,7 +9,10 @@ use super::square::*;
 use super::types::*;
 use super::zobrist::*;
 use regex::Regex;
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::fmt;
+use std::iter;
 use std::sync::LazyLock;
 
 #[derive(Clone)]
@@ -16,11 +20,16 @@ pub struct Board {
     board: SQMap<Option<Piece>>,
     piece_type_bb: PieceTypeMap<Bitboard>,
     color_bb: ColorMap<Bitboard>,
-    history: [HistoryEntry; Self::N_HISTORIES],
+    history: Vec<HistoryEntry>,
     ctm: Color,
     ply: usize,
+    ply_offset: usize,
     hasher: Hasher,
     network: Network,
+    use_nnue: bool,
+    castling: Castling,
+    chess960: bool,
+    check_info: Cell<Option<(Bitboard, Bitboard)>>,
 }
 
 impl Board {
@@ -33,13 +42,17 @@ impl Board {
     }
 
     pub fn clear(&mut self) {
+        self.check_info.set(None);
         self.ply = 0;
+        self.ply_offset = 0;
         self.ctm = Color::White;
-        self.history = [HistoryEntry::default(); Self::N_HISTORIES];
+        self.history.truncate(Self::N_HISTORIES);
+        self.history.fill(HistoryEntry::default());
 
         self.color_bb = ColorMap::new([Bitboard::ZERO; Color::N_COLORS]);
         self.piece_type_bb = PieceTypeMap::new([Bitboard::ZERO; PieceType::N_PIECE_TYPES]);
         self.board = SQMap::new([None; SQ::N_SQUARES]);
+        self.castling = Castling::default();
 
         self.hasher.clear();
         self.network = Network::new();
@@ -55,6 +68,29 @@ impl Board {
 
     pub fn set_piece_at(&mut self, pc: Piece, sq: SQ) {
         self.network.activate(pc, sq);
+        self.put_piece(pc, sq);
+    }
+
+    pub fn remove_piece(&mut self, sq: SQ) {
+        let pc = self.take_piece(sq);
+        self.network.deactivate(pc, sq);
+    }
+
+    pub fn move_piece_quiet(&mut self, from_sq: SQ, to_sq: SQ) {
+        let pc = self.shift_piece(from_sq, to_sq);
+        self.network.move_piece(pc, from_sq, to_sq);
+    }
+
+    pub fn move_piece(&mut self, from_sq: SQ, to_sq: SQ) {
+        self.remove_piece(to_sq);
+        self.move_piece_quiet(from_sq, to_sq);
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // These leave the network alone. Unmaking a move restores the
+    // accumulator saved when it was made instead.
+    ///////////////////////////////////////////////////////////////////
+    fn put_piece(&mut self, pc: Piece, sq: SQ) {
         self.hasher.update_piece(pc, sq);
 
         self.board[sq] = Some(pc);
@@ -62,25 +98,24 @@ impl Board {
         self.piece_type_bb[pc.type_of()] |= sq.bb();
     }
 
-    pub fn remove_piece(&mut self, sq: SQ) {
+    fn take_piece(&mut self, sq: SQ) -> Piece {
         let pc = self
             .piece_at(sq)
             .expect("Tried to remove a piece from an empty square.");
 
-        self.network.deactivate(pc, sq);
         self.hasher.update_piece(pc, sq);
 
         self.piece_type_bb[pc.type_of()] &= !sq.bb();
         self.color_bb[pc.color_of()] &= !sq.bb();
         self.board[sq] = None;
+        pc
     }
 
-    pub fn move_piece_quiet(&mut self, from_sq: SQ, to_sq: SQ) {
+    fn shift_piece(&mut self, from_sq: SQ, to_sq: SQ) -> Piece {
         let pc = self
             .piece_at(from_sq)
             .expect("Tried to move a piece off of an empty square.");
 
-        self.network.move_piece(pc, from_sq, to_sq);
         self.hasher.move_piece(pc, from_sq, to_sq);
 
         let mask = from_sq.bb() | to_sq.bb();
@@ -88,15 +123,98 @@ impl Board {
         self.color_bb[pc.color_of()] ^= mask;
         self.board[to_sq] = self.board[from_sq];
         self.board[from_sq] = None;
+        pc
     }
 
-    pub fn move_piece(&mut self, from_sq: SQ, to_sq: SQ) {
-        self.remove_piece(to_sq);
-        self.move_piece_quiet(from_sq, to_sq);
+    ///////////////////////////////////////////////////////////////////
+    // The network is kept up to date either way, so switching back to
+    // it doesn't need a refresh. Without it we fall back to material.
+    //
+    // Both evals are from white's point of view and have no tempo term.
+    // The sign flip for the side to move happens here and only here, so
+    // the result is always relative to the side to move, which is what
+    // negamax expects when it negates the child's value.
+    ///////////////////////////////////////////////////////////////////
+    pub fn eval(&self) -> Value {
+        let eval = if self.use_nnue {
+            self.network.eval()
+        } else {
+            self.simple_eval()
+        };
+        self.scale_eval(eval) * self.ctm.factor()
     }
 
-    pub fn eval(&self) -> Value {
-        self.network.eval() * self.ctm.factor()
+    ///////////////////////////////////////////////////////////////////
+    // Pull the eval toward zero as the fifty-move rule gets closer, down
+    // to half of it when a draw can be claimed. It's still from white's
+    // point of view here and rounds toward zero, so both sides are
+    // scaled alike. An eval never gets near the mate range, and this
+    // only shrinks it.
+    ///////////////////////////////////////////////////////////////////
+    fn scale_eval(&self, raw: Value) -> Value {
+        let half_move_counter = (self.history[self.ply].half_move_counter() as Value).min(100);
+        let raw = raw * self.ocb_scale() / Self::OCB_SCALE_NORMAL;
+        raw * (Self::FIFTY_SCALE - half_move_counter) / Self::FIFTY_SCALE
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Bishops on opposite colours, and nothing else but kings and
+    // pawns. An extra pawn is rarely enough to win these, two usually
+    // are, so only the closer ones are pulled toward a draw.
+    ///////////////////////////////////////////////////////////////////
+    fn ocb_scale(&self) -> Value {
+        let pawn_difference = self.bitboard_of(Color::White, PieceType::Pawn).pop_count()
+            - self.bitboard_of(Color::Black, PieceType::Pawn).pop_count();
+
+        if self.opposite_bishops() && pawn_difference.abs() <= 1 {
+            Self::OCB_SCALE
+        } else {
+            Self::OCB_SCALE_NORMAL
+        }
+    }
+
+    pub fn opposite_bishops(&self) -> bool {
+        let white_bishops = self.bitboard_of(Color::White, PieceType::Bishop);
+        let black_bishops = self.bitboard_of(Color::Black, PieceType::Bishop);
+
+        self.bitboard_of_pt(PieceType::Knight)
+            | self.bitboard_of_pt(PieceType::Rook)
+            | self.bitboard_of_pt(PieceType::Queen)
+            == Bitboard::ZERO
+            && white_bishops.is_single()
+            && black_bishops.is_single()
+            && (white_bishops & Bitboard::LIGHT_SQUARES == Bitboard::ZERO)
+                != (black_bishops & Bitboard::LIGHT_SQUARES == Bitboard::ZERO)
+    }
+
+    pub fn use_nnue(&self) -> bool {
+        self.use_nnue
+    }
+
+    pub fn set_use_nnue(&mut self, use_nnue: bool) {
+        self.use_nnue = use_nnue;
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // In chess960 castling is written as the king capturing its own
+    // rook, and fen castling rights can name the rook's file.
+    ///////////////////////////////////////////////////////////////////
+    pub fn chess960(&self) -> bool {
+        self.chess960
+    }
+
+    pub fn set_chess960(&mut self, chess960: bool) {
+        self.chess960 = chess960;
+    }
+
+    fn castling_right(&self, m: Move) -> CastlingRight {
+        let side = match m.flags() {
+            MoveFlags::OO => CastlingSide::King,
+            _ => CastlingSide::Queen,
+        };
+        self.castling
+            .get(self.ctm, side)
+            .expect("Tried to castle without a castling right.")
     }
 
     pub fn bitboard_of(&self, c: Color, pt: PieceType) -> Bitboard {
@@ -141,6 +259,7 @@ impl Board {
             | (self.bitboard_of(Color::Black, PieceType::Pawn)
                 & attacks::pawn_attacks_sq(sq, Color::White))
             | (self.bitboard_of_pt(PieceType::Knight) & attacks::knight_attacks(sq))
+            | (self.bitboard_of_pt(PieceType::King) & attacks::king_attacks(sq))
             | (self.diagonal_sliders() & attacks::bishop_attacks(sq, occ))
             | (self.orthogonal_sliders() & attacks::rook_attacks(sq, occ))
     }
@@ -179,23 +298,94 @@ impl Board {
     }
 
     pub fn in_check(&self) -> bool {
-        self.is_attacked(self.bitboard_of(self.ctm, PieceType::King).lsb())
+        self.check_info().0 != Bitboard::ZERO
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Whether a legal move checks the other king, without making it.
+    // After the move, the piece either attacks the king itself, or one
+    // of our sliders sees the king through the squares it vacated,
+    // including the pawn taken en passant.
+    ///////////////////////////////////////////////////////////////////
+    pub fn gives_check(&self, m: Move) -> bool {
+        let us = self.ctm;
+        let their_king = self.bitboard_of(!us, PieceType::King).lsb();
+        let from_sq = m.from_sq();
+
+        let (pt, to_sq, moved, occ) = if m.is_castling() {
+            let right = self.castling_right(m);
+            let moved = right.king.bb() | right.rook.bb();
+            let occ = self.all_pieces() ^ moved | right.king_to.bb() | right.rook_to.bb();
+            (PieceType::Rook, right.rook_to, moved, occ)
+        } else {
+            let pt = m.promotion().unwrap_or_else(|| {
+                self.piece_type_at(from_sq)
+                    .expect("Tried to check with a piece from an empty square.")
+            });
+            let mut occ = self.all_pieces() ^ from_sq.bb() | m.to_sq().bb();
+            if m.is_ep() {
+                occ ^= (m.to_sq() + Direction::South.relative(us)).bb();
+            }
+            (pt, m.to_sq(), from_sq.bb(), occ)
+        };
+
+        let direct = if pt == PieceType::Pawn {
+            attacks::pawn_attacks_sq(to_sq, us)
+        } else {
+            attacks::attacks(pt, to_sq, occ)
+        };
+        if direct & their_king.bb() != Bitboard::ZERO {
+            return true;
+        }
+
+        let discovered = (attacks::bishop_attacks(their_king, occ) & self.diagonal_sliders_c(us))
+            | (attacks::rook_attacks(their_king, occ) & self.orthogonal_sliders_c(us));
+        discovered & !moved != Bitboard::ZERO
     }
 
     pub fn peek(&self) -> Option<Move> {
         self.history[self.ply].moov()
     }
 
+    // The move n plies before the last one and the piece that made it.
+    // Stops at null moves and the start of the history.
+    pub fn peek_nth(&self, n: usize) -> Option<(Piece, Move)> {
+        let entry = self.history[self.ply.checked_sub(n)?];
+        entry.moved().zip(entry.moov())
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Neither side can force mate with only bishops that all stand on
+    // the same color of square, and a lone knight or two knights
+    // against a bare king can't force it either. Minor pieces on both
+    // sides otherwise can still mate with help, so those aren't drawn.
+    ///////////////////////////////////////////////////////////////////
     fn is_insufficient_material(&self) -> bool {
-        match self.all_pieces().pop_count() {
-            2 => true,
-            3 => {
-                self.bitboard_of_pt(PieceType::Rook)
-                    | self.bitboard_of_pt(PieceType::Queen)
-                    | self.bitboard_of_pt(PieceType::Pawn)
-                    == Bitboard::ZERO
+        if self.bitboard_of_pt(PieceType::Rook)
+            | self.bitboard_of_pt(PieceType::Queen)
+            | self.bitboard_of_pt(PieceType::Pawn)
+            != Bitboard::ZERO
+        {
+            return false;
+        }
+
+        let knights = self.bitboard_of_pt(PieceType::Knight);
+        let bishops = self.bitboard_of_pt(PieceType::Bishop);
+
+        if knights == Bitboard::ZERO {
+            bishops & Bitboard::LIGHT_SQUARES == Bitboard::ZERO
+                || bishops & Bitboard::DARK_SQUARES == Bitboard::ZERO
+        } else if bishops == Bitboard::ZERO {
+            match knights.pop_count() {
+                1 => true,
+                2 => {
+                    knights & self.all_pieces_c(Color::White) == knights
+                        || knights & self.all_pieces_c(Color::Black) == knights
+                }
+                _ => false,
             }
-            _ => false,
+        } else {
+            false
         }
     }
 
@@ -203,7 +393,7 @@ impl Board {
         self.history[self.ply].half_move_counter() >= 100
     }
 
-    fn is_repetition(&self) -> bool {
+    fn repetitions(&self) -> impl Iterator<Item = &HistoryEntry> + '_ {
         let lookback = self.history[self.ply]
             .plies_from_null()
             .min(self.history[self.ply].half_move_counter()) as usize;
@@ -213,7 +403,58 @@ impl Board {
             .rev()
             .skip(1)
             .step_by(2)
-            .any(|entry| self.material_hash() == entry.material_hash())
+            .filter(|entry| self.hash() == entry.hash())
+    }
+
+    // The en passant square only goes into the hash when a pawn of color c
+    // can take on it. Otherwise every double push would split a repetition.
+    fn is_ep_capturable(&self, epsq: SQ, c: Color) -> bool {
+        attacks::pawn_attacks_sq(epsq, !c) & self.bitboard_of(c, PieceType::Pawn) != Bitboard::ZERO
+    }
+
+    fn update_ep_hash(&mut self, epsq: Option<SQ>, c: Color) {
+        if let Some(epsq) = epsq.filter(|&epsq| self.is_ep_capturable(epsq, c)) {
+            self.hasher.update_ep(epsq.file());
+        }
+    }
+
+    fn is_repetition(&self) -> bool {
+        self.repetitions().next().is_some()
+    }
+
+    pub fn repetition_count(&self) -> usize {
+        self.repetitions().count()
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // The history starts out with room for N_HISTORIES plies and grows
+    // when a game or search goes past that. To keep long games from
+    // growing it, only the plies since the last irreversible move are
+    // needed to detect repetitions, so once the game gets close to
+    // the end those are moved to the front and the rest is dropped.
+    // Returns whether the history was truncated.
+    ///////////////////////////////////////////////////////////////////
+    pub fn truncate_history(&mut self) -> bool {
+        if self.ply + Self::HISTORY_RESERVE < Self::N_HISTORIES {
+            return false;
+        }
+
+        let lookback = self.history[self.ply]
+            .plies_from_null()
+            .min(self.history[self.ply].half_move_counter())
+            .min(100) as usize;
+        let start = self.ply - lookback;
+
+        self.history.copy_within(start..=self.ply, 0);
+        for (i, entry) in self.history[..=lookback].iter_mut().enumerate() {
+            *entry = entry.with_plies_from_null(entry.plies_from_null().min(i as u16));
+        }
+        self.history[0] = self.history[0].with_moov(None);
+
+        self.network.drop_oldest(start);
+        self.ply_offset += start;
+        self.ply = lookback;
+        true
     }
 
     pub fn is_draw(&self) -> bool {
@@ -226,7 +467,10 @@ impl Board {
     }
 
     pub fn push_null(&mut self) {
+        self.check_info.set(None);
+        self.network.push();
         self.ply += 1;
+        self.grow_history();
 
         self.history[self.ply] = HistoryEntry::default()
             .with_entry(self.history[self.ply - 1].entry())
@@ -234,29 +478,37 @@ impl Board {
             .with_plies_from_null(0)
             .with_material_hash(self.history[self.ply - 1].material_hash());
 
-        if let Some(epsq) = self.history[self.ply - 1].epsq() {
-            self.hasher.update_ep(epsq.file());
-        }
-
+        self.update_ep_hash(self.history[self.ply - 1].epsq(), self.ctm);
         self.hasher.update_color();
         self.ctm = !self.ctm;
+        self.history[self.ply] = self.history[self.ply].with_hash(self.hasher.hash());
+    }
+
+    fn grow_history(&mut self) {
+        if self.ply == self.history.len() {
+            self.history.push(HistoryEntry::default());
+        }
     }
 
     pub fn pop_null(&mut self) {
+        self.check_info.set(None);
+        self.network.pop();
         self.ply -= 1;
         self.hasher.update_color();
-
-        if let Some(epsq) = self.history[self.ply].epsq() {
-            self.hasher.update_ep(epsq.file());
-        }
         self.ctm = !self.ctm;
+        self.update_ep_hash(self.history[self.ply].epsq(), self.ctm);
     }
 
     pub fn push(&mut self, m: Move) {
+        self.check_info.set(None);
+        self.network.push();
+        self.update_ep_hash(self.history[self.ply].epsq(), self.ctm);
         let mut half_move_counter = self.history[self.ply].half_move_counter() + 1;
         let mut captured = None;
         let mut epsq = None;
+        let moved = self.piece_at(m.from_sq());
         self.ply += 1;
+        self.grow_history();
 
         if self.piece_type_at(m.from_sq()) == Some(PieceType::Pawn) {
             half_move_counter = 0;
@@ -269,17 +521,15 @@ impl Board {
             MoveFlags::DoublePush => {
                 self.move_piece_quiet(m.from_sq(), m.to_sq());
                 epsq = Some(m.from_sq() + Direction::North.relative(self.ctm));
-                if let Some(sq) = epsq {
-                    self.hasher.update_ep(sq.file());
-                }
-            }
-            MoveFlags::OO => {
-                self.move_piece_quiet(SQ::E1.relative(self.ctm), SQ::G1.relative(self.ctm));
-                self.move_piece_quiet(SQ::H1.relative(self.ctm), SQ::F1.relative(self.ctm));
+                self.update_ep_hash(epsq, !self.ctm);
             }
-            MoveFlags::OOO => {
-                self.move_piece_quiet(SQ::E1.relative(self.ctm), SQ::C1.relative(self.ctm));
-                self.move_piece_quiet(SQ::A1.relative(self.ctm), SQ::D1.relative(self.ctm));
+            MoveFlags::OO | MoveFlags::OOO => {
+                // The king and rook can land on each other's squares in chess960.
+                let right = self.castling_right(m);
+                self.remove_piece(right.king);
+                self.remove_piece(right.rook);
+                self.set_piece_at(Piece::make_piece(self.ctm, PieceType::King), right.king_to);
+                self.set_piece_at(Piece::make_piece(self.ctm, PieceType::Rook), right.rook_to);
             }
             MoveFlags::EnPassant => {
                 self.move_piece_quiet(m.from_sq(), m.to_sq());
@@ -307,9 +557,14 @@ impl Board {
                 );
             }
         };
+        let entry = self.history[self.ply - 1].entry() | m.to_sq().bb() | m.from_sq().bb();
+        self.hasher
+            .update_castling(self.history[self.ply - 1].entry(), entry);
+
         self.history[self.ply] = HistoryEntry::default()
-            .with_entry(self.history[self.ply - 1].entry() | m.to_sq().bb() | m.from_sq().bb())
+            .with_entry(entry)
             .with_moov(Some(m))
+            .with_moved(moved)
             .with_half_move_counter(half_move_counter)
             .with_plies_from_null(self.history[self.ply - 1].plies_from_null() + 1)
             .with_captured(captured)
@@ -317,46 +572,46 @@ impl Board {
             .with_material_hash(self.material_hash());
         self.ctm = !self.ctm;
         self.hasher.update_color();
+        self.history[self.ply] = self.history[self.ply].with_hash(self.hasher.hash());
     }
 
     pub fn pop(&mut self) -> Option<Move> {
+        self.check_info.set(None);
         self.ctm = !self.ctm;
         self.hasher.update_color();
 
         let m = self.history[self.ply].moov()?;
+        self.network.pop();
         match m.flags() {
             MoveFlags::Quiet => {
-                self.move_piece_quiet(m.to_sq(), m.from_sq());
+                self.shift_piece(m.to_sq(), m.from_sq());
             }
             MoveFlags::DoublePush => {
-                self.move_piece_quiet(m.to_sq(), m.from_sq());
-                if let Some(sq) = self.history[self.ply].epsq() {
-                    self.hasher.update_ep(sq.file());
-                }
+                self.shift_piece(m.to_sq(), m.from_sq());
+                self.update_ep_hash(self.history[self.ply].epsq(), !self.ctm);
             }
-            MoveFlags::OO => {
-                self.move_piece_quiet(SQ::G1.relative(self.ctm), SQ::E1.relative(self.ctm));
-                self.move_piece_quiet(SQ::F1.relative(self.ctm), SQ::H1.relative(self.ctm));
-            }
-            MoveFlags::OOO => {
-                self.move_piece_quiet(SQ::C1.relative(self.ctm), SQ::E1.relative(self.ctm));
-                self.move_piece_quiet(SQ::D1.relative(self.ctm), SQ::A1.relative(self.ctm));
+            MoveFlags::OO | MoveFlags::OOO => {
+                let right = self.castling_right(m);
+                self.take_piece(right.king_to);
+                self.take_piece(right.rook_to);
+                self.put_piece(Piece::make_piece(self.ctm, PieceType::King), right.king);
+                self.put_piece(Piece::make_piece(self.ctm, PieceType::Rook), right.rook);
             }
             MoveFlags::EnPassant => {
-                self.move_piece_quiet(m.to_sq(), m.from_sq());
-                self.set_piece_at(
+                self.shift_piece(m.to_sq(), m.from_sq());
+                self.put_piece(
                     Piece::make_piece(!self.ctm, PieceType::Pawn),
                     m.to_sq() + Direction::South.relative(self.ctm),
                 );
             }
             MoveFlags::PrKnight | MoveFlags::PrBishop | MoveFlags::PrRook | MoveFlags::PrQueen => {
-                self.remove_piece(m.to_sq());
-                self.set_piece_at(Piece::make_piece(self.ctm, PieceType::Pawn), m.from_sq());
+                self.take_piece(m.to_sq());
+                self.put_piece(Piece::make_piece(self.ctm, PieceType::Pawn), m.from_sq());
             }
             MoveFlags::PcKnight | MoveFlags::PcBishop | MoveFlags::PcRook | MoveFlags::PcQueen => {
-                self.remove_piece(m.to_sq());
-                self.set_piece_at(Piece::make_piece(self.ctm, PieceType::Pawn), m.from_sq());
-                self.set_piece_at(
+                self.take_piece(m.to_sq());
+                self.put_piece(Piece::make_piece(self.ctm, PieceType::Pawn), m.from_sq());
+                self.put_piece(
                     self.history[self.ply]
                         .captured()
                         .expect("Tried to revert a capture move with no capture."),
@@ -364,8 +619,8 @@ impl Board {
                 );
             }
             MoveFlags::Capture => {
-                self.move_piece_quiet(m.to_sq(), m.from_sq());
-                self.set_piece_at(
+                self.shift_piece(m.to_sq(), m.from_sq());
+                self.put_piece(
                     self.history[self.ply]
                         .captured()
                         .expect("Tried to revert a capture move with no capture."),
@@ -373,10 +628,125 @@ impl Board {
                 );
             }
         }
+        self.hasher.update_castling(
+            self.history[self.ply - 1].entry(),
+            self.history[self.ply].entry(),
+        );
+        self.update_ep_hash(self.history[self.ply - 1].epsq(), self.ctm);
         self.ply -= 1;
+        // Recomputing the accumulator is slow, so only a sample is checked.
+        debug_assert!(
+            self.hash() % Self::ACCUMULATOR_CHECK_RATE != 0
+                || self.network.matches_pieces(
+                    self.all_pieces()
+                        .map(|sq| (self.board[sq].expect("Occupied squares have a piece."), sq))
+                )
+        );
         Some(m)
     }
 
+    fn checkers_and_pinned(&self, us: Color) -> (Bitboard, Bitboard) {
+        let them = !us;
+        let us_bb = self.all_pieces_c(us);
+        let them_bb = self.all_pieces_c(them);
+        let our_king = self.bitboard_of(us, PieceType::King).lsb();
+
+        ///////////////////////////////////////////////////////////////////
+        // Checkers are identified by projecting attacks from the king
+        // square and then intersecting them with the enemy bitboard of the
+        // respective piece.
+        ///////////////////////////////////////////////////////////////////
+        let mut checkers = (attacks::knight_attacks(our_king)
+            & self.bitboard_of(them, PieceType::Knight))
+            | (attacks::pawn_attacks_sq(our_king, us) & self.bitboard_of(them, PieceType::Pawn));
+
+        ///////////////////////////////////////////////////////////////////
+        // Candidates are potential slider checkers and pinners.
+        ///////////////////////////////////////////////////////////////////
+        let candidates = (attacks::rook_attacks(our_king, them_bb)
+            & self.orthogonal_sliders_c(them))
+            | (attacks::bishop_attacks(our_king, them_bb) & self.diagonal_sliders_c(them));
+
+        let mut pinned = Bitboard::ZERO;
+
+        for sq in candidates {
+            let potentially_pinned = Bitboard::between(our_king, sq) & us_bb;
+
+            ///////////////////////////////////////////////////////////////////
+            // Do the squares between an enemy slider and our king contain any
+            // pieces? If yes, that piece is pinned. Otherwise, we are checked.
+            ///////////////////////////////////////////////////////////////////
+            if potentially_pinned == Bitboard::ZERO {
+                checkers ^= sq.bb();
+            } else if potentially_pinned.is_single() {
+                pinned ^= potentially_pinned;
+            }
+        }
+
+        (checkers, pinned)
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Checkers and pins for the side to move are cached until the
+    // position changes, so the search asking whether we're in check
+    // and then generating moves only computes them once.
+    ///////////////////////////////////////////////////////////////////
+    fn check_info(&self) -> (Bitboard, Bitboard) {
+        if let Some(check_info) = self.check_info.get() {
+            return check_info;
+        }
+
+        let check_info = self.checkers_and_pinned(self.ctm);
+        self.check_info.set(Some(check_info));
+        check_info
+    }
+
+    pub fn checkers(&self) -> Bitboard {
+        self.check_info().0
+    }
+
+    pub fn pinned_pieces(&self, color: Color) -> Bitboard {
+        if color == self.ctm {
+            self.check_info().1
+        } else {
+            self.checkers_and_pinned(color).1
+        }
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Whether the side to move has any legal move. The king usually has
+    // a safe square, and out of check an unpinned knight with somewhere
+    // to go always has a legal move, so the full move list is only
+    // generated when neither of those turn one up.
+    ///////////////////////////////////////////////////////////////////
+    pub fn any_legal_move(&self) -> bool {
+        let us = self.ctm;
+        let them = !self.ctm;
+        let us_bb = self.all_pieces_c(us);
+        let all = self.all_pieces();
+
+        let our_king = self.bitboard_of(us, PieceType::King).lsb();
+        let their_king = self.bitboard_of(them, PieceType::King).lsb();
+        let occ = all ^ our_king.bb();
+
+        if (attacks::king_attacks(our_king) & !us_bb).any(|sq| {
+            self.attackers_from_c(sq, occ, them) == Bitboard::ZERO
+                && attacks::king_attacks(sq) & their_king.bb() == Bitboard::ZERO
+        }) {
+            return true;
+        }
+
+        let (checkers, pinned) = self.check_info();
+        if checkers == Bitboard::ZERO
+            && (self.bitboard_of(us, PieceType::Knight) & !pinned)
+                .any(|sq| attacks::knight_attacks(sq) & !us_bb != Bitboard::ZERO)
+        {
+            return true;
+        }
+
+        MoveList::from(self).len() > 0
+    }
+
     pub fn generate_legal_moves<const QUIET: bool>(&self, moves: &mut MoveList) {
         let us = self.ctm;
         let them = !self.ctm;
@@ -428,7 +798,9 @@ impl Board {
 
         let king_attacks = attacks::king_attacks(our_king) & !(us_bb | danger);
 
-        moves.make_q(our_king, king_attacks & !them_bb);
+        if QUIET {
+            moves.make_q(our_king, king_attacks & !them_bb);
+        }
         moves.make_c(our_king, king_attacks & them_bb);
 
         ///////////////////////////////////////////////////////////////////
@@ -443,36 +815,7 @@ impl Board {
         ///////////////////////////////////////////////////////////////////
         let quiet_mask;
 
-        ///////////////////////////////////////////////////////////////////
-        // Checkers are identified by projecting attacks from the king
-        // square and then intersecting them with the enemy bitboard of the
-        // respective piece.
-        ///////////////////////////////////////////////////////////////////
-        let mut checkers = (attacks::knight_attacks(our_king)
-            & self.bitboard_of(them, PieceType::Knight))
-            | (attacks::pawn_attacks_sq(our_king, us) & self.bitboard_of(them, PieceType::Pawn));
-
-        ///////////////////////////////////////////////////////////////////
-        // Candidates are potential slider checkers and pinners.
-        ///////////////////////////////////////////////////////////////////
-        let candidates = (attacks::rook_attacks(our_king, them_bb) & their_orth_sliders)
-            | (attacks::bishop_attacks(our_king, them_bb) & their_diag_sliders);
-
-        let mut pinned = Bitboard::ZERO;
-
-        for sq in candidates {
-            let potentially_pinned = Bitboard::between(our_king, sq) & us_bb;
-
-            ///////////////////////////////////////////////////////////////////
-            // Do the squares between an enemy slider and our king contain any
-            // pieces? If yes, that piece is pinned. Otherwise, we are checked.
-            ///////////////////////////////////////////////////////////////////
-            if potentially_pinned == Bitboard::ZERO {
-                checkers ^= sq.bb();
-            } else if potentially_pinned.is_single() {
-                pinned ^= potentially_pinned;
-            }
-        }
+        let (checkers, pinned) = self.check_info();
 
         let not_pinned = !pinned;
 
@@ -584,12 +927,8 @@ impl Board {
                     ///////////////////////////////////////////////////////////////////
                     let pinned_epsq_attackers =
                         epsq_attackers & pinned & Bitboard::line(epsq, our_king);
-                    if pinned_epsq_attackers != Bitboard::ZERO {
-                        moves.push(Move::new(
-                            pinned_epsq_attackers.lsb(),
-                            epsq,
-                            MoveFlags::EnPassant,
-                        ));
+                    if let Some(sq) = pinned_epsq_attackers.first() {
+                        moves.push(Move::new(sq, epsq, MoveFlags::EnPassant));
                     }
                 }
 
@@ -597,27 +936,36 @@ impl Board {
                 // Only castle if:
                 // 1. Neither the king nor rook have moved.
                 // 2. The king is not in check.
-                // 3. The relevant squares are not attacked.
+                // 3. The squares between the king, the rook and where they're
+                //    going are empty.
+                // 4. The squares the king crosses are not attacked.
+                // 5. In chess960 the rook might have been shielding the king's
+                //    destination from a slider along the back rank.
                 ///////////////////////////////////////////////////////////////////
                 if QUIET {
-                    if ((self.history[self.ply].entry() & Bitboard::oo_mask(us))
-                        | ((all | danger) & Bitboard::oo_blockers_mask(us)))
-                        == Bitboard::ZERO
-                    {
-                        moves.push(match us {
-                            Color::White => Move::new(SQ::E1, SQ::G1, MoveFlags::OO),
-                            Color::Black => Move::new(SQ::E8, SQ::G8, MoveFlags::OO),
-                        });
-                    }
-                    if ((self.history[self.ply].entry() & Bitboard::ooo_mask(us))
-                        | ((all | (danger & !Bitboard::ignore_ooo_danger(us)))
-                            & Bitboard::ooo_blockers_mask(us)))
-                        == Bitboard::ZERO
-                    {
-                        moves.push(match us {
-                            Color::White => Move::new(SQ::E1, SQ::C1, MoveFlags::OOO),
-                            Color::Black => Move::new(SQ::E8, SQ::C8, MoveFlags::OOO),
-                        });
+                    for (side, flags) in [
+                        (CastlingSide::King, MoveFlags::OO),
+                        (CastlingSide::Queen, MoveFlags::OOO),
+                    ] {
+                        let Some(right) = self.castling.get(us, side) else {
+                            continue;
+                        };
+                        if (self.history[self.ply].entry() & right.mask())
+                            | (all & right.blockers)
+                            | (danger & right.king_path)
+                            | (attacks::rook_attacks(
+                                right.king_to,
+                                all ^ right.king.bb() ^ right.rook.bb(),
+                            ) & their_orth_sliders)
+                            == Bitboard::ZERO
+                        {
+                            let to_sq = if self.chess960 {
+                                right.rook
+                            } else {
+                                right.king_to
+                            };
+                            moves.push(Move::new(right.king, to_sq, flags));
+                        }
                     }
                 }
                 ///////////////////////////////////////////////////////////////////
@@ -842,110 +1190,622 @@ impl Board {
         }
     }
 
-    pub fn push_str(&mut self, move_str: &str) -> Result<(), &'static str> {
-        let m = MoveList::from(self)
-            .iter_moves()
-            .find(|m| m.to_string() == move_str)
-            .ok_or("Invalid move.")?;
+    ///////////////////////////////////////////////////////////////////
+    // Whether m could be generated here if pins and checks didn't
+    // matter: the right piece, a target it can reach, and flags that
+    // match what's on the squares. Moves from the hash table can come
+    // from a different position, so they have to pass this before
+    // they're pushed. Castling is checked in full.
+    ///////////////////////////////////////////////////////////////////
+    pub fn is_pseudo_legal(&self, m: Move) -> bool {
+        let us = self.ctm;
+        let from_sq = m.from_sq();
+        let to_sq = m.to_sq();
 
-        self.push(m);
-        Ok(())
-    }
+        let Some(pc) = self.piece_at(from_sq).filter(|pc| pc.color_of() == us) else {
+            return false;
+        };
 
-    pub fn set_fen(&mut self, fen: &str) -> Result<(), &'static str> {
-        self.clear();
-        let fen = fen.trim();
-        if !fen.is_ascii() || fen.lines().count() != 1 {
-            return Err("FEN should be a single ASCII line.");
+        if m.is_castling() {
+            return pc.type_of() == PieceType::King && self.can_castle(m);
         }
 
-        let re_captures = FEN_RE.captures(fen).ok_or("Invalid fen format.")?;
-
-        let piece_placement = re_captures
-            .name("piece_placement")
-            .ok_or("Invalid piece placement.")?
-            .as_str();
-        let ctm = re_captures
-            .name("active_color")
-            .ok_or("Invalid color.")?
-            .as_str();
-        let castling = re_captures
-            .name("castling")
-            .ok_or("Invalid castling rights.")?
-            .as_str();
-        let en_passant_sq = re_captures.name("en_passant").map_or("-", |m| m.as_str());
-        let halfmove_clock = re_captures.name("halfmove").map_or("0", |m| m.as_str());
-        let fullmove_counter = re_captures.name("fullmove").map_or("1", |m| m.as_str());
-
-        if piece_placement.split('/').count() != Rank::N_RANKS {
-            return Err("Pieces Placement FEN should have 8 ranks.");
+        let target = self.piece_at(to_sq);
+        if target
+            .is_some_and(|target| target.color_of() == us || target.type_of() == PieceType::King)
+        {
+            return false;
+        }
+        if !m.is_ep() && m.is_capture() != target.is_some() {
+            return false;
         }
 
-        self.ctm = Color::try_from(ctm.parse::<char>().map_err(|_| "Invalid color.")?)?;
-
-        self.ply = 2
-            * (fullmove_counter
-                .parse::<usize>()
-                .map_err(|_| "Invalid full move counter.")?
-                - 1);
-        if self.ctm == Color::Black {
-            self.ply += 1;
-            self.hasher.update_color();
+        if pc.type_of() != PieceType::Pawn {
+            return matches!(m.flags(), MoveFlags::Quiet | MoveFlags::Capture)
+                && attacks::attacks(pc.type_of(), from_sq, self.all_pieces()) & to_sq.bb()
+                    != Bitboard::ZERO;
         }
 
-        let ranks = piece_placement.split('/');
-        for (rank_idx, rank_fen) in ranks.enumerate() {
-            let mut idx = (7 - rank_idx) * 8;
+        let push_sq = from_sq + Direction::North.relative(us);
+        if m.promotion().is_some() != (to_sq.rank().relative(us) == Rank::Eight) {
+            return false;
+        }
 
-            for ch in rank_fen.chars() {
-                if let Some(digit) = ch.to_digit(10) {
-                    if digit > 8 {
-                        return Err("Invalid digit in position.");
-                    }
-                    idx += digit as usize;
-                } else {
-                    if idx > 63 {
-                        return Err("Invalid square index in FEN.");
-                    }
-                    let sq = SQ::from(idx as u8);
-                    let pc = Piece::try_from(ch)?;
-                    self.set_piece_at(pc, sq);
-                    idx += 1;
-                }
+        match m.flags() {
+            MoveFlags::DoublePush => {
+                from_sq.rank().relative(us) == Rank::Two
+                    && self.piece_at(push_sq).is_none()
+                    && to_sq == push_sq + Direction::North.relative(us)
             }
-
-            if idx != 64 - 8 * rank_idx {
-                return Err("FEN rank does not fill expected number of squares.");
+            MoveFlags::EnPassant => {
+                self.history[self.ply].epsq() == Some(to_sq)
+                    && attacks::pawn_attacks_sq(from_sq, us) & to_sq.bb() != Bitboard::ZERO
             }
-        }
-
-        self.history[self.ply] = self.history[self.ply].with_entry(Bitboard::ALL_CASTLING_MASK);
-        for (symbol, mask) in [
-            ('K', Bitboard::WHITE_OO_MASK),
-            ('Q', Bitboard::WHITE_OOO_MASK),
-            ('k', Bitboard::BLACK_OO_MASK),
-            ('q', Bitboard::BLACK_OOO_MASK),
-        ] {
-            if castling.contains(symbol) {
-                self.history[self.ply] =
-                    self.history[self.ply].with_entry(self.history[self.ply].entry() & !mask);
+            _ if m.is_capture() => {
+                attacks::pawn_attacks_sq(from_sq, us) & to_sq.bb() != Bitboard::ZERO
             }
+            _ => to_sq == push_sq,
         }
+    }
 
-        if en_passant_sq != "-" {
-            let epsq = SQ::try_from(en_passant_sq)?;
-            self.history[self.ply] = self.history[self.ply].with_epsq(Some(epsq));
-            self.hasher.update_ep(epsq.file());
+    ///////////////////////////////////////////////////////////////////
+    // A pseudo-legal move is legal when afterwards none of their pieces
+    // attack our king, not counting the piece it captured.
+    ///////////////////////////////////////////////////////////////////
+    pub fn is_legal(&self, m: Move) -> bool {
+        if !self.is_pseudo_legal(m) {
+            return false;
+        }
+        if m.is_castling() {
+            return true;
         }
-        self.history[self.ply] = self.history[self.ply].with_half_move_counter(
-            halfmove_clock
-                .parse::<u16>()
-                .map_err(|_| "Invalid half move counter.")?,
-        );
-        self.history[self.ply] =
-            self.history[self.ply].with_material_hash(self.hasher.material_hash());
-        Ok(())
-    }
+
+        let us = self.ctm;
+        let them = !us;
+        let from_sq = m.from_sq();
+        let to_sq = m.to_sq();
+
+        let king_sq = if self.piece_type_at(from_sq) == Some(PieceType::King) {
+            to_sq
+        } else {
+            self.bitboard_of(us, PieceType::King).lsb()
+        };
+        let captured_sq = if m.is_ep() {
+            to_sq + Direction::South.relative(us)
+        } else {
+            to_sq
+        };
+        let occ = (self.all_pieces() ^ from_sq.bb() ^ captured_sq.bb()) | to_sq.bb();
+
+        let attackers = self.attackers_from_c(king_sq, occ, them)
+            | (attacks::king_attacks(king_sq) & self.bitboard_of(them, PieceType::King));
+        attackers & !captured_sq.bb() == Bitboard::ZERO
+    }
+
+    // The same conditions the move generator castles under.
+    fn can_castle(&self, m: Move) -> bool {
+        let us = self.ctm;
+        let them = !us;
+        let side = match m.flags() {
+            MoveFlags::OO => CastlingSide::King,
+            _ => CastlingSide::Queen,
+        };
+        let Some(right) = self.castling.get(us, side) else {
+            return false;
+        };
+        let to_sq = if self.chess960 {
+            right.rook
+        } else {
+            right.king_to
+        };
+        if m.from_sq() != right.king || m.to_sq() != to_sq || self.in_check() {
+            return false;
+        }
+
+        let all = self.all_pieces();
+        let occ = all ^ right.king.bb();
+        let their_king = self.bitboard_of(them, PieceType::King);
+        let mut king_path = right.king_path;
+        (self.history[self.ply].entry() & right.mask()) | (all & right.blockers) == Bitboard::ZERO
+            && king_path.all(|sq| {
+                self.attackers_from_c(sq, occ, them) | (attacks::king_attacks(sq) & their_king)
+                    == Bitboard::ZERO
+            })
+            && attacks::rook_attacks(right.king_to, occ ^ right.rook.bb())
+                & self.orthogonal_sliders_c(them)
+                == Bitboard::ZERO
+    }
+
+    pub fn parse_move(&self, move_str: &str) -> Result<Move, &'static str> {
+        MoveList::from(self)
+            .iter_moves()
+            .find(|m| m.to_string() == move_str)
+            .ok_or("Invalid move.")
+    }
+
+    pub fn push_str(&mut self, move_str: &str) -> Result<(), &'static str> {
+        let m = self.parse_move(move_str)?;
+        self.push(m);
+        Ok(())
+    }
+
+    pub fn move_to_san(&self, m: Move) -> String {
+        let from_sq = m.from_sq();
+        let to_sq = m.to_sq();
+        let pt = self
+            .piece_type_at(from_sq)
+            .expect("Move should start on an occupied square.");
+        let from_str = SQ::SQ_DISPLAY[from_sq];
+
+        let mut san = String::new();
+        match m.flags() {
+            MoveFlags::OO => san.push_str("O-O"),
+            MoveFlags::OOO => san.push_str("O-O-O"),
+            _ => {
+                if pt == PieceType::Pawn {
+                    if m.is_capture() {
+                        san.push_str(&from_str[..1]);
+                    }
+                } else {
+                    san.push_str(&pt.to_string().to_uppercase());
+
+                    ///////////////////////////////////////////////////////////////////
+                    // Disambiguate by file, then by rank, then by both if another
+                    // piece of the same type can also reach the destination.
+                    ///////////////////////////////////////////////////////////////////
+                    let others = MoveList::from(self)
+                        .iter_moves()
+                        .filter(|other| {
+                            other.to_sq() == to_sq
+                                && other.from_sq() != from_sq
+                                && self.piece_type_at(other.from_sq()) == Some(pt)
+                        })
+                        .map(|other| other.from_sq())
+                        .collect::<Vec<SQ>>();
+
+                    if !others.is_empty() {
+                        if others.iter().all(|sq| sq.file() != from_sq.file()) {
+                            san.push_str(&from_str[..1]);
+                        } else if others.iter().all(|sq| sq.rank() != from_sq.rank()) {
+                            san.push_str(&from_str[1..]);
+                        } else {
+                            san.push_str(from_str);
+                        }
+                    }
+                }
+
+                if m.is_capture() {
+                    san.push('x');
+                }
+                san.push_str(SQ::SQ_DISPLAY[to_sq]);
+
+                if let Some(promotion_pt) = m.promotion() {
+                    san.push('=');
+                    san.push_str(&promotion_pt.to_string().to_uppercase());
+                }
+            }
+        }
+
+        ///////////////////////////////////////////////////////////////////
+        // Only checks need the move played, to tell mate from check.
+        ///////////////////////////////////////////////////////////////////
+        if self.gives_check(m) {
+            let mut board = self.clone();
+            board.push(m);
+            san.push(if !board.any_legal_move() { '#' } else { '+' });
+        }
+
+        san
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Check and annotation suffixes are ignored, and so is a missing
+    // or extra x, as long as exactly one legal move fits the rest.
+    ///////////////////////////////////////////////////////////////////
+    pub fn san_to_move(&self, san: &str) -> Result<Move, &'static str> {
+        let san = san.trim().trim_end_matches(['+', '#', '!', '?']);
+        let moves = MoveList::from(self);
+
+        let castling = match san {
+            "O-O" | "0-0" => Some(MoveFlags::OO),
+            "O-O-O" | "0-0-0" => Some(MoveFlags::OOO),
+            _ => None,
+        };
+        if let Some(flags) = castling {
+            return moves
+                .iter_moves()
+                .find(|m| m.flags() == flags)
+                .ok_or("Illegal move.");
+        }
+
+        let re_captures = SAN_RE.captures(san).ok_or("Invalid SAN.")?;
+
+        let pt = match re_captures.name("piece") {
+            Some(m) => {
+                Piece::try_from(m.as_str().parse::<char>().map_err(|_| "Invalid SAN.")?)?.type_of()
+            }
+            None => PieceType::Pawn,
+        };
+        let to_sq = SQ::try_from(&re_captures["to_sq"])?;
+        let from_file = re_captures.name("from_file").map(|m| m.as_str());
+        let from_rank = re_captures.name("from_rank").map(|m| m.as_str());
+        let promotion = re_captures
+            .name("promotion")
+            .map(|m| {
+                Piece::try_from(m.as_str().parse::<char>().map_err(|_| "Invalid SAN.")?)
+                    .map(|pc| pc.type_of())
+            })
+            .transpose()?;
+
+        let mut candidates = moves.iter_moves().filter(|m| {
+            let from_str = SQ::SQ_DISPLAY[m.from_sq()];
+            !m.is_castling()
+                && m.to_sq() == to_sq
+                && m.promotion() == promotion
+                && self.piece_type_at(m.from_sq()) == Some(pt)
+                && from_file.map_or(true, |file| from_str[..1] == *file)
+                && from_rank.map_or(true, |rank| from_str[1..] == *rank)
+        });
+
+        match (candidates.next(), candidates.next()) {
+            (Some(m), None) => Ok(m),
+            (Some(_), Some(_)) => Err("Ambiguous move."),
+            (None, _) => Err("Illegal move."),
+        }
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // The remaining rights as they're written in a FEN. Chess960 rights
+    // are written Shredder-FEN style, by rook file.
+    ///////////////////////////////////////////////////////////////////
+    pub fn castling_rights_str(&self) -> String {
+        let mut castling_rights_str = String::new();
+        for c in [Color::White, Color::Black] {
+            for (side, symbol) in [(CastlingSide::King, 'k'), (CastlingSide::Queen, 'q')] {
+                let Some(right) = self.castling.get(c, side) else {
+                    continue;
+                };
+                if right.mask() & self.history[self.ply].entry() != Bitboard::ZERO {
+                    continue;
+                }
+                let symbol = if self.chess960 {
+                    (b'a' + right.rook.file() as u8) as char
+                } else {
+                    symbol
+                };
+                castling_rights_str.push(match c {
+                    Color::White => symbol.to_ascii_uppercase(),
+                    Color::Black => symbol,
+                });
+            }
+        }
+        if castling_rights_str.is_empty() {
+            castling_rights_str = "-".to_string();
+        }
+        castling_rights_str
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // The game is replayed from the earliest position still in the
+    // history. That's the one that was set, unless a long game has
+    // had its history truncated, and it goes in a FEN tag whenever
+    // it isn't the standard starting position.
+    ///////////////////////////////////////////////////////////////////
+    pub fn to_pgn(&self) -> String {
+        let mut moves = (1..=self.ply)
+            .rev()
+            .map_while(|ply| self.history[ply].moov())
+            .collect::<Vec<Move>>();
+        moves.reverse();
+
+        let mut board = self.clone();
+        for _ in &moves {
+            board.pop();
+        }
+
+        let result = self.result();
+        let mut pgn = String::new();
+        for (tag, value) in [
+            ("Event", "?"),
+            ("Site", "?"),
+            ("Date", "????.??.??"),
+            ("Round", "?"),
+            ("White", "?"),
+            ("Black", "?"),
+            ("Result", result),
+        ] {
+            pgn.push_str(&format!("[{} \"{}\"]\n", tag, value));
+        }
+        if board.chess960() {
+            pgn.push_str("[Variant \"Chess960\"]\n");
+        }
+        let fen = board.to_string();
+        if fen != Self::STARTING_FEN {
+            pgn.push_str(&format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", fen));
+        }
+        pgn.push('\n');
+
+        let mut tokens = Vec::new();
+        for (i, &m) in moves.iter().enumerate() {
+            if board.ctm == Color::White {
+                tokens.push(format!("{}.", board.fullmove_number()));
+            } else if i == 0 {
+                tokens.push(format!("{}...", board.fullmove_number()));
+            }
+            tokens.push(board.move_to_san(m));
+            board.push(m);
+        }
+        tokens.push(result.to_string());
+
+        let mut line_len = 0;
+        for token in tokens {
+            if line_len > 0 && line_len + 1 + token.len() > Self::PGN_LINE_WIDTH {
+                pgn.push('\n');
+                line_len = 0;
+            } else if line_len > 0 {
+                pgn.push(' ');
+                line_len += 1;
+            }
+            line_len += token.len();
+            pgn.push_str(&token);
+        }
+        pgn
+    }
+
+    // Unlike is_draw, a repetition has to happen three times to end the game.
+    fn result(&self) -> &'static str {
+        if !self.any_legal_move() {
+            match (self.in_check(), self.ctm) {
+                (false, _) => "1/2-1/2",
+                (true, Color::White) => "0-1",
+                (true, Color::Black) => "1-0",
+            }
+        } else if self.is_fifty() || self.is_insufficient_material() || self.repetition_count() >= 2
+        {
+            "1/2-1/2"
+        } else {
+            "*"
+        }
+    }
+
+    pub fn set_fen(&mut self, fen: &str) -> Result<(), FenError> {
+        self.clear();
+        let fen = fen.trim();
+        if !fen.is_ascii() || fen.lines().count() != 1 {
+            return Err(FenError::NotSingleLine);
+        }
+
+        let re_captures = FEN_RE.captures(fen).ok_or(FenError::InvalidFormat)?;
+
+        let piece_placement = re_captures
+            .name("piece_placement")
+            .ok_or(FenError::InvalidFormat)?
+            .as_str();
+        let ctm = re_captures
+            .name("active_color")
+            .ok_or(FenError::InvalidFormat)?
+            .as_str();
+        let castling = re_captures
+            .name("castling")
+            .ok_or(FenError::InvalidFormat)?
+            .as_str();
+        let en_passant_sq = re_captures.name("en_passant").map_or("-", |m| m.as_str());
+        let halfmove_clock = re_captures.name("halfmove").map_or("0", |m| m.as_str());
+        let fullmove_counter = re_captures.name("fullmove").map_or("1", |m| m.as_str());
+
+        let rank_count = piece_placement.split('/').count();
+        if rank_count != Rank::N_RANKS {
+            return Err(FenError::RankCount(rank_count));
+        }
+
+        self.ctm = ctm
+            .parse::<char>()
+            .ok()
+            .and_then(|ch| Color::try_from(ch).ok())
+            .ok_or_else(|| FenError::InvalidColor(ctm.to_string()))?;
+
+        let fullmove_counter = fullmove_counter
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .ok_or_else(|| FenError::InvalidFullMove(fullmove_counter.to_string()))?;
+        self.ply_offset = 2 * fullmove_counter;
+        if self.ctm == Color::Black {
+            self.ply_offset += 1;
+            self.hasher.update_color();
+        }
+
+        let ranks = piece_placement.split('/');
+        for (rank_idx, rank_fen) in ranks.enumerate() {
+            let rank = Rank::N_RANKS - rank_idx;
+
+            ///////////////////////////////////////////////////////////////////
+            // Validate the whole rank before placing anything so that every
+            // square index below is in range.
+            ///////////////////////////////////////////////////////////////////
+            let squares = rank_fen.chars().try_fold(0, |squares, ch| match ch {
+                '1'..='8' => Ok(squares + (ch as usize - '0' as usize)),
+                _ => Piece::try_from(ch)
+                    .map(|_| squares + 1)
+                    .map_err(|_| FenError::InvalidCharacter { rank, ch }),
+            })?;
+
+            if squares != File::N_FILES {
+                return Err(FenError::RankLength { rank, squares });
+            }
+
+            let mut idx = (7 - rank_idx) * 8;
+            for ch in rank_fen.chars() {
+                if let Some(digit) = ch.to_digit(10) {
+                    idx += digit as usize;
+                } else {
+                    let pc = Piece::try_from(ch).expect("Rank was validated above.");
+                    self.set_piece_at(pc, SQ::from(idx as u8));
+                    idx += 1;
+                }
+            }
+        }
+
+        // Everything from check detection to move generation needs both kings.
+        for c in [Color::White, Color::Black] {
+            let count = self.bitboard_of(c, PieceType::King).pop_count() as usize;
+            if count != 1 {
+                return Err(FenError::KingCount { color: c, count });
+            }
+        }
+
+        ///////////////////////////////////////////////////////////////////
+        // No position with the sixteen pieces a side of a real game is
+        // known to come close to MAX_MOVES, but a made-up FEN with more
+        // could overflow a move list, here or after any later move.
+        ///////////////////////////////////////////////////////////////////
+        for c in [Color::White, Color::Black] {
+            let count = self.all_pieces_c(c).pop_count() as usize;
+            if count > 16 {
+                return Err(FenError::PieceCount { color: c, count });
+            }
+        }
+
+        for symbol in castling.chars() {
+            if let Some((c, side, right)) = self.parse_castling_right(symbol) {
+                self.castling.set(c, side, right);
+            }
+        }
+
+        self.hasher.set_castling_masks(self.castling.masks());
+        self.hasher
+            .update_castling(Bitboard::ALL, self.history[self.ply].entry());
+
+        if en_passant_sq != "-" {
+            let epsq = SQ::try_from(en_passant_sq)
+                .map_err(|_| FenError::InvalidEnPassant(en_passant_sq.to_string()))?;
+            self.history[self.ply] = self.history[self.ply].with_epsq(Some(epsq));
+            self.update_ep_hash(Some(epsq), self.ctm);
+        }
+        self.history[self.ply] = self.history[self.ply].with_half_move_counter(
+            halfmove_clock
+                .parse::<u16>()
+                .map_err(|_| FenError::InvalidHalfMove(halfmove_clock.to_string()))?,
+        );
+        self.history[self.ply] = self.history[self.ply]
+            .with_material_hash(self.hasher.material_hash())
+            .with_hash(self.hasher.hash());
+        Ok(())
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // K and Q take the outermost rook on that side of the king, and a
+    // file letter (Shredder-FEN) names the rook directly. Rights that
+    // don't match the position, or aren't standard outside of chess960,
+    // are dropped.
+    ///////////////////////////////////////////////////////////////////
+    fn parse_castling_right(&self, symbol: char) -> Option<(Color, CastlingSide, CastlingRight)> {
+        let c = if symbol.is_ascii_uppercase() {
+            Color::White
+        } else {
+            Color::Black
+        };
+        let back_rank = Rank::One.relative(c);
+        let king = (self.bitboard_of(c, PieceType::King) & back_rank.bb()).first()?;
+        let rooks = self.bitboard_of(c, PieceType::Rook) & back_rank.bb();
+
+        let rook = match symbol.to_ascii_lowercase() {
+            'k' => {
+                let h_side = Bitboard::between(king, SQ::H1.relative(c)) | SQ::H1.relative(c).bb();
+                (rooks & h_side).last()?
+            }
+            'q' => {
+                let a_side = Bitboard::between(king, SQ::A1.relative(c)) | SQ::A1.relative(c).bb();
+                (rooks & a_side).first()?
+            }
+            file @ 'a'..='h' => {
+                let sq = SQ::encode(back_rank, File::from(file as u8 - b'a'));
+                (rooks & sq.bb() != Bitboard::ZERO).then_some(sq)?
+            }
+            _ => return None,
+        };
+
+        let side = if rook.file() > king.file() {
+            CastlingSide::King
+        } else {
+            CastlingSide::Queen
+        };
+
+        let standard_rook = match side {
+            CastlingSide::King => SQ::H1.relative(c),
+            CastlingSide::Queen => SQ::A1.relative(c),
+        };
+        if !self.chess960 && (king != SQ::E1.relative(c) || rook != standard_rook) {
+            return None;
+        }
+
+        Some((c, side, CastlingRight::new(c, side, king, rook)))
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // An EPD line is the first four fields of a FEN followed by
+    // operations of the form `opcode operand ...;`. The move counters,
+    // if given, come from the hmvc and fmvn operations.
+    ///////////////////////////////////////////////////////////////////
+    pub fn from_epd(epd: &str) -> Result<(Board, HashMap<String, String>), FenError> {
+        let mut rest = epd.trim();
+        let mut fields = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            fields.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+
+        let operations = Self::parse_epd_operations(rest)?;
+
+        let halfmove = operations.get("hmvc").map_or("0", |n| n.as_str());
+        let fullmove = operations.get("fmvn").map_or("1", |n| n.as_str());
+        let fen = format!("{} {} {}", fields.join(" "), halfmove, fullmove);
+
+        Ok((Board::try_from(fen.as_str())?, operations))
+    }
+
+    fn parse_epd_operations(operations: &str) -> Result<HashMap<String, String>, FenError> {
+        let mut result = HashMap::new();
+        let mut chars = operations.chars().peekable();
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace() || *c == ';').is_some() {}
+            if chars.peek().is_none() {
+                return Ok(result);
+            }
+
+            let opcode: String =
+                iter::from_fn(|| chars.next_if(|c| !c.is_whitespace() && *c != ';')).collect();
+
+            ///////////////////////////////////////////////////////////////////
+            // Operands run up to the semicolon. A quoted operand may contain
+            // spaces and semicolons, and its quotes are dropped.
+            ///////////////////////////////////////////////////////////////////
+            let mut operands = Vec::new();
+            loop {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                match chars.next() {
+                    None | Some(';') => break,
+                    Some('"') => {
+                        operands.push(iter::from_fn(|| chars.next_if(|c| *c != '"')).collect());
+                        if chars.next().is_none() {
+                            return Err(FenError::InvalidEpdOperation(opcode));
+                        }
+                    }
+                    Some(ch) => operands.push(
+                        iter::once(ch)
+                            .chain(iter::from_fn(|| {
+                                chars.next_if(|c| !c.is_whitespace() && *c != ';')
+                            }))
+                            .collect::<String>(),
+                    ),
+                }
+            }
+
+            result.insert(opcode, operands.join(" "));
+        }
+    }
 
     pub fn simple_eval_c(&self, color: Color) -> Value {
         const PIECE_TYPE_VALUES: PieceTypeMap<Value> =
@@ -960,6 +1820,35 @@ impl Board {
         eval
     }
 
+    ///////////////////////////////////////////////////////////////////
+    // Game phase from the non-pawn material left on the board, going
+    // from MAX_PHASE with all pieces on to 0 with only kings and pawns.
+    // Promotions can add material, so it's capped at MAX_PHASE.
+    ///////////////////////////////////////////////////////////////////
+    pub fn phase(&self) -> Value {
+        const PIECE_TYPE_PHASES: PieceTypeMap<Value> = PieceTypeMap::new([0, 1, 1, 2, 4, 0]);
+
+        PieceType::iter(PieceType::Knight, PieceType::Queen)
+            .map(|pt| self.bitboard_of_pt(pt).pop_count() * PIECE_TYPE_PHASES[pt])
+            .sum::<Value>()
+            .min(Self::MAX_PHASE)
+    }
+
+    pub fn piece_count(&self, c: Color, pt: PieceType) -> u32 {
+        self.bitboard_of(c, pt).pop_count() as u32
+    }
+
+    pub fn material_count(&self) -> MaterialCount {
+        let mut counts =
+            ColorMap::new([PieceTypeMap::new([0; PieceType::N_PIECE_TYPES]); Color::N_COLORS]);
+        for color in [Color::White, Color::Black] {
+            for pt in PieceType::iter(PieceType::Pawn, PieceType::King) {
+                counts[color][pt] = self.piece_count(color, pt);
+            }
+        }
+        MaterialCount(counts)
+    }
+
     pub fn simple_eval(&self) -> Value {
         self.simple_eval_c(Color::White) - self.simple_eval_c(Color::Black)
     }
@@ -969,19 +1858,94 @@ impl Board {
     }
 
     pub fn ply(&self) -> usize {
-        self.ply
+        self.ply_offset + self.ply
     }
 
     pub fn hash(&self) -> Hash {
         self.hasher.hash()
     }
 
+    ///////////////////////////////////////////////////////////////////
+    // The key push(m) would leave us with, for prefetching the hash
+    // table before the move is made. Mirrors the hash updates in push.
+    ///////////////////////////////////////////////////////////////////
+    pub fn key_after(&self, m: Move) -> Hash {
+        let (from_sq, to_sq) = (m.from_sq(), m.to_sq());
+        let pc = self
+            .piece_at(from_sq)
+            .expect("Tried to move a piece off of an empty square.");
+        let rook = Piece::make_piece(self.ctm, PieceType::Rook);
+        let key = |pc, sq| self.hasher.piece_key(pc, sq);
+
+        let mut hash = self.hash() ^ self.hasher.color_key();
+        hash ^= match m.flags() {
+            MoveFlags::Quiet => key(pc, from_sq) ^ key(pc, to_sq),
+            MoveFlags::DoublePush => {
+                let epsq = from_sq + Direction::North.relative(self.ctm);
+                let ep = if self.is_ep_capturable(epsq, !self.ctm) {
+                    self.hasher.ep_key(epsq.file())
+                } else {
+                    0
+                };
+                key(pc, from_sq) ^ key(pc, to_sq) ^ ep
+            }
+            MoveFlags::OO | MoveFlags::OOO => {
+                let right = self.castling_right(m);
+                key(pc, right.king)
+                    ^ key(pc, right.king_to)
+                    ^ key(rook, right.rook)
+                    ^ key(rook, right.rook_to)
+            }
+            MoveFlags::EnPassant => {
+                key(pc, from_sq)
+                    ^ key(pc, to_sq)
+                    ^ key(
+                        Piece::make_piece(!self.ctm, PieceType::Pawn),
+                        to_sq + Direction::South.relative(self.ctm),
+                    )
+            }
+            MoveFlags::Capture => {
+                let captured = self
+                    .piece_at(to_sq)
+                    .expect("Tried to capture on an empty square.");
+                key(pc, from_sq) ^ key(pc, to_sq) ^ key(captured, to_sq)
+            }
+            // Promotions:
+            _ => {
+                let promotion = Piece::make_piece(
+                    self.ctm,
+                    m.promotion()
+                        .expect("Tried to set a promotion piece for a non-promotion move."),
+                );
+                let captured = self
+                    .piece_at(to_sq)
+                    .map_or(0, |captured| key(captured, to_sq));
+                key(pc, from_sq) ^ key(promotion, to_sq) ^ captured
+            }
+        };
+
+        let entry = self.history[self.ply].entry();
+        hash ^= self
+            .hasher
+            .castling_key(entry, entry | from_sq.bb() | to_sq.bb());
+        if let Some(epsq) = self.history[self.ply].epsq() {
+            if self.is_ep_capturable(epsq, self.ctm) {
+                hash ^= self.hasher.ep_key(epsq.file());
+            }
+        }
+        hash
+    }
+
     pub fn material_hash(&self) -> Hash {
         self.hasher.material_hash()
     }
 
+    pub fn pawn_hash(&self) -> Hash {
+        self.hasher.pawn_hash()
+    }
+
     pub fn fullmove_number(&self) -> usize {
-        self.ply / 2 + 1
+        self.ply() / 2 + 1
     }
 }
 
@@ -993,15 +1957,20 @@ impl Default for Board {
             board: SQMap::new([None; SQ::N_SQUARES]),
             ctm: Color::White,
             ply: 0,
+            ply_offset: 0,
             hasher: Hasher::new(),
             network: Network::new(),
-            history: [HistoryEntry::default(); Self::N_HISTORIES],
+            use_nnue: true,
+            castling: Castling::default(),
+            chess960: false,
+            history: vec![HistoryEntry::default(); Self::N_HISTORIES],
+            check_info: Cell::new(None),
         }
     }
 }
 
 impl TryFrom<&str> for Board {
-    type Error = &'static str;
+    type Error = FenError;
 
     fn try_from(fen: &str) -> Result<Self, Self::Error> {
         let mut board = Board::default();
@@ -1040,21 +2009,6 @@ impl fmt::Display for Board {
             }
         }
 
-        let mut castling_rights_str = String::new();
-        for (symbol, mask) in "KQkq".chars().zip([
-            Bitboard::WHITE_OO_MASK,
-            Bitboard::WHITE_OOO_MASK,
-            Bitboard::BLACK_OO_MASK,
-            Bitboard::BLACK_OOO_MASK,
-        ]) {
-            if mask & self.history[self.ply].entry() == Bitboard::ZERO {
-                castling_rights_str.push(symbol);
-            }
-        }
-        if castling_rights_str.is_empty() {
-            castling_rights_str = "-".to_string();
-        }
-
         let epsq_str = match self.history[self.ply].epsq() {
             Some(epsq) => epsq.to_string(),
             None => "-".to_string(),
@@ -1065,10 +2019,10 @@ impl fmt::Display for Board {
             "{} {} {} {} {} {}",
             board_str,
             self.ctm,
-            castling_rights_str,
+            self.castling_rights_str(),
             epsq_str,
             self.history[self.ply].half_move_counter(),
-            self.ply / 2 + 1,
+            self.fullmove_number(),
         )
     }
 }
@@ -1096,16 +2050,37 @@ impl fmt::Debug for Board {
 }
 
 impl Board {
-    const N_HISTORIES: usize = 1000;
+    pub const N_HISTORIES: usize = 1000;
+    pub const MAX_PHASE: Value = 24;
+    const FIFTY_SCALE: Value = 200;
+    const OCB_SCALE_NORMAL: Value = 64;
+    const OCB_SCALE: Value = 32;
+    const HISTORY_RESERVE: usize = 2 * MAX_MOVES;
+    const ACCUMULATOR_CHECK_RATE: Hash = 64;
+    const PGN_LINE_WIDTH: usize = 80;
     const STARTING_FEN: &'static str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 }
 
+static SAN_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?x)^
+                (?P<piece>[NBRQK])?
+                (?P<from_file>[a-h])?
+                (?P<from_rank>[1-8])?
+                x?
+                (?P<to_sq>[a-h][1-8])
+                (?:=?(?P<promotion>[NBRQ]))?
+            $",
+    )
+    .expect("Failed to compile san regex.")
+});
+
 static FEN_RE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(
         r"(?x)^
-                (?P<piece_placement>[KQRBNPkqrbnp1-8/]+)\s+
+                (?P<piece_placement>\S+)\s+
                 (?P<active_color>[wb])\s+
-                (?P<castling>[KQkq\-]+)\s+
+                (?P<castling>[KQkqA-Ha-h\-]+)\s+
                 (?P<en_passant>[a-h1-8\-]+)
                 (?:\s+(?P<halfmove>\d+))?
                 (?:\s+(?P<fullmove>\d+))?
@@ -1114,13 +2089,82 @@ static FEN_RE: LazyLock<Regex> = LazyLock::new(|| {
     .expect("Failed to compile fen regex.")
 });
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FenError {
+    NotSingleLine,
+    InvalidFormat,
+    RankCount(usize),
+    InvalidCharacter { rank: usize, ch: char },
+    RankLength { rank: usize, squares: usize },
+    InvalidColor(String),
+    InvalidEnPassant(String),
+    InvalidHalfMove(String),
+    InvalidFullMove(String),
+    InvalidEpdOperation(String),
+    KingCount { color: Color, count: usize },
+    PieceCount { color: Color, count: usize },
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotSingleLine => write!(f, "FEN should be a single ASCII line."),
+            Self::InvalidFormat => write!(
+                f,
+                "Invalid FEN format. Expected piece placement, active color, castling rights, and en passant square."
+            ),
+            Self::RankCount(count) => write!(
+                f,
+                "Piece placement should have 8 ranks, found {}.",
+                count
+            ),
+            Self::InvalidCharacter { rank, ch } => write!(
+                f,
+                "Invalid character '{}' in rank {} of the piece placement.",
+                ch, rank
+            ),
+            Self::RankLength { rank, squares } => write!(
+                f,
+                "Rank {} of the piece placement covers {} squares instead of 8.",
+                rank, squares
+            ),
+            Self::InvalidColor(color) => {
+                write!(f, "Invalid active color '{}', expected 'w' or 'b'.", color)
+            }
+            Self::InvalidEnPassant(sq) => write!(f, "Invalid en passant square '{}'.", sq),
+            Self::InvalidHalfMove(n) => write!(f, "Invalid half move counter '{}'.", n),
+            Self::InvalidFullMove(n) => write!(f, "Invalid full move counter '{}'.", n),
+            Self::InvalidEpdOperation(opcode) => {
+                write!(f, "Unterminated string in EPD operation '{}'.", opcode)
+            }
+            Self::KingCount { color, count } => {
+                write!(f, "{:?} should have one king, found {}.", color, count)
+            }
+            Self::PieceCount { color, count } => {
+                write!(f, "{:?} should have at most 16 pieces, found {}.", color, count)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct MaterialCount(ColorMap<PieceTypeMap<u32>>);
+
+impl MaterialCount {
+    pub fn get(&self, c: Color, pt: PieceType) -> u32 {
+        self.0[c][pt]
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct HistoryEntry {
     entry: Bitboard,
     captured: Option<Piece>,
     epsq: Option<SQ>,
     moov: Option<Move>,
+    moved: Option<Piece>,
     material_hash: Hash,
+    hash: Hash,
     half_move_counter: u16,
     plies_from_null: u16,
 }
@@ -1138,6 +2182,10 @@ impl HistoryEntry {
         self.captured
     }
 
+    pub fn moved(&self) -> Option<Piece> {
+        self.moved
+    }
+
     pub fn epsq(&self) -> Option<SQ> {
         self.epsq
     }
@@ -1154,6 +2202,10 @@ impl HistoryEntry {
         self.material_hash
     }
 
+    pub fn hash(&self) -> Hash {
+        self.hash
+    }
+
     pub fn with_entry(&mut self, entry: Bitboard) -> Self {
         self.entry = entry;
         *self
@@ -1169,6 +2221,11 @@ impl HistoryEntry {
         *self
     }
 
+    pub fn with_moved(&mut self, pc: Option<Piece>) -> Self {
+        self.moved = pc;
+        *self
+    }
+
     pub fn with_epsq(&mut self, sq: Option<SQ>) -> Self {
         self.epsq = sq;
         *self
@@ -1188,14 +2245,21 @@ impl HistoryEntry {
         self.material_hash = material_hash;
         *self
     }
-}
+
+    pub fn with_hash(&mut self, hash: Hash) -> Self {
+        self.hash = hash;
+        *self
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use crate::bitboard::*;
     use crate::board::*;
     use crate::magics::*;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn threefold_repetition() {
         init_magics();
         let mut board = Board::new();
@@ -1213,4 +2277,1050 @@ mod tests {
         board.push_str("c5f8").unwrap();
         assert_eq!(board.is_repetition(), true);
     }
+
+    #[test]
+    fn insufficient_material() {
+        init_magics();
+        init_bb();
+
+        for (fen, expected) in [
+            ("8/8/3k4/8/8/4K3/8/8 w - - 0 1", true),
+            ("8/8/3k4/8/8/4KB2/8/8 w - - 0 1", true),
+            ("8/8/3k4/8/8/4KN2/8/8 w - - 0 1", true),
+            ("8/8/3k4/8/8/3NKN2/8/8 w - - 0 1", true),
+            ("8/8/3knn2/8/8/4K3/8/8 w - - 0 1", true),
+            ("8/8/3kb3/8/8/4KB2/8/8 w - - 0 1", true),
+            ("8/8/3k4/8/8/3BKB2/8/8 b - - 0 1", true),
+            ("8/8/3k1b2/8/8/4KB2/8/8 w - - 0 1", false),
+            ("8/8/3k4/8/8/2B1KB2/8/8 w - - 0 1", false),
+            ("8/8/3kn3/8/8/4KN2/8/8 w - - 0 1", false),
+            ("8/8/3kb3/8/8/4KN2/8/8 w - - 0 1", false),
+            ("8/8/3k4/8/8/3BKN2/8/8 w - - 0 1", false),
+            ("8/8/3k4/8/8/2NNKN2/8/8 w - - 0 1", false),
+            ("8/8/3k4/8/8/4KR2/8/8 w - - 0 1", false),
+            ("8/8/3k4/8/8/4K3/5P2/8 w - - 0 1", false),
+        ] {
+            let board = Board::try_from(fen).unwrap();
+            assert_eq!(board.is_insufficient_material(), expected, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn peek_nth() {
+        init_magics();
+        init_bb();
+
+        let mut board = Board::new();
+        assert_eq!(board.peek_nth(0), None);
+
+        board.push_str("e2e4").unwrap();
+        board.push_str("d7d5").unwrap();
+        board.push_str("e4d5").unwrap();
+        let moved = |n| board.peek_nth(n).map(|(pc, m)| (pc, m.to_string()));
+        assert_eq!(moved(0), Some((Piece::WhitePawn, "e4d5".to_string())));
+        assert_eq!(moved(1), Some((Piece::BlackPawn, "d7d5".to_string())));
+        assert_eq!(moved(2), Some((Piece::WhitePawn, "e2e4".to_string())));
+        assert_eq!(moved(3), None);
+        assert_eq!(moved(4), None);
+
+        board.push_null();
+        assert_eq!(board.peek_nth(0), None);
+        assert!(board.peek_nth(1).is_some());
+    }
+
+    #[test]
+    fn eval_is_side_to_move_relative() {
+        init_magics();
+        init_bb();
+
+        for use_nnue in [true, false] {
+            for fen in [
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "6k1/5ppp/8/8/8/5N2/5PPP/6K1 b - - 0 1",
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            ] {
+                let mut board = Board::try_from(fen).unwrap();
+                board.set_use_nnue(use_nnue);
+                let eval = board.eval();
+
+                // Only the fifty-move scaling sees the extra half move.
+                board.push_null();
+                let null_eval = board.eval();
+                assert_eq!(null_eval.signum(), -eval.signum(), "{}", fen);
+                assert!((eval + null_eval).abs() <= eval.abs() / 100 + 1, "{}", fen);
+
+                board.pop_null();
+                assert_eq!(board.eval(), eval, "{}", fen);
+            }
+        }
+    }
+
+    #[test]
+    fn repetition_needs_same_castling_rights() {
+        init_magics();
+        init_bb();
+
+        // The rooks go out and back, so the pieces are where they started
+        // but nobody can castle kingside anymore.
+        let mut board = Board::try_from("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let hash = board.hash();
+        for m in ["h1g1", "h8g8", "g1h1", "g8h8"] {
+            board.push_str(m).unwrap();
+            assert!(!board.is_repetition());
+        }
+        assert_eq!(board.material_hash(), board.history[0].material_hash());
+        assert_ne!(board.hash(), hash);
+
+        for m in ["h1g1", "h8g8", "g1h1", "g8h8"] {
+            board.push_str(m).unwrap();
+        }
+        assert!(board.is_repetition());
+        assert_eq!(board.repetition_count(), 1);
+    }
+
+    #[test]
+    fn repetition_needs_same_en_passant() {
+        init_magics();
+        init_bb();
+
+        // Black could take on e3 right after the double push, but not once
+        // the knights have gone out and back.
+        let mut board = Board::try_from("4k3/8/8/8/3p4/8/4P3/4K1N1 w - - 0 1").unwrap();
+        board.push_str("e2e4").unwrap();
+        for m in ["e8d7", "g1f3", "d7e8", "f3g1"] {
+            board.push_str(m).unwrap();
+        }
+        assert!(!board.is_repetition());
+
+        // Without a pawn to take, the en passant square doesn't matter.
+        let mut board = Board::try_from("4k3/8/8/8/8/8/4P3/4K1N1 w - - 0 1").unwrap();
+        board.push_str("e2e4").unwrap();
+        for m in ["e8d7", "g1f3", "d7e8", "f3g1"] {
+            board.push_str(m).unwrap();
+        }
+        assert!(board.is_repetition());
+    }
+
+    #[test]
+    fn castling_rights_hash() {
+        init_magics();
+        init_bb();
+
+        let castling = Board::try_from("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let no_castling = Board::try_from("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1").unwrap();
+        let white_oo = Board::try_from("r3k2r/8/8/8/8/8/8/R3K2R w K - 0 1").unwrap();
+
+        assert_ne!(castling.hash(), no_castling.hash());
+        assert_ne!(castling.hash(), white_oo.hash());
+        assert_ne!(no_castling.hash(), white_oo.hash());
+        assert_eq!(castling.material_hash(), no_castling.material_hash());
+    }
+
+    #[test]
+    fn incremental_hash_matches_fen() {
+        init_magics();
+        init_bb();
+
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "rnbqkbnr/pp1ppppp/8/2pP4/8/8/PPP1PPPP/RNBQKBNR w KQkq c6 0 3",
+        ] {
+            let mut board = Board::try_from(fen).unwrap();
+            let hash = board.hash();
+            let pawn_hash = board.pawn_hash();
+            for m in MoveList::from(&board).iter_moves() {
+                board.push(m);
+                let expected = Board::try_from(board.to_string().as_str()).unwrap();
+                assert_eq!(board.hash(), expected.hash(), "{} after {}", fen, m);
+                assert_eq!(
+                    board.pawn_hash(),
+                    expected.pawn_hash(),
+                    "{} after {}",
+                    fen,
+                    m
+                );
+                board.pop();
+                assert_eq!(board.hash(), hash);
+                assert_eq!(board.pawn_hash(), pawn_hash);
+            }
+        }
+    }
+
+    #[test]
+    fn pinned_pieces() {
+        init_magics();
+        init_bb();
+
+        let board = Board::try_from("4k3/4r3/8/8/1b6/8/3PN3/4K3 w - - 0 1").unwrap();
+        assert_eq!(board.pinned_pieces(Color::White), SQ::D2.bb() | SQ::E2.bb());
+        assert_eq!(board.pinned_pieces(Color::Black), Bitboard::ZERO);
+        assert_eq!(board.checkers(), Bitboard::ZERO);
+
+        // Two of our pieces between the king and the slider isn't a pin.
+        let board = Board::try_from("4k3/4r3/8/8/8/4P3/4N3/4K3 w - - 0 1").unwrap();
+        assert_eq!(board.pinned_pieces(Color::White), Bitboard::ZERO);
+    }
+
+    #[test]
+    fn double_check() {
+        init_magics();
+        init_bb();
+
+        let board = Board::try_from("4k3/8/8/8/8/5n2/8/r3K3 w - - 0 1").unwrap();
+        assert_eq!(board.checkers(), SQ::A1.bb() | SQ::F3.bb());
+        let moves = MoveList::from(&board);
+        assert!(moves.len() > 0);
+        assert!(moves.iter_moves().all(|m| m.from_sq() == SQ::E1));
+    }
+
+    #[test]
+    fn san() {
+        init_magics();
+        init_bb();
+
+        let cases = [
+            (Board::STARTING_FEN, "e2e4", "e4"),
+            (Board::STARTING_FEN, "g1f3", "Nf3"),
+            (
+                "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+                "e4d5",
+                "exd5",
+            ),
+            (
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+                "e5f6",
+                "exf6",
+            ),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "e1g1",
+                "O-O",
+            ),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "e1c1",
+                "O-O-O",
+            ),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                "e2a6",
+                "Bxa6",
+            ),
+            ("4k3/8/8/8/8/8/8/R4RK1 w - - 0 1", "a1d1", "Rad1"),
+            ("4k3/8/8/R7/8/8/8/R5K1 w - - 0 1", "a1a3", "R1a3"),
+            ("4k3/8/8/8/8/2N1N3/1N6/6K1 w - - 0 1", "c3d1", "Ncd1"),
+            ("8/8/8/7k/8/Q1Q5/8/Q5K1 w - - 0 1", "a3b2", "Qa3b2"),
+            ("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", "a1a8", "Ra8#"),
+            ("4k3/8/8/8/8/8/8/R5K1 w - - 0 1", "a1a8", "Ra8+"),
+            ("8/P6k/8/8/8/8/8/K7 w - - 0 1", "a7a8q", "a8=Q"),
+            ("1n5k/P7/8/8/8/8/8/K7 w - - 0 1", "a7b8n", "axb8=N"),
+        ];
+
+        for (fen, move_str, expected) in cases {
+            let board = Board::try_from(fen).unwrap();
+            let m = board.parse_move(move_str).unwrap();
+            assert_eq!(board.move_to_san(m), expected);
+            assert_eq!(board.san_to_move(expected), Ok(m));
+            assert_eq!(board.to_string(), Board::try_from(fen).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn to_pgn() {
+        init_magics();
+        init_bb();
+
+        let tags = |result: &str| {
+            format!(
+                "[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n\
+                 [White \"?\"]\n[Black \"?\"]\n[Result \"{}\"]\n",
+                result
+            )
+        };
+
+        let mut board = Board::new();
+        assert_eq!(board.to_pgn(), format!("{}\n*", tags("*")));
+
+        for m in ["f2f3", "e7e5", "g2g4", "d8h4"] {
+            board.push_str(m).unwrap();
+        }
+        assert_eq!(
+            board.to_pgn(),
+            format!("{}\n1. f3 e5 2. g4 Qh4# 0-1", tags("0-1"))
+        );
+
+        let fen = "4k3/8/8/8/8/8/4p3/R3K3 b Q - 0 30";
+        let mut board = Board::try_from(fen).unwrap();
+        for m in ["e8d7", "e1e2", "d7c6"] {
+            board.push_str(m).unwrap();
+        }
+        assert_eq!(
+            board.to_pgn(),
+            format!(
+                "{}[SetUp \"1\"]\n[FEN \"{}\"]\n\n30... Kd7 31. Kxe2 Kc6 *",
+                tags("*"),
+                fen
+            )
+        );
+
+        // A threefold repetition ends the game, and the moves wrap.
+        let mut board = Board::new();
+        for _ in 0..5 {
+            for m in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                board.push_str(m).unwrap();
+            }
+        }
+        let pgn = board.to_pgn();
+        assert!(pgn.starts_with(&tags("1/2-1/2")));
+        assert!(pgn.ends_with("10. Ng1 Ng8 1/2-1/2"));
+        assert!(pgn.lines().all(|line| line.len() <= Board::PGN_LINE_WIDTH));
+        assert_eq!(pgn.lines().count(), 10);
+    }
+
+    #[test]
+    fn san_to_move() {
+        init_magics();
+        init_bb();
+
+        let cases = [
+            // Knights on b1 and f1 need the file, on c3 and c1 the rank.
+            ("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", "Nbd2", Ok("b1d2")),
+            (
+                "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1",
+                "Nd2",
+                Err("Ambiguous move."),
+            ),
+            ("4k3/8/8/8/8/2N5/8/2N1K3 w - - 0 1", "N1e2", Ok("c1e2")),
+            (
+                "4k3/8/8/8/8/2N5/8/2N1K3 w - - 0 1",
+                "Nce2",
+                Err("Ambiguous move."),
+            ),
+            ("4k3/8/8/8/8/2N5/8/2N1K3 w - - 0 1", "N3xe2", Ok("c3e2")),
+            (
+                "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+                "exd5",
+                Ok("e4d5"),
+            ),
+            (
+                "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2",
+                "exd6",
+                Err("Illegal move."),
+            ),
+            (
+                "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+                "exf6",
+                Ok("e5f6"),
+            ),
+            ("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", "Ra8#", Ok("a1a8")),
+            ("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", "Ra8", Ok("a1a8")),
+            ("8/P6k/8/8/8/8/8/K7 w - - 0 1", "a8=N", Ok("a7a8n")),
+            ("8/P6k/8/8/8/8/8/K7 w - - 0 1", "a8", Err("Illegal move.")),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+                "0-0-0",
+                Ok("e8c8"),
+            ),
+            (Board::STARTING_FEN, "O-O", Err("Illegal move.")),
+            (Board::STARTING_FEN, "Ke2", Err("Illegal move.")),
+            (Board::STARTING_FEN, "e", Err("Invalid SAN.")),
+        ];
+
+        for (fen, san, expected) in cases {
+            let board = Board::try_from(fen).unwrap();
+            assert_eq!(
+                board.san_to_move(san).map(|m| m.to_string()),
+                expected.map(|m| m.to_string()),
+                "{} {}",
+                fen,
+                san
+            );
+        }
+
+        let mut board =
+            Board::try_from("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        for m in [
+            "e1g1", "b4c3", "d5e6", "a6e2", "e6f7", "e8d8", "f7f8q", "h8f8",
+        ] {
+            for m in MoveList::from(&board).iter_moves() {
+                assert_eq!(board.san_to_move(&board.move_to_san(m)), Ok(m));
+            }
+            board.push_str(m).unwrap();
+        }
+    }
+
+    #[test]
+    fn pawn_hash() {
+        init_magics();
+        init_bb();
+
+        let mut board = Board::new();
+        let pawn_hash = board.pawn_hash();
+        assert_ne!(pawn_hash, 0);
+
+        board.push_str("g1f3").unwrap();
+        board.push_str("b8c6").unwrap();
+        assert_eq!(board.pawn_hash(), pawn_hash);
+
+        board.push_str("e2e4").unwrap();
+        assert_ne!(board.pawn_hash(), pawn_hash);
+
+        board.pop();
+        assert_eq!(board.pawn_hash(), pawn_hash);
+    }
+
+    #[test]
+    fn is_legal() {
+        init_magics();
+        init_bb();
+
+        let board = Board::try_from("4k3/4r3/8/8/1b6/8/3PN3/4K3 w - - 0 1").unwrap();
+        for m in MoveList::from(&board).iter_moves() {
+            assert!(board.is_legal(m));
+        }
+
+        // The knight and pawn are pinned.
+        assert!(!board.is_legal(Move::new(SQ::E2, SQ::C3, MoveFlags::Quiet)));
+        assert!(!board.is_legal(Move::new(SQ::D2, SQ::D3, MoveFlags::Quiet)));
+
+        // The king can't move into check or leave itself in check.
+        let board = Board::try_from("4k3/8/8/8/8/8/3r4/4K3 w - - 0 1").unwrap();
+        assert!(!board.is_legal(Move::new(SQ::E1, SQ::D1, MoveFlags::Quiet)));
+        assert!(!board.is_legal(Move::new(SQ::E1, SQ::E2, MoveFlags::Quiet)));
+        assert!(board.is_legal(Move::new(SQ::E1, SQ::D2, MoveFlags::Capture)));
+        assert!(!board.is_legal(Move::new(SQ::E1, SQ::D2, MoveFlags::Quiet)));
+
+        // Wrong side to move and empty from square.
+        assert!(!board.is_legal(Move::new(SQ::D2, SQ::D1, MoveFlags::Quiet)));
+        assert!(!board.is_legal(Move::new(SQ::A1, SQ::A2, MoveFlags::Quiet)));
+    }
+
+    #[test]
+    fn is_pseudo_legal() {
+        init_magics();
+        init_bb();
+
+        let flags = (0..16)
+            .filter(|n| !matches!(n, 0b0110 | 0b0111))
+            .collect::<Vec<u8>>();
+
+        // Every move with one of our pieces on its from square is legal
+        // exactly when the move generator comes up with it.
+        fn check(board: &mut Board, flags: &[u8], depth: usize) {
+            let moves = MoveList::from(&*board);
+            for from_sq in board.all_pieces_c(board.ctm) {
+                for to_sq in (0..SQ::N_SQUARES as u8).map(SQ::from) {
+                    if to_sq == from_sq {
+                        continue;
+                    }
+                    for &flag in flags {
+                        let m = Move::new(from_sq, to_sq, MoveFlags::from(flag));
+                        assert_eq!(board.is_legal(m), moves.contains(m), "{} {}", board, m);
+                        if moves.contains(m) {
+                            assert!(board.is_pseudo_legal(m), "{} {}", board, m);
+                        }
+                    }
+                }
+            }
+            if depth > 1 {
+                for m in moves.iter_moves() {
+                    board.push(m);
+                    check(board, flags, depth - 1);
+                    board.pop();
+                }
+            }
+        }
+
+        for fen in [
+            Board::STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "rnbqkbnr/pp1ppppp/8/2pP4/8/8/PPP1PPPP/RNBQKBNR w Kq c6 0 3",
+            "8/8/8/KPp4r/8/8/8/7k w - c6 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1",
+        ] {
+            check(&mut Board::try_from(fen).unwrap(), &flags, 2);
+        }
+
+        let mut board = Board::default();
+        board.set_chess960(true);
+        board
+            .set_fen("qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9")
+            .unwrap();
+        check(&mut board, &flags, 2);
+
+        // A move from another position with a pinned piece is rejected
+        // by is_legal but passes as pseudo-legal.
+        let board = Board::try_from("4k3/4r3/8/8/8/8/4N3/4K3 w - - 0 1").unwrap();
+        let m = Move::new(SQ::E2, SQ::C3, MoveFlags::Quiet);
+        assert!(board.is_pseudo_legal(m));
+        assert!(!board.is_legal(m));
+        assert!(!board.is_pseudo_legal(Move::new(SQ::E2, SQ::E4, MoveFlags::Quiet)));
+        assert!(!board.is_pseudo_legal(Move::new(SQ::E1, SQ::G1, MoveFlags::OO)));
+    }
+
+    #[test]
+    fn fen_errors() {
+        init_magics();
+        init_bb();
+
+        assert_eq!(
+            Board::try_from("rnbqkbnr/ppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").err(),
+            Some(FenError::RankLength {
+                rank: 7,
+                squares: 7
+            })
+        );
+        assert_eq!(
+            Board::try_from("rnbqkbnr/pppppppp/8/8/4P4/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").err(),
+            Some(FenError::RankLength {
+                rank: 4,
+                squares: 9
+            })
+        );
+        let err = Board::try_from("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1")
+            .err()
+            .unwrap();
+        assert_eq!(err, FenError::InvalidCharacter { rank: 1, ch: 'X' });
+        assert_eq!(
+            err.to_string(),
+            "Invalid character 'X' in rank 1 of the piece placement."
+        );
+        assert_eq!(
+            Board::try_from("8/8/8/8/8/8/8 w - - 0 1").err(),
+            Some(FenError::RankCount(7))
+        );
+        assert_eq!(
+            Board::try_from("4k3/8/8/8/8/8/8/4K3 w - e 0 1").err(),
+            Some(FenError::InvalidEnPassant("e".to_string()))
+        );
+        assert_eq!(
+            Board::try_from("4k3/8/8/8/8/8/8/4K3 w - - 0 0").err(),
+            Some(FenError::InvalidFullMove("0".to_string()))
+        );
+        assert!(Board::try_from("4k3/8/8/8/8/8/8/4K3 w - - 0 1").is_ok());
+
+        // Check detection and move generation need both kings.
+        let err = Board::try_from("8/8/8/8/8/8/8/4K3 w - - 0 1")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            FenError::KingCount {
+                color: Color::Black,
+                count: 0
+            }
+        );
+        assert_eq!(err.to_string(), "Black should have one king, found 0.");
+        assert_eq!(
+            Board::try_from("4k3/8/8/8/8/8/8/3KK3 b - - 0 1").err(),
+            Some(FenError::KingCount {
+                color: Color::White,
+                count: 2
+            })
+        );
+        let mut board = Board::new();
+        assert!(board.set_fen("4k3/8/8/8/8/8/8/8 w - - 0 1").is_err());
+
+        // More than sixteen pieces could overflow a move list, while the
+        // most moves known still fit.
+        assert!(Board::try_from("4k3/8/8/8/8/8/PPPPPPPP/NNNNKNNN w - - 0 1").is_ok());
+        assert_eq!(
+            Board::try_from("4k3/8/8/8/8/N7/PPPPPPPP/NNNNKNNN w - - 0 1").err(),
+            Some(FenError::PieceCount {
+                color: Color::White,
+                count: 17
+            })
+        );
+        let board =
+            Board::try_from("R6R/3Q4/1Q4Q1/4Q3/2Q4Q/Q4Q2/pp1Q4/kBNN1KB1 w - - 0 1").unwrap();
+        assert_eq!(MoveList::from(&board).len(), 218);
+    }
+
+    #[test]
+    fn truncate_history() {
+        init_magics();
+        let mut board = Board::new();
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        let files = ['a', 'b', 'c', 'd', 'e', 'g', 'h'];
+
+        let mut plies = 0;
+        for (white, black) in [((2, 3), (7, 6)), ((3, 4), (6, 5))] {
+            for file in files {
+                board
+                    .push_str(&format!("{file}{}{file}{}", white.0, white.1))
+                    .unwrap();
+                board
+                    .push_str(&format!("{file}{}{file}{}", black.0, black.1))
+                    .unwrap();
+                board.truncate_history();
+                assert!(!board.is_repetition());
+
+                for _ in 0..24 {
+                    for m in shuffle {
+                        board.push_str(m).unwrap();
+                        board.truncate_history();
+                    }
+                }
+                assert_eq!(board.repetition_count(), 24);
+                assert_eq!(board.history.len(), Board::N_HISTORIES);
+                plies += 98;
+            }
+        }
+
+        assert!(plies > Board::N_HISTORIES);
+        assert_eq!(board.ply(), plies);
+        assert_eq!(board.fullmove_number(), plies / 2 + 1);
+        assert!(board
+            .to_string()
+            .ends_with(&format!(" 96 {}", plies / 2 + 1)));
+
+        let board = Board::try_from("4k3/8/8/8/8/8/8/4K3 b - - 0 900").unwrap();
+        assert_eq!(board.ply(), 1799);
+        assert_eq!(board.to_string(), "4k3/8/8/8/8/8/8/4K3 b - - 0 900");
+    }
+
+    #[test]
+    fn material_count() {
+        init_magics();
+        let board =
+            Board::try_from("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        assert_eq!(board.piece_count(Color::White, PieceType::Pawn), 8);
+        assert_eq!(board.piece_count(Color::Black, PieceType::Pawn), 8);
+        assert_eq!(board.piece_count(Color::White, PieceType::Knight), 2);
+        assert_eq!(board.piece_count(Color::Black, PieceType::Queen), 1);
+
+        let material = board.material_count();
+        for color in [Color::White, Color::Black] {
+            for pt in PieceType::iter(PieceType::Pawn, PieceType::King) {
+                assert_eq!(material.get(color, pt), board.piece_count(color, pt));
+            }
+        }
+
+        let material = Board::try_from("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1")
+            .unwrap()
+            .material_count();
+        assert_eq!(material.get(Color::White, PieceType::Pawn), 3);
+        assert_eq!(material.get(Color::Black, PieceType::Pawn), 3);
+        assert_eq!(material.get(Color::White, PieceType::Rook), 1);
+        assert_eq!(material.get(Color::Black, PieceType::Queen), 0);
+        assert_eq!(material.get(Color::Black, PieceType::King), 1);
+    }
+
+    #[test]
+    fn phase() {
+        init_magics();
+        assert_eq!(Board::new().phase(), Board::MAX_PHASE);
+        assert_eq!(
+            Board::try_from("4k3/8/8/8/8/8/8/4K3 w - - 0 1")
+                .unwrap()
+                .phase(),
+            0
+        );
+        assert_eq!(
+            Board::try_from("4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1")
+                .unwrap()
+                .phase(),
+            0
+        );
+        assert_eq!(
+            Board::try_from("r3k3/8/8/8/8/8/8/1N2KB1Q w - - 0 1")
+                .unwrap()
+                .phase(),
+            8
+        );
+        assert_eq!(
+            Board::try_from("qqqqkqqq/8/8/8/8/8/8/QQQQKQQQ w - - 0 1")
+                .unwrap()
+                .phase(),
+            Board::MAX_PHASE
+        );
+    }
+
+    #[test]
+    fn eval_modes() {
+        init_magics();
+        init_bb();
+
+        for use_nnue in [true, false] {
+            let mut board = Board::new();
+            board.set_use_nnue(use_nnue);
+            assert!(board.eval().abs() < 100, "{}", board.eval());
+
+            // White is up a queen.
+            for (fen, sign) in [
+                (
+                    "rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                    1,
+                ),
+                (
+                    "rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1",
+                    -1,
+                ),
+            ] {
+                board.set_fen(fen).unwrap();
+                assert!(board.use_nnue() == use_nnue);
+                assert!(board.eval() * sign > 500, "{} {}", fen, board.eval());
+            }
+        }
+    }
+
+    #[test]
+    fn fifty_move_scaling() {
+        init_magics();
+        init_bb();
+
+        for use_nnue in [true, false] {
+            let eval = |fen: &str| {
+                let mut board = Board::try_from(fen).unwrap();
+                board.set_use_nnue(use_nnue);
+                board.eval()
+            };
+
+            // White is up a knight, with either side to move.
+            for (fen, sign) in [
+                ("6k1/5ppp/8/8/8/5N2/5PPP/6K1 w", 1),
+                ("6k1/5ppp/8/8/8/5N2/5PPP/6K1 b", -1),
+            ] {
+                let fresh = eval(&format!("{} - - 0 60", fen));
+                let stale = eval(&format!("{} - - 90 60", fen));
+                assert!(fresh * sign > 0);
+                assert!(stale * sign > 0);
+                assert!(stale.abs() < fresh.abs(), "{} {}", fresh, stale);
+                assert_eq!(eval(&format!("{} - - 100 60", fen)), fresh / 2);
+            }
+        }
+    }
+
+    #[test]
+    fn opposite_bishop_scaling() {
+        init_magics();
+        init_bb();
+
+        let board = |fen: &str| {
+            let mut board = Board::try_from(fen).unwrap();
+            board.set_use_nnue(false);
+            board
+        };
+
+        // White is a pawn up with bishops on opposite colours, then on
+        // the same colour.
+        let ocb = board("4k3/1p2b3/8/8/8/1P6/P3B3/4K3 w - - 0 40");
+        let same = board("4k3/1p6/6b1/8/8/1P6/P3B3/4K3 w - - 0 40");
+        assert!(ocb.opposite_bishops());
+        assert!(!same.opposite_bishops());
+        assert_eq!(same.eval(), 100);
+        assert_eq!(ocb.eval(), 50);
+        assert_eq!(board("4k3/1p2b3/8/8/8/1P6/P3B3/4K3 b - - 0 40").eval(), -50);
+
+        // Two pawns up, or with more than bishops on, it's left alone.
+        let two_up = board("4k3/4b3/8/8/8/1P6/P3B3/4K3 w - - 0 40");
+        assert!(two_up.opposite_bishops());
+        assert_eq!(two_up.eval(), 200);
+        for fen in [
+            "r3k3/1p2b3/8/8/8/1P6/P3B3/R3K3 w - - 0 40",
+            "4k3/1p2b3/8/8/8/1P6/P3B3/4KN2 w - - 0 40",
+            "4k3/1p2bb2/8/8/8/1P6/P3B3/4K3 w - - 0 40",
+        ] {
+            assert!(!board(fen).opposite_bishops(), "{}", fen);
+        }
+
+        let mut nnue = ocb.clone();
+        nnue.set_use_nnue(true);
+        assert_eq!(nnue.eval(), nnue.network.eval() / 2);
+    }
+
+    #[test]
+    fn any_legal_move() {
+        init_magics();
+        init_bb();
+
+        fn check(board: &mut Board, depth: usize) {
+            let moves = MoveList::from(&*board);
+            assert_eq!(board.any_legal_move(), moves.len() > 0, "{}", board);
+            if depth == 0 {
+                return;
+            }
+            for m in moves.iter_moves() {
+                board.push(m);
+                check(board, depth - 1);
+                board.pop();
+            }
+        }
+
+        for fen in [
+            Board::STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        ] {
+            check(&mut Board::try_from(fen).unwrap(), 2);
+        }
+
+        for (fen, any_legal_move) in [
+            // Checkmate and stalemate.
+            ("6k1/5ppp/8/8/8/8/5PPP/R5K1 b - - 0 1", true),
+            ("R5k1/5ppp/8/8/8/8/5PPP/6K1 b - - 0 1", false),
+            ("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", false),
+            // Only a pinned knight and a boxed-in king.
+            ("k7/n7/1K6/4B3/8/8/8/Q7 b - - 0 1", false),
+            // Only a pawn can move.
+            ("k7/2Q5/1K6/8/8/8/7p/8 b - - 0 1", true),
+        ] {
+            let board = Board::try_from(fen).unwrap();
+            assert_eq!(board.any_legal_move(), any_legal_move, "{}", fen);
+            assert_eq!(MoveList::from(&board).len() > 0, any_legal_move, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn key_after() {
+        init_magics();
+        init_bb();
+
+        fn check(board: &mut Board, depth: usize) {
+            for m in MoveList::from(&*board).iter_moves() {
+                let key = board.key_after(m);
+                board.push(m);
+                assert_eq!(key, board.hash(), "{} {}", board, m);
+                if depth > 1 {
+                    check(board, depth - 1);
+                }
+                board.pop();
+            }
+        }
+
+        for fen in [
+            Board::STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "rnbqkbnr/pp1ppppp/8/2pP4/8/8/PPP1PPPP/RNBQKBNR w Kq c6 0 3",
+        ] {
+            check(&mut Board::try_from(fen).unwrap(), 3);
+        }
+    }
+
+    #[test]
+    fn gives_check() {
+        init_magics();
+        init_bb();
+
+        fn check(board: &mut Board, depth: usize) {
+            for m in MoveList::from(&*board).iter_moves() {
+                let gives_check = board.gives_check(m);
+                board.push(m);
+                assert_eq!(gives_check, board.in_check(), "{} {}", board, m);
+                if depth > 1 {
+                    check(board, depth - 1);
+                }
+                board.pop();
+            }
+        }
+
+        for fen in [
+            Board::STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "8/8/8/1k1pP2R/8/8/8/4K3 w - d6 0 1",
+            "5k2/8/8/8/8/8/8/4K2R w K - 0 1",
+        ] {
+            check(&mut Board::try_from(fen).unwrap(), 3);
+        }
+
+        let mut board = Board::default();
+        board.set_chess960(true);
+        board
+            .set_fen("qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9")
+            .unwrap();
+        check(&mut board, 3);
+
+        // The knight uncovers the rook wherever it goes.
+        let board = Board::try_from("4k3/8/8/8/4N3/8/8/4R1K1 w - - 0 1").unwrap();
+        for m in MoveList::from(&board).iter_moves() {
+            let knight_move = board.piece_type_at(m.from_sq()) == Some(PieceType::Knight);
+            assert_eq!(board.gives_check(m), knight_move, "{}", m);
+        }
+
+        // Taking en passant uncovers the rook, and castling checks with the rook.
+        let board = Board::try_from("8/8/8/1k1pP2R/8/8/8/4K3 w - d6 0 1").unwrap();
+        assert!(board.gives_check(board.parse_move("e5d6").unwrap()));
+        let board = Board::try_from("5k2/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        assert!(board.gives_check(board.parse_move("e1g1").unwrap()));
+    }
+
+    #[test]
+    fn chess960_castling() {
+        init_magics();
+        init_bb();
+
+        let chess960 = |fen: &str| {
+            let mut board = Board::default();
+            board.set_chess960(true);
+            board.set_fen(fen).unwrap();
+            board
+        };
+
+        // Shredder-FEN names the rook files, X-FEN takes the outermost rooks.
+        let fen = "qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9";
+        assert_eq!(chess960(fen).to_string(), fen);
+        assert_eq!(
+            chess960("qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w KQkq - 1 9")
+                .to_string(),
+            fen
+        );
+        assert_eq!(
+            chess960(Board::STARTING_FEN).to_string(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1"
+        );
+
+        // Outside of chess960 only the standard rights are kept.
+        assert_eq!(
+            Board::try_from(fen).unwrap().to_string(),
+            "qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w - - 1 9"
+        );
+        assert_eq!(
+            Board::try_from("r3k2r/8/8/8/8/8/8/R3K2R w HAha - 0 1")
+                .unwrap()
+                .to_string(),
+            "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1"
+        );
+
+        // The king captures its own rook, and only the rook moves here.
+        let mut board = chess960(fen);
+        let hash = board.hash();
+        let m = board.parse_move("g1h1").unwrap();
+        assert!(m.is_castling());
+        assert!(board.is_legal(m));
+        assert_eq!(board.move_to_san(m), "O-O");
+        let key = board.key_after(m);
+        board.push(m);
+        assert_eq!(board.hash(), key);
+        assert_eq!(
+            board.to_string(),
+            "qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1RRK1 b he - 2 9"
+        );
+        assert_eq!(
+            board.hash(),
+            chess960("qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1RRK1 b he - 2 9").hash()
+        );
+        board.pop();
+        assert_eq!(board.hash(), hash);
+        assert_eq!(board.to_string(), fen);
+
+        // Standard castling is written the same way in chess960.
+        let mut board = chess960("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        assert!(board.parse_move("e1g1").is_err());
+        board.push_str("e1h1").unwrap();
+        board.push_str("e8a8").unwrap();
+        assert_eq!(board.to_string(), "2kr3r/8/8/8/8/8/8/R4RK1 w - - 2 2");
+
+        // The rook on b1 shields c1 from the queen, so the king can't stay.
+        for (fen, can_castle) in [
+            ("4k3/8/8/8/8/8/8/qRK5 w B - 0 1", false),
+            ("4k3/8/8/8/8/8/q7/1RK5 w B - 0 1", true),
+        ] {
+            let board = chess960(fen);
+            assert_eq!(
+                MoveList::from(&board).iter_moves().any(|m| m.is_castling()),
+                can_castle,
+                "{}",
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        init_magics();
+        for fen in [
+            Board::STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+            "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 2",
+            "rnbqkbnr/pp1ppppp/8/2pP4/8/8/PPP1PPPP/RNBQKBNR w Kq c6 0 3",
+            "4k3/8/8/8/8/8/8/4K2R b K - 37 120",
+            "8/8/8/8/8/8/6k1/4K2R w - - 99 600",
+            "1k6/8/8/8/8/8/8/QQQQKQQQ b - - 0 1",
+        ] {
+            let board = Board::try_from(fen).unwrap();
+            let exported = board.to_string();
+            assert_eq!(exported, fen);
+
+            let reimported = Board::try_from(exported.as_str()).unwrap();
+            assert_eq!(reimported.hash(), board.hash(), "{}", fen);
+            assert_eq!(reimported.to_string(), exported);
+        }
+    }
+
+    #[test]
+    fn from_epd() {
+        init_magics();
+
+        let (board, operations) = Board::from_epd(
+            r#"r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - bm Bb5 Bc4; am Qe2; id "Open game; 3rd move"; c0 "best line";"#,
+        )
+        .unwrap();
+        assert_eq!(
+            board.to_string(),
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 1"
+        );
+        assert_eq!(operations.len(), 4);
+        assert_eq!(operations["bm"], "Bb5 Bc4");
+        assert_eq!(operations["am"], "Qe2");
+        assert_eq!(operations["id"], "Open game; 3rd move");
+        assert_eq!(operations["c0"], "best line");
+
+        let (board, operations) =
+            Board::from_epd("4k3/8/8/8/8/8/8/4K2R b K -  hmvc 12;fmvn 40; noop;").unwrap();
+        assert_eq!(board.to_string(), "4k3/8/8/8/8/8/8/4K2R b K - 12 40");
+        assert_eq!(operations["hmvc"], "12");
+        assert_eq!(operations["noop"], "");
+
+        let (_, operations) = Board::from_epd("4k3/8/8/8/8/8/8/4K3 w - -").unwrap();
+        assert!(operations.is_empty());
+
+        assert_eq!(
+            Board::from_epd(r#"4k3/8/8/8/8/8/8/4K3 w - - id "unterminated;"#).err(),
+            Some(FenError::InvalidEpdOperation("id".to_string()))
+        );
+        assert!(Board::from_epd("4k3/8/8/8/8/8/8/4K3 x - - bm Kd2;").is_err());
+        assert!(Board::from_epd("4k3/8/8/8/8/8/8/4K3 w - - fmvn zero;").is_err());
+    }
+
+    #[test]
+    fn long_game() {
+        init_magics();
+        let mut board = Board::new();
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        while board.ply() + 4 < Board::N_HISTORIES {
+            board.push_str(shuffle[board.ply() % 4]).unwrap();
+        }
+        board.push_str("e2e4").unwrap();
+        board.push_str("e7e5").unwrap();
+
+        while board.ply() < 1100 {
+            board.push_str(shuffle[(board.ply() - 998) % 4]).unwrap();
+            assert_eq!(board.repetition_count(), (board.ply() - 998) / 4);
+        }
+        assert!(board.history.len() > Board::N_HISTORIES);
+
+        while board.ply() > 996 {
+            board.pop();
+        }
+        assert_eq!(board.repetition_count(), 996 / 4);
+    }
 }
@@ -0,0 +1,152 @@
+use super::bitboard::*;
+use super::piece::*;
+use super::square::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CastlingSide {
+    King,
+    Queen,
+}
+
+impl CastlingSide {
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+///////////////////////////////////////////////////////////////////
+// The squares involved in one castling right. In chess960 the king
+// and rook can start anywhere on the back rank, but they always end
+// up on the same squares as in standard chess.
+///////////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CastlingRight {
+    pub king: SQ,
+    pub rook: SQ,
+    pub king_to: SQ,
+    pub rook_to: SQ,
+    // Squares that have to be empty, not counting the king and rook.
+    pub blockers: Bitboard,
+    // Squares the king crosses or lands on, which can't be attacked.
+    pub king_path: Bitboard,
+}
+
+impl CastlingRight {
+    pub fn new(c: Color, side: CastlingSide, king: SQ, rook: SQ) -> Self {
+        let (king_to, rook_to) = match side {
+            CastlingSide::King => (SQ::G1.relative(c), SQ::F1.relative(c)),
+            CastlingSide::Queen => (SQ::C1.relative(c), SQ::D1.relative(c)),
+        };
+        let king_path = Self::span(king, king_to);
+
+        Self {
+            king,
+            rook,
+            king_to,
+            rook_to,
+            blockers: (king_path | Self::span(rook, rook_to)) & !(king.bb() | rook.bb()),
+            king_path,
+        }
+    }
+
+    // Everything from one square to the other, not counting the first. A
+    // chess960 king or rook might already be on its castling square.
+    fn span(from_sq: SQ, to_sq: SQ) -> Bitboard {
+        if from_sq == to_sq {
+            return to_sq.bb();
+        }
+        Bitboard::between(from_sq, to_sq) | to_sq.bb()
+    }
+
+    // The right is lost as soon as anything moves from or to one of these.
+    pub fn mask(&self) -> Bitboard {
+        self.king.bb() | self.rook.bb()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Castling([Option<CastlingRight>; 4]);
+
+impl Castling {
+    pub fn get(&self, c: Color, side: CastlingSide) -> Option<CastlingRight> {
+        self.0[Self::index(c, side)]
+    }
+
+    pub fn set(&mut self, c: Color, side: CastlingSide, right: CastlingRight) {
+        self.0[Self::index(c, side)] = Some(right);
+    }
+
+    // In the order the hasher keeps its castling keys.
+    pub fn masks(&self) -> [Bitboard; 4] {
+        self.0
+            .map(|right| right.map_or(Bitboard::ZERO, |right| right.mask()))
+    }
+
+    fn index(c: Color, side: CastlingSide) -> usize {
+        c.index() * 2 + side.index()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bitboard::*;
+    use crate::castling::*;
+
+    #[test]
+    fn standard_rights() {
+        init_bb();
+
+        for c in [Color::White, Color::Black] {
+            let oo = CastlingRight::new(
+                c,
+                CastlingSide::King,
+                SQ::E1.relative(c),
+                SQ::H1.relative(c),
+            );
+            assert_eq!(oo.mask(), SQ::E1.relative(c).bb() | SQ::H1.relative(c).bb());
+            assert_eq!(
+                oo.blockers,
+                SQ::F1.relative(c).bb() | SQ::G1.relative(c).bb()
+            );
+            assert_eq!(oo.king_path, oo.blockers);
+
+            let ooo = CastlingRight::new(
+                c,
+                CastlingSide::Queen,
+                SQ::E1.relative(c),
+                SQ::A1.relative(c),
+            );
+            assert_eq!(
+                ooo.mask(),
+                SQ::E1.relative(c).bb() | SQ::A1.relative(c).bb()
+            );
+            assert_eq!(
+                ooo.blockers,
+                SQ::B1.relative(c).bb() | SQ::C1.relative(c).bb() | SQ::D1.relative(c).bb()
+            );
+            assert_eq!(
+                ooo.king_path,
+                SQ::C1.relative(c).bb() | SQ::D1.relative(c).bb()
+            );
+        }
+    }
+
+    #[test]
+    fn chess960_rights() {
+        init_bb();
+
+        // The king already stands on g1 and only the rook moves.
+        let oo = CastlingRight::new(Color::White, CastlingSide::King, SQ::G1, SQ::H1);
+        assert_eq!(oo.blockers, SQ::F1.bb());
+        assert_eq!(oo.king_path, SQ::G1.bb());
+
+        // The king and rook swap squares.
+        let oo = CastlingRight::new(Color::Black, CastlingSide::King, SQ::F8, SQ::G8);
+        assert_eq!(oo.blockers, Bitboard::ZERO);
+        assert_eq!(oo.king_path, SQ::G8.bb());
+
+        let ooo = CastlingRight::new(Color::White, CastlingSide::Queen, SQ::B1, SQ::A1);
+        assert_eq!(ooo.blockers, SQ::C1.bb() | SQ::D1.bb());
+        assert_eq!(ooo.king_path, SQ::C1.bb());
+    }
+}
@@ -0,0 +1,610 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::board::*;
+use super::moov::*;
+use super::rng::*;
+use super::search::*;
+use super::timer::*;
+use super::tt::*;
+use super::types::*;
+
+pub enum EngineOption {
+    Hash(usize),
+    Threads(u16),
+    Overhead(Duration),
+    MinThinkTime(Duration),
+    MaxThinkTime(Duration),
+    NodesTime(u64),
+    Seed(u64),
+    SanPv(bool),
+    AnalyseMode(bool),
+    ShowWdl(bool),
+    RootMoveRandomization(Value),
+    LimitStrength(bool),
+    Elo(u32),
+    Contempt(Value),
+    UseNnue(bool),
+    Chess960(bool),
+    Ponder(bool),
+    AspirationWindow(Value),
+    RfpMarginMultiplier(Value),
+    NullDepthDivider(Depth),
+    LmrBaseReduction(f32),
+    LmrMoveDivider(f32),
+    LmrPvReduction(Depth),
+    LmrImprovingReduction(Depth),
+    LmrHistoryDivider(Value),
+    SingExtendDepthMargin(Depth),
+    ClearHash,
+}
+
+pub struct Engine {
+    stop: Arc<AtomicBool>,
+    pondering: Arc<AtomicBool>,
+    board: Board,
+    num_threads: u16,
+    tt: TT,
+    timer_options: TimerOptions,
+    seed: u64,
+    rng: Rng,
+    search_options: SearchOptions,
+    ponder: bool,
+}
+
+impl Engine {
+    pub fn new(stop: Arc<AtomicBool>) -> Self {
+        crate::init();
+        Self {
+            stop,
+            pondering: Arc::new(AtomicBool::new(false)),
+            board: Board::new(),
+            num_threads: 1,
+            tt: TT::new(16),
+            timer_options: TimerOptions::default(),
+            seed: 0,
+            rng: Self::seeded_rng(0),
+            search_options: SearchOptions::default(),
+            ponder: false,
+        }
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Whoever reads the GUI's commands sets this for go ponder and
+    // clears it on ponderhit, while the search is running.
+    ///////////////////////////////////////////////////////////////////
+    pub fn with_pondering(mut self, pondering: Arc<AtomicBool>) -> Self {
+        self.pondering = pondering;
+        self
+    }
+
+    pub fn new_game(&mut self) {
+        self.board.reset();
+        self.tt.clear();
+        self.rng = Self::seeded_rng(self.seed);
+    }
+
+    pub fn set_position(&mut self, fen: Option<&str>, moves: &[String]) -> Result<(), String> {
+        let mut board = Board::new();
+        board.set_chess960(self.board.chess960());
+        board.set_use_nnue(self.board.use_nnue());
+        if let Some(fen) = fen {
+            board.set_fen(fen).map_err(|err| err.to_string())?;
+        }
+
+        for m in moves {
+            board.push_str(m).map_err(|err| format!("{} {}", err, m))?;
+            board.truncate_history();
+        }
+
+        self.board = board;
+        Ok(())
+    }
+
+    pub fn search(&mut self, time_control: TimeControl) -> (Option<Move>, Value) {
+        self.search_among(time_control, None, &[])
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Only the given root moves are searched, as with go searchmoves.
+    // Moves that aren't legal here are dropped, and if none are left
+    // we search everything. A mate limit ends the search early once it
+    // finds a short enough mate, as with go mate.
+    ///////////////////////////////////////////////////////////////////
+    pub fn search_among(
+        &mut self,
+        time_control: TimeControl,
+        mate_limit: Option<u32>,
+        search_moves: &[String],
+    ) -> (Option<Move>, Value) {
+        self.stop.store(false, Ordering::SeqCst);
+        self.tt.new_search();
+        let nodes = Arc::new(AtomicU64::new(0));
+        let search_moves = search_moves
+            .iter()
+            .filter_map(|m| self.board.parse_move(m).ok())
+            .collect::<Vec<Move>>();
+
+        thread::scope(|s| {
+            // Create main search thread with the actual time control. This thread controls self.stop.
+            let mut main_search_thread = Search::new(
+                Timer::new(
+                    &self.board,
+                    time_control,
+                    self.stop.clone(),
+                    nodes.clone(),
+                    self.timer_options,
+                )
+                .with_pondering(self.pondering.clone())
+                .with_mate_limit(mate_limit),
+                &self.tt,
+                self.search_options,
+                0,
+            )
+            .with_rng(Rng::new(self.rng.next_u64()))
+            .with_search_moves(search_moves.clone());
+
+            // Create helper search threads which will stop when self.stop resolves to true.
+            for id in 1..self.num_threads {
+                let thread_board = self.board.clone();
+                let mut helper_search_thread = Search::new(
+                    Timer::new(
+                        &thread_board,
+                        TimeControl::Infinite,
+                        self.stop.clone(),
+                        nodes.clone(),
+                        self.timer_options,
+                    ),
+                    &self.tt,
+                    self.search_options,
+                    id,
+                )
+                .with_search_moves(search_moves.clone());
+                s.spawn(move || helper_search_thread.go(thread_board));
+            }
+            main_search_thread.go(self.board.clone())
+        })
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // The reply to the best move comes from the hash table rather than
+    // the pv, so there's one even when the last iteration didn't get
+    // that far. Only legal moves are offered, in case of a collision.
+    ///////////////////////////////////////////////////////////////////
+    pub fn ponder_move(&mut self, best_move: Move) -> Option<Move> {
+        self.board.push(best_move);
+        let ponder_move = self
+            .tt
+            .probe(&self.board)
+            .and_then(|entry| entry.best_move())
+            .filter(|&m| self.board.is_legal(m));
+        self.board.pop();
+        ponder_move
+    }
+
+    pub fn set_option(&mut self, option: EngineOption) {
+        match option {
+            EngineOption::Hash(mb_size) => self.tt.resize(mb_size),
+            EngineOption::ClearHash => self.tt.clear(),
+            EngineOption::Threads(num_threads) => self.num_threads = num_threads,
+            EngineOption::Overhead(overhead) => self.timer_options.overhead = overhead,
+            EngineOption::MinThinkTime(min_think_time) => {
+                self.timer_options.min_think_time = min_think_time
+            }
+            EngineOption::MaxThinkTime(max_think_time) => {
+                self.timer_options.max_think_time = max_think_time
+            }
+            EngineOption::NodesTime(nodestime) => self.timer_options.nodestime = nodestime,
+            EngineOption::Seed(seed) => {
+                self.seed = seed;
+                self.rng = Self::seeded_rng(seed);
+            }
+            EngineOption::SanPv(san_pv) => self.search_options.san_pv = san_pv,
+            EngineOption::UseNnue(use_nnue) => self.board.set_use_nnue(use_nnue),
+            EngineOption::Chess960(chess960) => self.board.set_chess960(chess960),
+            EngineOption::Ponder(ponder) => self.ponder = ponder,
+            EngineOption::AnalyseMode(analyse_mode) => {
+                self.search_options.analyse_mode = analyse_mode
+            }
+            EngineOption::ShowWdl(show_wdl) => self.search_options.show_wdl = show_wdl,
+            EngineOption::RootMoveRandomization(margin) => {
+                self.search_options.root_randomization = margin
+            }
+            EngineOption::LimitStrength(limit_strength) => {
+                self.search_options.limit_strength = limit_strength
+            }
+            EngineOption::Elo(elo) => {
+                self.search_options.elo = elo.clamp(Search::MIN_ELO, Search::MAX_ELO)
+            }
+            // Draw scores in the hash table carry the contempt they were
+            // searched with, so they go when it changes. A new game clears
+            // them anyway, in case we're playing the other side now.
+            EngineOption::Contempt(contempt) => {
+                let contempt = contempt.clamp(-Search::MAX_CONTEMPT, Search::MAX_CONTEMPT);
+                if contempt != self.search_options.contempt {
+                    self.search_options.contempt = contempt;
+                    self.tt.clear();
+                }
+            }
+            EngineOption::AspirationWindow(window) => {
+                self.search_options.params.aspiration_window = window.max(1)
+            }
+            EngineOption::RfpMarginMultiplier(multiplier) => {
+                self.search_options.params.rfp_margin_multiplier = multiplier
+            }
+            EngineOption::NullDepthDivider(divider) => {
+                self.search_options.params.null_depth_divider = divider.max(1)
+            }
+            EngineOption::LmrBaseReduction(reduction) => {
+                self.search_options.params.lmr_base_reduction = reduction
+            }
+            EngineOption::LmrMoveDivider(divider) => {
+                self.search_options.params.lmr_move_divider = divider.max(0.01)
+            }
+            EngineOption::LmrPvReduction(reduction) => {
+                self.search_options.params.lmr_pv_reduction = reduction
+            }
+            EngineOption::LmrImprovingReduction(reduction) => {
+                self.search_options.params.lmr_improving_reduction = reduction
+            }
+            EngineOption::LmrHistoryDivider(divider) => {
+                self.search_options.params.lmr_history_divider = divider.max(1)
+            }
+            EngineOption::SingExtendDepthMargin(margin) => {
+                self.search_options.params.sing_extend_depth_margin = margin
+            }
+        }
+    }
+
+    // From the side to move's point of view.
+    pub fn eval(&self) -> Value {
+        self.board.eval()
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn board_mut(&mut self) -> &mut Board {
+        &mut self.board
+    }
+
+    pub fn hash_mb(&self) -> usize {
+        self.tt.mb_size()
+    }
+
+    pub fn hashfull(&self) -> usize {
+        self.tt.hashfull()
+    }
+
+    pub fn num_threads(&self) -> u16 {
+        self.num_threads
+    }
+
+    pub fn overhead(&self) -> Duration {
+        self.timer_options.overhead
+    }
+
+    pub fn timer_options(&self) -> TimerOptions {
+        self.timer_options
+    }
+
+    pub fn ponder(&self) -> bool {
+        self.ponder
+    }
+
+    pub fn search_options(&self) -> SearchOptions {
+        self.search_options
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Seed 0, the default, means a fresh seed from the clock each time
+    // the generator is set up. Any other seed gives the same sequence
+    // every time.
+    ///////////////////////////////////////////////////////////////////
+    fn seeded_rng(seed: u64) -> Rng {
+        if seed == 0 {
+            Rng::new(Rng::time_seed())
+        } else {
+            Rng::new(seed)
+        }
+    }
+
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new(Arc::new(AtomicBool::new(false)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::bitboard::*;
+    use crate::engine::*;
+    use crate::magics::*;
+
+    #[test]
+    fn search_position() {
+        init_magics();
+        init_bb();
+
+        let mut engine = Engine::default();
+        engine.set_option(EngineOption::Hash(1));
+        engine.set_option(EngineOption::Threads(2));
+
+        // Back rank mate in one.
+        engine
+            .set_position(Some("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1"), &[])
+            .unwrap();
+        let (best_move, value) = engine.search(TimeControl::FixedDepth(4));
+        assert_eq!(best_move.map(|m| m.to_string()), Some("a1a8".to_string()));
+        assert!(Search::is_checkmate(value));
+
+        // Stalemate.
+        engine
+            .set_position(Some("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), &[])
+            .unwrap();
+        assert_eq!(engine.search(TimeControl::FixedDepth(4)), (None, 0));
+
+        assert!(engine
+            .set_position(None, &["e2e4".to_string(), "e2e4".to_string()])
+            .is_err());
+    }
+
+    #[test]
+    fn search_among() {
+        init_magics();
+        init_bb();
+
+        let mut engine = Engine::default();
+        engine.set_option(EngineOption::Hash(1));
+
+        // The mate in one isn't on the list, and neither are the moves
+        // that aren't legal.
+        engine
+            .set_position(Some("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1"), &[])
+            .unwrap();
+        let search_moves = ["a1a2", "h2h3", "e2e4", "a1a9"].map(|m| m.to_string());
+        for threads in [1, 2] {
+            engine.set_option(EngineOption::Threads(threads));
+            let (best_move, value) =
+                engine.search_among(TimeControl::FixedDepth(4), None, &search_moves);
+            let best_move = best_move.map(|m| m.to_string()).unwrap();
+            assert!(["a1a2", "h2h3"].contains(&best_move.as_str()));
+            assert!(!Search::is_checkmate(value));
+        }
+
+        // With nothing valid left, every move is considered.
+        let (best_move, _) =
+            engine.search_among(TimeControl::FixedDepth(4), None, &["e2e4".to_string()]);
+        assert_eq!(best_move.map(|m| m.to_string()), Some("a1a8".to_string()));
+    }
+
+    #[test]
+    fn clear_hash() {
+        init_magics();
+        init_bb();
+
+        let mut engine = Engine::default();
+        engine.set_option(EngineOption::Hash(1));
+        engine.search(TimeControl::FixedDepth(8));
+        assert!(engine.hashfull() > 0);
+
+        engine.set_option(EngineOption::ClearHash);
+        assert_eq!(engine.hashfull(), 0);
+    }
+
+    #[test]
+    fn stop_in_capture_sequence() {
+        init_magics();
+        init_bb();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut engine = Engine::new(stop.clone());
+        engine.set_option(EngineOption::Hash(1));
+        engine
+            .set_position(
+                Some("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1"),
+                &[],
+            )
+            .unwrap();
+
+        ///////////////////////////////////////////////////////////////////
+        // Keep raising the flag, since the search clears it when it starts.
+        // Nodes, q_search included, check it every Timer::CHECK_FREQ calls,
+        // so an infinite search has to return soon after.
+        ///////////////////////////////////////////////////////////////////
+        let searching = Arc::new(AtomicBool::new(true));
+        let start = Instant::now();
+        let stopper = {
+            let searching = searching.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                let stopped_at = Instant::now();
+                while searching.load(Ordering::SeqCst) {
+                    stop.store(true, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(1));
+                }
+                stopped_at.elapsed()
+            })
+        };
+
+        let (best_move, _) = engine.search(TimeControl::Infinite);
+        searching.store(false, Ordering::SeqCst);
+        let latency = stopper.join().unwrap();
+
+        assert!(best_move.is_some());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(latency < Duration::from_secs(1), "{:?}", latency);
+    }
+
+    #[test]
+    fn ponder_move() {
+        init_magics();
+        init_bb();
+
+        let mut engine = Engine::default();
+        engine.set_option(EngineOption::Hash(1));
+
+        ///////////////////////////////////////////////////////////////////
+        // A depth 1 search goes straight into q_search below the root,
+        // which doesn't store anything, so there's no reply to offer.
+        ///////////////////////////////////////////////////////////////////
+        let (best_move, _) = engine.search(TimeControl::FixedDepth(1));
+        assert_eq!(engine.ponder_move(best_move.unwrap()), None);
+
+        engine.new_game();
+        let (best_move, _) = engine.search(TimeControl::FixedDepth(6));
+        let best_move = best_move.unwrap();
+        let ponder_move = engine.ponder_move(best_move).unwrap();
+
+        let fen = engine.board().to_string();
+        let mut board = engine.board().clone();
+        board.push(best_move);
+        assert!(board.is_legal(ponder_move));
+        assert_eq!(engine.board().to_string(), fen);
+    }
+
+    #[test]
+    fn seeded_rng() {
+        let mut a = Engine::default();
+        let mut b = Engine::default();
+        a.set_option(EngineOption::Seed(1234));
+        b.set_option(EngineOption::Seed(1234));
+
+        let draws: Vec<u64> = (0..8).map(|_| a.rng().below(100)).collect();
+        assert_eq!(
+            draws,
+            (0..8).map(|_| b.rng().below(100)).collect::<Vec<_>>()
+        );
+
+        // A new game restarts the sequence from the seed.
+        a.new_game();
+        assert_eq!(
+            draws,
+            (0..8).map(|_| a.rng().below(100)).collect::<Vec<_>>()
+        );
+
+        // Setting the advertised default is the same as leaving it alone.
+        let mut c = Engine::default();
+        assert_eq!(c.seed(), 0);
+        c.set_option(EngineOption::Seed(0));
+        assert_eq!(c.seed(), 0);
+    }
+
+    #[test]
+    fn root_move_randomization() {
+        init_magics();
+        init_bb();
+
+        let best_moves = |seed: u64| {
+            let mut engine = Engine::default();
+            engine.set_option(EngineOption::Hash(1));
+            engine.set_option(EngineOption::Seed(seed));
+            engine.set_option(EngineOption::RootMoveRandomization(50));
+            (0..3)
+                .map(|_| engine.search(TimeControl::FixedDepth(4)).0.unwrap())
+                .collect::<Vec<Move>>()
+        };
+
+        assert_eq!(best_moves(7), best_moves(7));
+
+        let mut chosen = (1..=8).flat_map(best_moves).collect::<Vec<Move>>();
+        chosen.sort_by_key(|m| m.move_int());
+        chosen.dedup();
+        assert!(chosen.len() > 1);
+        assert!(chosen.iter().all(|&m| Board::new().is_legal(m)));
+    }
+
+    #[test]
+    fn contempt_avoids_repetition() {
+        init_magics();
+        init_bb();
+
+        let best_move = |contempt| {
+            let mut engine = Engine::default();
+            engine.set_option(EngineOption::Hash(1));
+            engine.set_option(EngineOption::Contempt(contempt));
+            let moves =
+                ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"].map(|m| m.to_string());
+            engine.set_position(None, &moves).unwrap();
+            engine
+                .search(TimeControl::FixedDepth(6))
+                .0
+                .unwrap()
+                .to_string()
+        };
+
+        // Going back to g8 repeats the starting position a third time.
+        assert_eq!(best_move(-Search::MAX_CONTEMPT), "f6g8");
+        assert_ne!(best_move(Search::MAX_CONTEMPT), "f6g8");
+    }
+
+    #[test]
+    fn limit_strength() {
+        init_magics();
+        init_bb();
+
+        let best_moves = |seed: u64, elo: u32| {
+            let mut engine = Engine::default();
+            engine.set_option(EngineOption::Hash(1));
+            engine.set_option(EngineOption::Seed(seed));
+            engine.set_option(EngineOption::LimitStrength(true));
+            engine.set_option(EngineOption::Elo(elo));
+            (0..3)
+                .map(|_| engine.search(TimeControl::FixedDepth(60)).0.unwrap())
+                .collect::<Vec<Move>>()
+        };
+
+        // The depth and node caps end the search long before its depth limit.
+        assert_eq!(best_moves(7, 1000), best_moves(7, 1000));
+
+        let chosen = |elo| {
+            let mut chosen = (1..=8)
+                .flat_map(|seed| best_moves(seed, elo))
+                .collect::<Vec<Move>>();
+            chosen.sort_by_key(|m| m.move_int());
+            chosen.dedup();
+            chosen
+        };
+        let weak = chosen(1000);
+        assert!(weak.len() > chosen(2800).len());
+        assert!(weak.iter().all(|&m| Board::new().is_legal(m)));
+
+        // Off unless asked for.
+        let mut engine = Engine::default();
+        engine.set_option(EngineOption::Elo(1000));
+        assert!(!engine.search_options().limit_strength);
+
+        // An infinite search still waits for stop once the caps are hit.
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut engine = Engine::new(stop.clone());
+        engine.set_option(EngineOption::Hash(1));
+        engine.set_option(EngineOption::LimitStrength(true));
+        engine.set_option(EngineOption::Elo(1000));
+        let start = Instant::now();
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            stop.store(true, Ordering::SeqCst);
+        });
+        assert!(engine.search(TimeControl::Infinite).0.is_some());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        stopper.join().unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+use super::board::*;
+use super::moov::*;
+
+///////////////////////////////////////////////////////////////////
+// A position from an EPD test suite. It's solved by playing one of
+// the best moves, when there are any, and none of the moves to avoid.
+// Both are resolved from SAN when the line is parsed, so a move that
+// doesn't fit the position is an error rather than a failed test.
+///////////////////////////////////////////////////////////////////
+pub struct EpdPosition {
+    pub board: Board,
+    pub id: Option<String>,
+    pub best_moves: Vec<Move>,
+    pub avoid_moves: Vec<Move>,
+}
+
+impl EpdPosition {
+    pub fn is_solved_by(&self, m: Move) -> bool {
+        (self.best_moves.is_empty() || self.best_moves.contains(&m))
+            && !self.avoid_moves.contains(&m)
+    }
+}
+
+impl TryFrom<&str> for EpdPosition {
+    type Error = String;
+
+    fn try_from(line: &str) -> Result<Self, Self::Error> {
+        let (board, operations) = Board::from_epd(line).map_err(|err| err.to_string())?;
+
+        let moves = |opcode: &str| {
+            operations.get(opcode).map_or(Ok(Vec::new()), |sans| {
+                sans.split_whitespace()
+                    .map(|san| {
+                        board
+                            .san_to_move(san)
+                            .map_err(|err| format!("{} {}", err, san))
+                    })
+                    .collect::<Result<Vec<Move>, String>>()
+            })
+        };
+        let best_moves = moves("bm")?;
+        let avoid_moves = moves("am")?;
+
+        if best_moves.is_empty() && avoid_moves.is_empty() {
+            return Err("EPD position has no bm or am operation.".to_string());
+        }
+
+        Ok(Self {
+            id: operations.get("id").cloned(),
+            board,
+            best_moves,
+            avoid_moves,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bitboard::*;
+    use crate::epd::*;
+    use crate::magics::*;
+
+    #[test]
+    fn parse_and_score() {
+        init_magics();
+        init_bb();
+
+        let position = EpdPosition::try_from(
+            r#"2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id "WAC.001";"#,
+        )
+        .unwrap();
+        assert_eq!(position.id.as_deref(), Some("WAC.001"));
+        assert_eq!(
+            position.board.to_string(),
+            "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1"
+        );
+        let qg6 = position.board.parse_move("g3g6").unwrap();
+        let nf7 = position.board.parse_move("e5f7").unwrap();
+        assert_eq!(position.best_moves, [qg6]);
+        assert!(position.avoid_moves.is_empty());
+        assert!(position.is_solved_by(qg6));
+        assert!(!position.is_solved_by(nf7));
+
+        let position = EpdPosition::try_from(
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - am Qe2 Ba6;",
+        )
+        .unwrap();
+        assert!(position.id.is_none());
+        assert_eq!(position.avoid_moves.len(), 2);
+        assert!(!position.is_solved_by(position.board.parse_move("d1e2").unwrap()));
+        assert!(position.is_solved_by(position.board.parse_move("f1b5").unwrap()));
+
+        // A best move to avoid is never a solution.
+        let position =
+            EpdPosition::try_from("4k3/8/8/8/8/8/8/4K2R w K - bm O-O Rh8+; am O-O;").unwrap();
+        assert!(!position.is_solved_by(position.best_moves[0]));
+        assert!(position.is_solved_by(position.best_moves[1]));
+
+        assert!(EpdPosition::try_from("4k3/8/8/8/8/8/8/4K3 w - - id \"no moves\";").is_err());
+        assert!(EpdPosition::try_from("4k3/8/8/8/8/8/8/4K3 w - - bm Qd2;").is_err());
+        assert!(EpdPosition::try_from("4k3/8/8/8/8/8/8/4K3 x - - bm Kd2;").is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! Weiawaga's board, search and evaluation as a library. The UCI loop
+//! lives in the binary, on top of the same [`Engine`].
+//!
+//! ```
+//! use weiawaga::{Engine, TimeControl};
+//!
+//! let mut engine = Engine::default();
+//! engine.set_position(None, &[]).unwrap();
+//!
+//! let (best_move, _value) = engine.search(TimeControl::FixedDepth(1));
+//! assert!(best_move.is_some_and(|m| engine.board().is_legal(m)));
+//! assert!(engine.eval().abs() < 100);
+//! ```
+
+#![allow(static_mut_refs)]
+#![allow(dead_code)]
+#![allow(clippy::upper_case_acronyms)]
+#![allow(clippy::from_over_into)]
+#![allow(clippy::wrong_self_convention)]
+#![allow(clippy::needless_range_loop)]
+#![allow(clippy::len_without_is_empty)]
+
+use std::sync::Once;
+
+#[macro_use]
+mod bitboard;
+mod attacks;
+mod bench;
+mod board;
+mod castling;
+mod engine;
+mod epd;
+mod magics;
+mod moov;
+mod move_list;
+mod move_picker;
+mod move_sorter;
+mod nnue;
+mod nnue_weights;
+mod perft;
+mod piece;
+mod rng;
+mod search;
+mod square;
+mod timer;
+mod tt;
+mod types;
+mod zobrist;
+
+pub use bench::{bench, print_bench, BENCH_DEPTH};
+pub use board::{Board, FenError};
+pub use engine::{Engine, EngineOption};
+pub use epd::EpdPosition;
+pub use moov::{Move, MoveFlags};
+pub use move_list::MoveList;
+pub use perft::{perft, perft_hashed, print_divide, print_perft};
+pub use rng::Rng;
+pub use search::{Bound, Search, SearchOptions, SearchParams};
+pub use timer::{TimeControl, Timer, TimerOptions};
+pub use tt::TT;
+pub use types::{Depth, Value};
+
+///////////////////////////////////////////////////////////////////
+// Fills in the attack tables everything else depends on. Creating
+// an engine does this, but anything used without one needs it
+// called first.
+///////////////////////////////////////////////////////////////////
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        magics::init_magics();
+        bitboard::init_bb();
+    });
+}
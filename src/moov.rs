@@ -98,6 +98,7 @@ pub enum MoveFlags {
 
 impl From<u8> for MoveFlags {
     fn from(n: u8) -> Self {
+        debug_assert!(n < 16 && !matches!(n, 0b0110 | 0b0111));
         unsafe { std::mem::transmute::<u8, Self>(n) }
     }
 }
@@ -6,6 +6,7 @@ use super::types::*;
 
 use arrayvec::ArrayVec;
 
+// The most legal moves known in any reachable position is 218, so this leaves some headroom.
 pub const MAX_MOVES: usize = 252;
 
 pub struct MoveListEntry {
@@ -38,14 +39,32 @@ impl MoveList {
         moves
     }
 
+    // The quiescence moves plus every quiet move that gives check.
+    pub fn from_q_checks(board: &Board) -> Self {
+        let mut moves = Self::from_q(board);
+        for m in Self::from(board).iter_moves() {
+            if m.is_quiet() && board.gives_check(m) {
+                moves.push(m);
+            }
+        }
+        moves
+    }
+
     pub fn len(&self) -> usize {
         self.0.len()
     }
 
     pub fn push(&mut self, m: Move) {
+        debug_assert!(self.len() < MAX_MOVES, "MoveList is full.");
         self.0.push(MoveListEntry::new(m));
     }
 
+    pub fn try_push(&mut self, m: Move) -> Result<(), &'static str> {
+        self.0
+            .try_push(MoveListEntry::new(m))
+            .map_err(|_| "MoveList is full.")
+    }
+
     pub fn contains(&self, m: Move) -> bool {
         self.0.iter().any(|entry| entry.m == m)
     }
@@ -94,6 +113,10 @@ impl MoveList {
         }
     }
 
+    pub fn retain(&mut self, mut f: impl FnMut(Move) -> bool) {
+        self.0.retain(|entry| f(entry.m));
+    }
+
     pub fn iter_moves(&self) -> impl Iterator<Item = Move> + '_ {
         self.0.iter().map(|entry| entry.m)
     }
@@ -123,6 +146,12 @@ impl MoveList {
     }
 }
 
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'a> IntoIterator for &'a mut MoveList {
     type Item = &'a mut MoveListEntry;
     type IntoIter = std::slice::IterMut<'a, MoveListEntry>;
@@ -160,3 +189,43 @@ impl std::fmt::Debug for MoveList {
         write!(f, "{}", result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::bitboard::*;
+    use crate::magics::*;
+    use crate::move_list::*;
+
+    #[test]
+    fn push_to_capacity() {
+        let m = Move::new(SQ::E2, SQ::E4, MoveFlags::DoublePush);
+        let mut moves = MoveList::new();
+
+        for _ in 0..MAX_MOVES - 1 {
+            moves.push(m);
+        }
+        assert_eq!(moves.try_push(m), Ok(()));
+        assert_eq!(moves.len(), MAX_MOVES);
+
+        assert!(moves.try_push(m).is_err());
+        assert_eq!(moves.len(), MAX_MOVES);
+    }
+
+    #[test]
+    fn quiescence_checks() {
+        init_magics();
+        init_bb();
+
+        let board = Board::try_from("4k3/8/8/3p4/8/8/8/R3K1N1 w - - 0 1").unwrap();
+        let captures = MoveList::from_q(&board);
+        let with_checks = MoveList::from_q_checks(&board);
+
+        assert!(captures.iter_moves().all(|m| with_checks.contains(m)));
+        let quiet_checks = with_checks
+            .iter_moves()
+            .filter(|&m| !captures.contains(m))
+            .map(|m| m.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(quiet_checks, ["a1a8"]);
+    }
+}
@@ -0,0 +1,192 @@
+use super::board::*;
+use super::moov::*;
+use super::move_list::*;
+use super::move_sorter::*;
+use super::piece::*;
+use super::types::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Stage {
+    HashMove,
+    GenerateCaptures,
+    GoodCaptures,
+    Killers,
+    GenerateQuiets,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+///////////////////////////////////////////////////////////////////
+// Hands out the moves of a position in stages, so that a cutoff by
+// the hash move or an early capture skips generating and scoring the
+// rest. Every legal move comes out exactly once.
+///////////////////////////////////////////////////////////////////
+pub struct MovePicker {
+    stage: Stage,
+    hash_move: Option<Move>,
+    ply: Ply,
+    killers: [Option<Move>; MoveSorter::N_KILLERS],
+    killer_idx: usize,
+    captures: MoveList,
+    capture_idx: usize,
+    quiets: MoveList,
+    quiet_idx: usize,
+}
+
+impl MovePicker {
+    pub fn new(hash_move: Option<Move>, ply: Ply) -> Self {
+        Self {
+            stage: Stage::HashMove,
+            hash_move,
+            ply,
+            killers: [None; MoveSorter::N_KILLERS],
+            killer_idx: 0,
+            captures: MoveList::new(),
+            capture_idx: 0,
+            quiets: MoveList::new(),
+            quiet_idx: 0,
+        }
+    }
+
+    pub fn next(&mut self, move_sorter: &MoveSorter, board: &Board) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::HashMove => {
+                    self.stage = Stage::GenerateCaptures;
+                    if let Some(m) = self.hash_move.filter(|&m| board.is_legal(m)) {
+                        return Some(m);
+                    }
+                }
+                Stage::GenerateCaptures => {
+                    board.generate_legal_moves::<false>(&mut self.captures);
+                    let hash_move = self.hash_move;
+                    self.captures.retain(|m| Some(m) != hash_move);
+                    move_sorter.score_moves(&mut self.captures, board, self.ply, None);
+                    self.stage = Stage::GoodCaptures;
+                }
+                Stage::GoodCaptures => {
+                    ///////////////////////////////////////////////////////////////////
+                    // Losing captures score below zero. The first one ends the
+                    // stage and stays where it is for the bad captures.
+                    ///////////////////////////////////////////////////////////////////
+                    match self.captures.next_best(self.capture_idx) {
+                        Some(m) if self.captures[self.capture_idx].score >= 0 => {
+                            self.capture_idx += 1;
+                            return Some(m);
+                        }
+                        _ => {
+                            self.killers = move_sorter.killers(board, self.ply);
+                            self.stage = Stage::Killers;
+                        }
+                    }
+                }
+                Stage::Killers => {
+                    let Some(&killer) = self.killers.get(self.killer_idx) else {
+                        self.stage = Stage::GenerateQuiets;
+                        continue;
+                    };
+
+                    ///////////////////////////////////////////////////////////////////
+                    // Killers come from sibling nodes and can repeat, so each one
+                    // has to be legal here and not already handed out.
+                    ///////////////////////////////////////////////////////////////////
+                    let seen = &self.killers[..self.killer_idx];
+                    self.killer_idx += 1;
+                    if let Some(m) = killer.filter(|&m| {
+                        Some(m) != self.hash_move && !seen.contains(&Some(m)) && board.is_legal(m)
+                    }) {
+                        return Some(m);
+                    }
+                }
+                Stage::GenerateQuiets => {
+                    board.generate_legal_moves::<true>(&mut self.quiets);
+                    let (hash_move, killers) = (self.hash_move, self.killers);
+                    self.quiets.retain(|m| {
+                        !Self::is_q_move(m) && Some(m) != hash_move && !killers.contains(&Some(m))
+                    });
+                    move_sorter.score_moves(&mut self.quiets, board, self.ply, None);
+                    self.stage = Stage::Quiets;
+                }
+                Stage::Quiets => match self.quiets.next_best(self.quiet_idx) {
+                    Some(m) => {
+                        self.quiet_idx += 1;
+                        return Some(m);
+                    }
+                    None => self.stage = Stage::BadCaptures,
+                },
+                Stage::BadCaptures => match self.captures.next_best(self.capture_idx) {
+                    Some(m) => {
+                        self.capture_idx += 1;
+                        return Some(m);
+                    }
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    // Whether the capture stage generated m. Underpromotions are left
+    // for the quiets.
+    fn is_q_move(m: Move) -> bool {
+        match m.promotion() {
+            Some(pt) => pt == PieceType::Queen,
+            None => m.is_capture(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bitboard::*;
+    use crate::magics::*;
+    use crate::move_picker::*;
+    use crate::square::*;
+
+    #[test]
+    fn picks_every_legal_move_once() {
+        init_magics();
+        init_bb();
+
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "4k3/8/8/8/1b6/8/5P2/4K3 w - - 0 1",
+        ] {
+            let board = Board::try_from(fen).unwrap();
+            let legal = MoveList::from(&board);
+
+            let mut move_sorter = MoveSorter::new();
+            let killers = legal
+                .iter_moves()
+                .filter(|m| m.is_quiet())
+                .take(2)
+                .collect::<Vec<_>>();
+            for &killer in killers.iter().chain(&killers) {
+                move_sorter.add_killer(&board, killer, 0);
+            }
+
+            for hash_move in [
+                None,
+                legal.iter_moves().last(),
+                Some(Move::new(SQ::A1, SQ::H8, MoveFlags::Quiet)),
+            ] {
+                let mut picker = MovePicker::new(hash_move, 0);
+                let mut picked = Vec::new();
+                while let Some(m) = picker.next(&move_sorter, &board) {
+                    picked.push(m);
+                }
+
+                assert_eq!(picked.len(), legal.len(), "{}", fen);
+                assert!(legal.iter_moves().all(|m| picked.contains(&m)), "{}", fen);
+                if let Some(m) = hash_move.filter(|&m| legal.contains(m)) {
+                    assert_eq!(picked[0], m);
+                }
+            }
+        }
+    }
+}
@@ -9,14 +9,32 @@ use super::types::*;
 
 pub struct MoveSorter {
     killer_moves: ColorMap<[[Option<Move>; Self::N_KILLERS]; MAX_MOVES]>,
-    history_scores: SQMap<SQMap<Value>>,
+    history_scores: ColorMap<PieceTypeMap<SQMap<Value>>>,
+    // Indexed by the earlier move's piece and target square, then the
+    // piece and target square of the move being scored.
+    continuation_history: Box<[PieceMap<SQMap<Value>>]>,
+    capture_history: PieceTypeMap<SQMap<PieceTypeMap<Value>>>,
 }
 
 impl MoveSorter {
     pub fn new() -> Self {
         Self {
             killer_moves: ColorMap::new([[[None; Self::N_KILLERS]; MAX_MOVES]; Color::N_COLORS]),
-            history_scores: SQMap::new([SQMap::new([0; SQ::N_SQUARES]); SQ::N_SQUARES]),
+            history_scores: ColorMap::new(
+                [PieceTypeMap::new([SQMap::new([0; SQ::N_SQUARES]); PieceType::N_PIECE_TYPES]);
+                    Color::N_COLORS],
+            ),
+            continuation_history: vec![
+                PieceMap::new(
+                    [SQMap::new([0; SQ::N_SQUARES]); Piece::N_PIECES]
+                );
+                Piece::N_PIECES * SQ::N_SQUARES
+            ]
+            .into_boxed_slice(),
+            capture_history: PieceTypeMap::new(
+                [SQMap::new([PieceTypeMap::new([0; PieceType::N_PIECE_TYPES]); SQ::N_SQUARES]);
+                    PieceType::N_PIECE_TYPES],
+            ),
         }
     }
 
@@ -46,7 +64,9 @@ impl MoveSorter {
                 return Self::CASTLING_SCORE;
             }
 
-            return Self::HISTORY_MOVE_OFFSET + self.history_score(m);
+            return Self::HISTORY_MOVE_OFFSET
+                + (self.history_score(board, m) + self.continuation_score(board, m))
+                    .min(-Self::HISTORY_MOVE_OFFSET - 1);
         }
 
         let mut score = 0;
@@ -56,6 +76,7 @@ impl MoveSorter {
             }
 
             score += Self::mvv_lva_score(board, m)
+                + self.capture_history_score(board, m) / Self::CAPTURE_HISTORY_DIVIDER
                 + if Self::see(board, m) {
                     Self::WINNING_CAPTURES_OFFSET
                 } else {
@@ -92,29 +113,128 @@ impl MoveSorter {
         killer_moves[0] = Some(m);
     }
 
-    pub fn add_history(&mut self, m: Move, depth: Depth) {
-        let depth = depth as Value;
-        let from = m.from_sq();
-        let to = m.to_sq();
-        self.history_scores[from][to] += depth * depth;
+    ///////////////////////////////////////////////////////////////////
+    // The quiet move that caused a cutoff gets a bonus, and the quiets
+    // searched before it a malus of the same size.
+    ///////////////////////////////////////////////////////////////////
+    pub fn apply_history(
+        &mut self,
+        board: &Board,
+        m: Move,
+        quiets_tried: impl IntoIterator<Item = Move>,
+        depth: Depth,
+    ) {
+        let bonus = ((depth as Value) * (depth as Value)).min(Self::HISTORY_BONUS_MAX);
+        self.update_history(board, m, bonus);
+        for quiet in quiets_tried {
+            self.update_history(board, quiet, -bonus);
+        }
+    }
 
-        if self.history_scores[from][to] >= -Self::HISTORY_MOVE_OFFSET {
+    fn update_history(&mut self, board: &Board, m: Move, bonus: Value) {
+        let pc = Self::moved_piece(board, m);
+        let score = &mut self.history_scores[pc.color_of()][pc.type_of()][m.to_sq()];
+        *score += bonus;
+
+        if score.abs() >= -Self::HISTORY_MOVE_OFFSET {
             self.history_scores
                 .iter_mut()
                 .flatten()
-                .for_each(|x| *x >>= 1);
+                .flatten()
+                .for_each(|x| *x /= 2);
+        }
+
+        for idx in Self::continuation_indices(board) {
+            let table = &mut self.continuation_history[idx];
+            let score = &mut table[pc][m.to_sq()];
+            *score += bonus;
+
+            if score.abs() >= -Self::HISTORY_MOVE_OFFSET {
+                table.iter_mut().flatten().for_each(|x| *x /= 2);
+            }
+        }
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Captures that caused a cutoff get a bonus, and the captures tried
+    // before any cutoff get a malus of the same size.
+    ///////////////////////////////////////////////////////////////////
+    pub fn add_capture_history(&mut self, board: &Board, m: Move, depth: Depth) {
+        self.update_capture_history(board, m, (depth as Value) * (depth as Value));
+    }
+
+    pub fn penalize_capture(&mut self, board: &Board, m: Move, depth: Depth) {
+        self.update_capture_history(board, m, -(depth as Value) * (depth as Value));
+    }
+
+    fn update_capture_history(&mut self, board: &Board, m: Move, bonus: Value) {
+        let Some(captured_pt) = board.piece_type_at(m.to_sq()) else {
+            return;
+        };
+        let pt = Self::moved_piece(board, m).type_of();
+        let score = &mut self.capture_history[pt][m.to_sq()][captured_pt];
+        *score += bonus;
+
+        if score.abs() >= Self::CAPTURE_HISTORY_MAX {
+            self.capture_history
+                .iter_mut()
+                .flatten()
+                .flatten()
+                .for_each(|x| *x /= 2);
         }
     }
 
+    fn capture_history_score(&self, board: &Board, m: Move) -> Value {
+        board.piece_type_at(m.to_sq()).map_or(0, |captured_pt| {
+            self.capture_history[Self::moved_piece(board, m).type_of()][m.to_sq()][captured_pt]
+        })
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Continuation history follows up on the opponent's last move and
+    // on our own move before that.
+    ///////////////////////////////////////////////////////////////////
+    fn continuation_indices(board: &Board) -> impl Iterator<Item = usize> + '_ {
+        (0..Self::N_CONTINUATIONS)
+            .map_while(|n| board.peek_nth(n))
+            .map(|(pc, m)| pc.index() * SQ::N_SQUARES + m.to_sq().index())
+    }
+
+    fn continuation_score(&self, board: &Board, m: Move) -> Value {
+        let pc = Self::moved_piece(board, m);
+        Self::continuation_indices(board)
+            .map(|idx| self.continuation_history[idx][pc][m.to_sq()])
+            .sum()
+    }
+
+    pub fn killers(&self, board: &Board, ply: Ply) -> [Option<Move>; Self::N_KILLERS] {
+        self.killer_moves[board.ctm()][ply]
+    }
+
     fn is_killer(&self, board: &Board, m: Move, ply: usize) -> bool {
         self.killer_moves[board.ctm()][ply].contains(&Some(m))
     }
 
-    fn history_score(&self, m: Move) -> Value {
-        self.history_scores[m.from_sq()][m.to_sq()]
+    pub fn history_score(&self, board: &Board, m: Move) -> Value {
+        let pc = Self::moved_piece(board, m);
+        self.history_scores[pc.color_of()][pc.type_of()][m.to_sq()]
+    }
+
+    fn moved_piece(board: &Board, m: Move) -> Piece {
+        board
+            .piece_at(m.from_sq())
+            .expect("No piece on the from square of a history move.")
     }
 
     pub fn see(board: &Board, m: Move) -> bool {
+        Self::see_ge(board, m, 0)
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Whether the exchange started by m on its target square wins at
+    // least threshold for the side making it. Promotions always pass.
+    ///////////////////////////////////////////////////////////////////
+    pub fn see_ge(board: &Board, m: Move, threshold: Value) -> bool {
         if m.promotion().is_some() {
             return true;
         }
@@ -122,11 +242,17 @@ impl MoveSorter {
         let from_sq = m.from_sq();
         let to_sq = m.to_sq();
 
-        let Some(captured_pt) = board.piece_type_at(to_sq) else {
-            return false;
+        let mut occ = board.all_pieces() ^ from_sq.bb();
+        let captured_value = if m.is_ep() {
+            occ ^= (to_sq + Direction::South.relative(board.ctm())).bb();
+            Self::SEE_PIECE_TYPE[PieceType::Pawn]
+        } else {
+            board
+                .piece_type_at(to_sq)
+                .map_or(0, |captured_pt| Self::SEE_PIECE_TYPE[captured_pt])
         };
 
-        let mut value = Self::SEE_PIECE_TYPE[captured_pt];
+        let mut value = captured_value - threshold;
 
         if value < 0 {
             return false;
@@ -142,7 +268,6 @@ impl MoveSorter {
             return true;
         }
 
-        let mut occ = board.all_pieces() ^ from_sq.bb();
         let mut attackers = board.attackers(to_sq, occ);
 
         let diagonal_sliders = board.diagonal_sliders();
@@ -199,7 +324,8 @@ impl MoveSorter {
 }
 
 impl MoveSorter {
-    const N_KILLERS: usize = 3;
+    pub const N_KILLERS: usize = 3;
+    const N_CONTINUATIONS: usize = 2;
     const HASH_MOVE_SCORE: Value = 25000;
     const QUEEN_PROMOTION_SCORE: Value = 8000;
     const ROOK_PROMOTION_SCORE: Value = 7000;
@@ -209,6 +335,11 @@ impl MoveSorter {
     const KILLER_MOVE_SCORE: Value = 2;
     const CASTLING_SCORE: Value = 1;
     const HISTORY_MOVE_OFFSET: Value = -30000;
+    const HISTORY_BONUS_MAX: Value = 1600;
+    const CAPTURE_HISTORY_MAX: Value = 30000;
+    // Keeps the capture history within a pawn's worth of MVV-LVA, so it
+    // reorders captures without changing which side of zero they're on.
+    const CAPTURE_HISTORY_DIVIDER: Value = 320;
     const LOSING_CAPTURES_OFFSET: Value = -30001;
 
     const SEE_PIECE_TYPE: PieceTypeMap<Value> =
@@ -224,3 +355,242 @@ impl MoveSorter {
         605, 604, 603, 602, 601, 600
     ];
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::bitboard::*;
+    use crate::magics::*;
+    use crate::move_sorter::*;
+
+    #[test]
+    fn losing_captures_score_below_zero() {
+        init_magics();
+        init_bb();
+
+        let move_sorter = MoveSorter::new();
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1",
+            "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+        ] {
+            let board = Board::try_from(fen).unwrap();
+            let mut moves = MoveList::from_q(&board);
+            move_sorter.score_moves(&mut moves, &board, 0, None);
+
+            ///////////////////////////////////////////////////////////////////
+            // q_search stops at the first negative score instead of running
+            // SEE again, so the sign has to agree with it.
+            ///////////////////////////////////////////////////////////////////
+            for entry in (0..moves.len()).map(|i| &moves[i]) {
+                if entry.m.is_capture() && !entry.m.is_ep() {
+                    assert_eq!(
+                        entry.score >= 0,
+                        MoveSorter::see(&board, entry.m),
+                        "{} {}",
+                        fen,
+                        entry.m
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn continuation_history() {
+        init_magics();
+        init_bb();
+
+        let mut board = Board::new();
+        let mut move_sorter = MoveSorter::new();
+        let score = |move_sorter: &MoveSorter, board: &Board, move_str: &str| {
+            let m = board.parse_move(move_str).unwrap();
+            move_sorter.score_move(m, board, 0, None)
+        };
+
+        // Nf6 after e4 only.
+        board.push_str("e2e4").unwrap();
+        let m = board.parse_move("g8f6").unwrap();
+        move_sorter.apply_history(&board, m, [], 4);
+        let after_e4 = score(&move_sorter, &board, "g8f6");
+        assert!(after_e4 > score(&move_sorter, &board, "b8c6"));
+        board.pop();
+
+        board.push_str("d2d4").unwrap();
+        assert!(score(&move_sorter, &board, "g8f6") < after_e4);
+        board.pop();
+
+        // Nothing to follow up on after a null move.
+        board.push_null();
+        let m = board.parse_move("g8f6").unwrap();
+        assert_eq!(move_sorter.continuation_score(&board, m), 0);
+    }
+
+    #[test]
+    fn history_malus() {
+        init_magics();
+        init_bb();
+
+        let board = Board::new();
+        let mut move_sorter = MoveSorter::new();
+        let score = |move_sorter: &MoveSorter, move_str: &str| {
+            let m = board.parse_move(move_str).unwrap();
+            move_sorter.score_move(m, &board, 0, None)
+        };
+        let untouched = score(&move_sorter, "d2d4");
+
+        // a3 and h3 were searched before e4 cut off.
+        let quiets = ["a2a3", "h2h3"].map(|move_str| board.parse_move(move_str).unwrap());
+        let m = board.parse_move("e2e4").unwrap();
+        move_sorter.apply_history(&board, m, quiets, 8);
+        assert!(score(&move_sorter, "e2e4") > untouched);
+        assert!(score(&move_sorter, "a2a3") < untouched);
+        assert!(score(&move_sorter, "h2h3") < untouched);
+        assert_eq!(score(&move_sorter, "d2d4"), untouched);
+
+        // The bonus is capped however deep the search went.
+        let before = score(&move_sorter, "d2d4");
+        let m = board.parse_move("d2d4").unwrap();
+        move_sorter.apply_history(&board, m, [], 100);
+        assert_eq!(
+            score(&move_sorter, "d2d4") - before,
+            MoveSorter::HISTORY_BONUS_MAX
+        );
+    }
+
+    #[test]
+    fn capture_history() {
+        init_magics();
+        init_bb();
+
+        let board = Board::try_from("4k3/8/8/3p4/4P3/2N5/8/4K3 w - - 0 1").unwrap();
+        let pawn_takes = board.parse_move("e4d5").unwrap();
+        let knight_takes = board.parse_move("c3d5").unwrap();
+
+        let mut move_sorter = MoveSorter::new();
+        let score = |move_sorter: &MoveSorter, m| move_sorter.score_move(m, &board, 0, None);
+        assert!(score(&move_sorter, pawn_takes) > score(&move_sorter, knight_takes));
+
+        for _ in 0..4 {
+            move_sorter.add_capture_history(&board, knight_takes, 20);
+            move_sorter.penalize_capture(&board, pawn_takes, 20);
+        }
+        assert!(score(&move_sorter, pawn_takes) < score(&move_sorter, knight_takes));
+
+        // However bad its history, a winning capture stays a winning capture.
+        for _ in 0..1000 {
+            move_sorter.penalize_capture(&board, pawn_takes, 20);
+        }
+        assert!(score(&move_sorter, pawn_takes) >= 0);
+    }
+
+    #[test]
+    fn see_thresholds() {
+        init_magics();
+        init_bb();
+
+        ///////////////////////////////////////////////////////////////////
+        // Positions from the usual SEE test suites, with the value of the
+        // exchange worked out for our piece values.
+        ///////////////////////////////////////////////////////////////////
+        for (fen, move_str, value) in [
+            (
+                "1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1",
+                "e1e5",
+                100,
+            ),
+            (
+                "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+                "d3e5",
+                -275,
+            ),
+            (
+                "4R3/2r3p1/5bk1/1p1r3p/p2PR1P1/P1BK1P2/1P6/8 b - - 0 1",
+                "h5g4",
+                0,
+            ),
+            (
+                "4R3/2r3p1/5bk1/1p1r1p1p/p2PR1P1/P1BK1P2/1P6/8 b - - 0 1",
+                "h5g4",
+                0,
+            ),
+            (
+                "4r1k1/5pp1/nbp4p/1p2p2q/1P2P1b1/1BP2N1P/1B2QPPK/3R4 b - - 0 1",
+                "g4f3",
+                0,
+            ),
+            (
+                "2r1r1k1/pp1bppbp/3p1np1/q3P3/2P2P2/1P2B3/P1N1B1PP/2RQ1RK1 b - - 0 1",
+                "d6e5",
+                100,
+            ),
+            (
+                "7r/5qpk/p1Qp1b1p/3r3n/BB3p2/5p2/P1P2P2/4RK1R w - - 0 1",
+                "e1e8",
+                0,
+            ),
+            (
+                "6rr/6pk/p1Qp1b1p/2n5/1B3p2/5p2/P1P2P2/4RK1R w - - 0 1",
+                "e1e8",
+                -500,
+            ),
+            (
+                "7r/5qpk/2Qp1b1p/1N1r3n/BB3p2/5p2/P1P2P2/4RK1R w - - 0 1",
+                "e1e8",
+                -500,
+            ),
+            (
+                "6k1/1pp4p/p1pb4/6q1/3P1pRr/2P4P/PP1Br1P1/5RKN w - - 0 1",
+                "f1f4",
+                -25,
+            ),
+            (
+                "5rk1/1pp2q1p/p1pb4/8/3P1NP1/2P5/1P1BQ1P1/5RK1 b - - 0 1",
+                "d6f4",
+                0,
+            ),
+            (
+                "4k3/5pp1/1p1p4/2pPq3/P1P5/5Q2/6PP/5RK1 w - - 0 1",
+                "f3f7",
+                100,
+            ),
+            (
+                "3r2k1/pp3p1p/2q2pp1/8/3Q4/2P5/P4PPP/3R2K1 w - - 0 1",
+                "d4d8",
+                500,
+            ),
+            (
+                "1nbkq2r/pppp1ppp/4p3/7Q/8/1B6/PPPP1PPP/RNB1K2R w KQ - 0 1",
+                "h5f7",
+                -925,
+            ),
+            ("8/8/8/3pk3/4P3/3K4/8/8 w - - 0 1", "e4d5", 0),
+            ("8/8/3k4/3p4/4P3/3K4/8/8 w - - 0 1", "e4d5", 0),
+            ("8/8/8/3pk3/4P3/8/8/3RK3 w - - 0 1", "e4d5", 100),
+            (
+                "2r2r1k/6bp/p7/2q2p1Q/3PpP2/1B6/P5PP/2RR3K b - - 0 1",
+                "c5c1",
+                -25,
+            ),
+            (
+                "r2qk1nr/pp2ppbp/2b3p1/2p1p3/8/2N2N2/PPPP1PPP/R1BQR1K1 w kq - 0 1",
+                "f3e5",
+                100,
+            ),
+            (
+                "2r4r/1P4pk/p2p1b1p/7n/BB3p2/2R2p2/P1P2P2/4RK2 w - - 0 1",
+                "c3c8",
+                500,
+            ),
+        ] {
+            let board = Board::try_from(fen).unwrap();
+            let m = board.parse_move(move_str).unwrap();
+
+            assert!(MoveSorter::see_ge(&board, m, value), "{} {}", fen, m);
+            assert!(MoveSorter::see_ge(&board, m, value - 100), "{} {}", fen, m);
+            assert!(!MoveSorter::see_ge(&board, m, value + 1), "{} {}", fen, m);
+            assert_eq!(MoveSorter::see(&board, m), value >= 0, "{} {}", fen, m);
+        }
+    }
+}
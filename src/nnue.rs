@@ -13,6 +13,18 @@ impl Layer {
     pub fn new(weights: &'static [i16], biases: &'static [i16]) -> Self {
         Self { weights, biases }
     }
+
+    fn feature_weights(&self, feature_idx: usize) -> &'static [i16] {
+        let size = self.biases.len();
+        &self.weights[feature_idx * size..(feature_idx + 1) * size]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Accumulator {
+    activations: [i16; INPUT_LAYER_BIAS.len()],
+    psqt_value: i16,
+    pop_count: i16,
 }
 
 #[derive(Clone)]
@@ -20,9 +32,9 @@ pub struct Network {
     input_layer: Layer,
     hidden_layers: [Layer; 8],
     psqt_layer: Layer,
-    psqt_value: i16,
-    accumulator: [i16; INPUT_LAYER_BIAS.len()],
-    pop_count: i16,
+    accumulator: Accumulator,
+    // One saved accumulator per move made, so unmaking one is a copy.
+    stack: Vec<Accumulator>,
 }
 
 impl Network {
@@ -40,15 +52,59 @@ impl Network {
                 Layer::new(&HIDDEN_LAYER_7_WEIGHT, &HIDDEN_LAYER_7_BIAS),
             ],
             psqt_layer: Layer::new(&PSQT_LAYER_WEIGHT, &[]),
-            accumulator: INPUT_LAYER_BIAS,
-            psqt_value: 0,
-            pop_count: 0,
+            accumulator: Accumulator {
+                activations: INPUT_LAYER_BIAS,
+                psqt_value: 0,
+                pop_count: 0,
+            },
+            stack: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self) {
+        self.stack.push(self.accumulator);
+    }
+
+    pub fn pop(&mut self) {
+        self.accumulator = self
+            .stack
+            .pop()
+            .expect("Tried to restore an accumulator that was never saved.");
+    }
+
+    // The oldest moves can't be unmade anymore once the board drops them.
+    pub fn drop_oldest(&mut self, n: usize) {
+        self.stack.drain(..n);
+    }
+
+    pub fn matches_pieces(&self, pieces: impl IntoIterator<Item = (Piece, SQ)>) -> bool {
+        let mut fresh = Self::new();
+        for (pc, sq) in pieces {
+            fresh.activate(pc, sq);
         }
+        fresh.accumulator == self.accumulator
     }
 
     pub fn move_piece(&mut self, piece: Piece, from_sq: SQ, to_sq: SQ) {
-        self.deactivate(piece, from_sq);
-        self.activate(piece, to_sq);
+        ///////////////////////////////////////////////////////////////////
+        // Moving a piece doesn't change the piece count, so the two
+        // feature updates are fused into one pass over the accumulator.
+        ///////////////////////////////////////////////////////////////////
+        let from_idx = Self::feature_idx(piece, from_sq);
+        let to_idx = Self::feature_idx(piece, to_sq);
+
+        for ((activation, &from_weight), &to_weight) in self
+            .accumulator
+            .activations
+            .iter_mut()
+            .zip(self.input_layer.feature_weights(from_idx))
+            .zip(self.input_layer.feature_weights(to_idx))
+        {
+            *activation += to_weight - from_weight;
+        }
+
+        self.accumulator.psqt_value +=
+            self.psqt_layer.weights[to_idx] - self.psqt_layer.weights[from_idx];
     }
 
     pub fn activate(&mut self, piece: Piece, sq: SQ) {
@@ -65,39 +121,54 @@ impl Network {
         sq: SQ,
         mut update_fn: impl FnMut(&mut i16, &i16),
     ) {
-        let feature_idx: usize = piece.index() * SQ::N_SQUARES + sq.index();
-        let accumulator_idx = feature_idx * self.input_layer.biases.len();
-        let weights = self.input_layer.weights
-            [accumulator_idx..accumulator_idx + self.input_layer.biases.len()]
-            .iter();
+        let feature_idx = Self::feature_idx(piece, sq);
 
         self.accumulator
+            .activations
             .iter_mut()
-            .zip(weights)
+            .zip(self.input_layer.feature_weights(feature_idx))
             .for_each(|(activation, weight)| update_fn(activation, weight));
 
-        update_fn(&mut self.psqt_value, &self.psqt_layer.weights[feature_idx]);
-        update_fn(&mut self.pop_count, &1);
+        update_fn(
+            &mut self.accumulator.psqt_value,
+            &self.psqt_layer.weights[feature_idx],
+        );
+        update_fn(&mut self.accumulator.pop_count, &1);
     }
 
     pub fn eval(&self) -> Value {
-        let bucket = (self.pop_count as usize - 1) / 4;
+        self.bucket_eval(self.bucket())
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Each output layer covers four piece counts, from 1-4 pieces in
+    // the first up to 29-32 in the last.
+    ///////////////////////////////////////////////////////////////////
+    fn bucket(&self) -> usize {
+        (self.accumulator.pop_count as usize - 1) / 4
+    }
 
+    fn bucket_eval(&self, bucket: usize) -> Value {
         let hidden_layer = &self.hidden_layers[bucket];
         let output = self
             .accumulator
+            .activations
             .iter()
             .zip(hidden_layer.weights)
             .map(|(&activation, &weight)| Self::clipped_relu(activation) * Value::from(weight))
             .sum::<Value>();
 
         (Value::from(hidden_layer.biases[0])
-            + Value::from(self.psqt_value)
+            + Value::from(self.accumulator.psqt_value)
             + output / Self::INPUT_SCALE)
             * Self::NNUE2SCORE
             / Self::HIDDEN_SCALE
     }
 
+    fn feature_idx(piece: Piece, sq: SQ) -> usize {
+        piece.index() * SQ::N_SQUARES + sq.index()
+    }
+
     fn clipped_relu(x: i16) -> Value {
         Value::from(x).clamp(0, Self::INPUT_SCALE)
     }
@@ -108,3 +179,97 @@ impl Network {
     const HIDDEN_SCALE: Value = 64;
     const NNUE2SCORE: Value = 400;
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::nnue::*;
+
+    #[test]
+    fn move_piece_matches_separate_updates() {
+        let mut fused = Network::new();
+        for (pc, sq) in [
+            (Piece::WhiteKing, SQ::E1),
+            (Piece::BlackKing, SQ::E8),
+            (Piece::WhiteQueen, SQ::D1),
+            (Piece::BlackRook, SQ::A8),
+        ] {
+            fused.activate(pc, sq);
+        }
+        let mut separate = fused.clone();
+
+        for (pc, from_sq, to_sq) in [
+            (Piece::WhiteQueen, SQ::D1, SQ::H5),
+            (Piece::BlackRook, SQ::A8, SQ::A1),
+            (Piece::WhiteKing, SQ::E1, SQ::F2),
+        ] {
+            fused.move_piece(pc, from_sq, to_sq);
+            separate.deactivate(pc, from_sq);
+            separate.activate(pc, to_sq);
+
+            assert_eq!(fused.accumulator, separate.accumulator);
+            assert_eq!(fused.eval(), separate.eval());
+        }
+    }
+
+    #[test]
+    fn buckets_agree_at_boundaries() {
+        ///////////////////////////////////////////////////////////////////
+        // Capturing across a bucket boundary swaps the output layer as
+        // well as removing a piece. Both buckets should give about the
+        // same eval for positions right at the boundary.
+        ///////////////////////////////////////////////////////////////////
+        let mut network = Network::new();
+        for (pc, sq) in [
+            (Piece::WhiteKing, SQ::G1),
+            (Piece::BlackKing, SQ::G8),
+            (Piece::WhiteRook, SQ::A1),
+            (Piece::BlackRook, SQ::A8),
+            (Piece::WhiteKnight, SQ::C3),
+            (Piece::BlackKnight, SQ::C6),
+            (Piece::WhiteBishop, SQ::C1),
+            (Piece::BlackBishop, SQ::C8),
+            (Piece::WhitePawn, SQ::F2),
+            (Piece::BlackPawn, SQ::F7),
+            (Piece::WhitePawn, SQ::G2),
+            (Piece::BlackPawn, SQ::G7),
+            (Piece::WhitePawn, SQ::H2),
+            (Piece::BlackPawn, SQ::H7),
+            (Piece::WhitePawn, SQ::E4),
+            (Piece::BlackPawn, SQ::E5),
+        ] {
+            network.activate(pc, sq);
+
+            let bucket = network.bucket();
+            if network.accumulator.pop_count % 4 == 0 && bucket + 1 < network.hidden_layers.len() {
+                let (this, next) = (network.bucket_eval(bucket), network.bucket_eval(bucket + 1));
+                assert!((this - next).abs() < 200, "{} {}", this, next);
+            }
+        }
+    }
+
+    #[test]
+    fn pop_restores_accumulator() {
+        let pieces = [
+            (Piece::WhiteKing, SQ::E1),
+            (Piece::BlackKing, SQ::E8),
+            (Piece::WhiteKnight, SQ::G1),
+        ];
+        let mut network = Network::new();
+        for (pc, sq) in pieces {
+            network.activate(pc, sq);
+        }
+        let before = network.accumulator;
+
+        network.push();
+        network.move_piece(Piece::WhiteKnight, SQ::G1, SQ::F3);
+        network.push();
+        network.deactivate(Piece::WhiteKnight, SQ::F3);
+        assert!(!network.matches_pieces(pieces));
+
+        network.pop();
+        network.pop();
+        assert_eq!(network.accumulator, before);
+        assert!(network.matches_pieces(pieces));
+        assert!(network.stack.is_empty());
+    }
+}
@@ -1,14 +1,19 @@
+use std::mem::size_of;
 use std::time::Instant;
 
 use super::board::*;
 use super::move_list::*;
 use super::types::*;
 
-fn perft(board: &mut Board, depth: Depth) -> u128 {
+pub fn perft(board: &mut Board, depth: Depth) -> u64 {
+    if depth <= 0 {
+        return 1;
+    }
+
     let moves: MoveList = MoveList::from(board);
 
     if depth == 1 {
-        return moves.len() as u128;
+        return moves.len() as u64;
     }
 
     let mut nodes = 0;
@@ -21,29 +26,101 @@ fn perft(board: &mut Board, depth: Depth) -> u128 {
     nodes
 }
 
-pub fn print_perft(board: &mut Board, depth: Depth) -> u128 {
-    let now = Instant::now();
+///////////////////////////////////////////////////////////////////
+// Node counts of positions we've already counted, keyed by hash and
+// depth. Positions are found by probing a few slots from where their
+// hash points, and the shallowest of those is replaced when full.
+///////////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Default)]
+struct PerftEntry {
+    hash: Hash,
+    depth: Depth,
+    nodes: u64,
+}
+
+pub struct PerftTable {
+    entries: Vec<PerftEntry>,
+}
+
+impl PerftTable {
+    const PROBES: usize = 4;
+
+    pub fn new(mb_size: usize) -> Self {
+        let count = (mb_size * 1024 * 1024 / size_of::<PerftEntry>() + 1).next_power_of_two() / 2;
+        Self {
+            entries: vec![PerftEntry::default(); count.max(Self::PROBES)],
+        }
+    }
+
+    fn slots(&self, hash: Hash) -> impl Iterator<Item = usize> {
+        let mask = self.entries.len() - 1;
+        (0..Self::PROBES).map(move |i| (hash as usize).wrapping_add(i) & mask)
+    }
+
+    fn probe(&self, hash: Hash, depth: Depth) -> Option<u64> {
+        self.slots(hash)
+            .map(|idx| self.entries[idx])
+            .find(|entry| entry.nodes > 0 && entry.hash == hash && entry.depth == depth)
+            .map(|entry| entry.nodes)
+    }
+
+    fn insert(&mut self, hash: Hash, depth: Depth, nodes: u64) {
+        let idx = self
+            .slots(hash)
+            .min_by_key(|&idx| {
+                let entry = self.entries[idx];
+                if entry.hash == hash && entry.depth == depth {
+                    Depth::MIN
+                } else {
+                    entry.depth
+                }
+            })
+            .expect("There is always at least one slot to probe.");
+        self.entries[idx] = PerftEntry { hash, depth, nodes };
+    }
+}
+
+pub const PERFT_HASH_MB: usize = 32;
+
+// Each call gets a fresh table.
+pub fn perft_hashed(board: &mut Board, depth: Depth) -> u64 {
+    perft_with_table(board, depth, &mut PerftTable::new(PERFT_HASH_MB))
+}
+
+fn perft_with_table(board: &mut Board, depth: Depth, table: &mut PerftTable) -> u64 {
+    if depth <= 0 {
+        return 1;
+    }
 
     let moves: MoveList = MoveList::from(board);
+
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    if let Some(nodes) = table.probe(board.hash(), depth) {
+        return nodes;
+    }
+
     let mut nodes = 0;
-    let hash = board.hash();
-    let material_hash = board.material_hash();
+
     for m in moves.iter_moves() {
-        print!("{}: ", m);
-        let move_nodes;
-        if depth <= 1 {
-            move_nodes = 1;
-        } else {
-            board.push(m);
-            move_nodes = perft(board, depth - 1);
-            board.pop();
-        }
-        nodes += move_nodes;
-        println!("{}", move_nodes);
+        board.push(m);
+        nodes += perft_with_table(board, depth - 1, table);
+        board.pop();
     }
+    table.insert(board.hash(), depth, nodes);
+    nodes
+}
 
-    assert_eq!(board.hash(), hash);
-    assert_eq!(board.material_hash(), material_hash);
+///////////////////////////////////////////////////////////////////
+// Prints the nodes under each root move, so a wrong count can be
+// tracked down by comparing against another move generator.
+///////////////////////////////////////////////////////////////////
+pub fn print_perft(board: &mut Board, depth: Depth, hashed: bool) -> u64 {
+    let now = Instant::now();
+
+    let nodes = divide(board, depth, hashed);
 
     let elapsed = now.elapsed().as_secs_f32();
     println!();
@@ -58,6 +135,41 @@ pub fn print_perft(board: &mut Board, depth: Depth) -> u128 {
     nodes
 }
 
+///////////////////////////////////////////////////////////////////
+// The same breakdown followed by the total the way Stockfish prints
+// it, so the two outputs can be diffed line for line.
+///////////////////////////////////////////////////////////////////
+pub fn print_divide(board: &mut Board, depth: Depth, hashed: bool) -> u64 {
+    let nodes = divide(board, depth, hashed);
+    println!();
+    println!("Nodes searched: {}", nodes);
+    println!();
+    nodes
+}
+
+fn divide(board: &mut Board, depth: Depth, hashed: bool) -> u64 {
+    let moves: MoveList = MoveList::from(board);
+    let mut nodes = 0;
+    let hash = board.hash();
+    let material_hash = board.material_hash();
+    let mut table = hashed.then(|| PerftTable::new(PERFT_HASH_MB));
+    for m in moves.iter_moves() {
+        print!("{}: ", m);
+        board.push(m);
+        let move_nodes = match table.as_mut() {
+            Some(table) => perft_with_table(board, depth - 1, table),
+            None => perft(board, depth - 1),
+        };
+        board.pop();
+        nodes += move_nodes;
+        println!("{}", move_nodes);
+    }
+
+    assert_eq!(board.hash(), hash);
+    assert_eq!(board.material_hash(), material_hash);
+    nodes
+}
+
 #[cfg(test)]
 mod tests {
     use crate::bitboard::init_bb;
@@ -66,6 +178,109 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn perft_shallow() {
+        init_magics();
+        init_bb();
+
+        let mut board = Board::new();
+        for (depth, nodes) in [(0, 1), (1, 20), (2, 400), (3, 8902), (4, 197281)] {
+            assert_eq!(perft(&mut board, depth), nodes);
+        }
+
+        let mut board =
+            Board::try_from("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        for (depth, nodes) in [(1, 48), (2, 2039), (3, 97862)] {
+            assert_eq!(perft(&mut board, depth), nodes);
+        }
+        assert_eq!(print_perft(&mut board, 2, false), 2039);
+        assert_eq!(print_perft(&mut board, 3, true), 97862);
+        assert_eq!(print_divide(&mut board, 2, false), 2039);
+        assert_eq!(print_divide(&mut board, 3, true), 97862);
+    }
+
+    #[test]
+    fn perft_hashed_matches() {
+        init_magics();
+        init_bb();
+
+        for (fen, depth, nodes) in [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                5,
+                4865609,
+            ),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                4,
+                4085603,
+            ),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 5, 674624),
+            (
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                4,
+                422333,
+            ),
+            (
+                "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+                4,
+                2103487,
+            ),
+        ] {
+            let mut board = Board::try_from(fen).unwrap();
+            assert_eq!(perft_hashed(&mut board, depth), nodes, "{}", fen);
+            assert_eq!(board.to_string(), Board::try_from(fen).unwrap().to_string());
+        }
+
+        // Small enough that entries get replaced.
+        let mut board = Board::new();
+        assert_eq!(
+            perft_with_table(&mut board, 5, &mut PerftTable::new(0)),
+            4865609
+        );
+    }
+
+    #[test]
+    fn perft_chess960() {
+        init_magics();
+        init_bb();
+
+        for (fen, counts) in [
+            (
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+                [21, 528, 12189, 326672],
+            ),
+            (
+                "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+                [21, 807, 18002, 667366],
+            ),
+            (
+                "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GEge - 3 9",
+                [20, 479, 10471, 273318],
+            ),
+            (
+                "qbbnnrkr/2pp2pp/p7/1p2pp2/8/P3PP2/1PPP1KPP/QBBNNR1R w hf - 0 9",
+                [22, 593, 13440, 382958],
+            ),
+            (
+                "1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9",
+                [28, 1120, 31058, 1171749],
+            ),
+            (
+                "qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9",
+                [29, 899, 26578, 824055],
+            ),
+        ] {
+            let mut board = Board::default();
+            board.set_chess960(true);
+            board.set_fen(fen).unwrap();
+            for (depth, nodes) in (1..).zip(counts) {
+                assert_eq!(perft(&mut board, depth), nodes, "{} {}", fen, depth);
+            }
+        }
+    }
+
     #[test]
     fn test_perft() {
         init_magics();
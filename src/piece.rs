@@ -53,6 +53,7 @@ impl Piece {
 
 impl From<u8> for Piece {
     fn from(n: u8) -> Self {
+        debug_assert!((n as usize) < Self::PIECE_STR.len() && !matches!(n, 0b0110 | 0b0111));
         unsafe { std::mem::transmute::<u8, Self>(n) }
     }
 }
@@ -63,7 +64,7 @@ impl TryFrom<char> for Piece {
     fn try_from(value: char) -> Result<Self, Self::Error> {
         Self::PIECE_STR
             .chars()
-            .position(|c| c == value)
+            .position(|c| c == value && c != ' ')
             .map(|x| Self::from(x as u8))
             .ok_or("Piece symbols should be one of \"KQRBNPkqrbnp\"")
     }
@@ -115,6 +116,7 @@ impl PieceType {
 
 impl From<u8> for PieceType {
     fn from(n: u8) -> Self {
+        debug_assert!((n as usize) < Self::N_PIECE_TYPES);
         unsafe { std::mem::transmute::<u8, Self>(n) }
     }
 }
@@ -165,6 +167,7 @@ impl Color {
 
 impl From<u8> for Color {
     fn from(n: u8) -> Self {
+        debug_assert!((n as usize) < Self::N_COLORS);
         unsafe { std::mem::transmute::<u8, Self>(n) }
     }
 }
@@ -211,3 +214,32 @@ impl TryFrom<char> for Color {
 impl Color {
     pub const N_COLORS: usize = 2;
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::piece::*;
+
+    #[test]
+    fn piece_symbols() {
+        for pc in Piece::iter(Piece::WhitePawn, Piece::BlackKing) {
+            let symbol = pc.to_string().chars().next().unwrap();
+            assert_eq!(Piece::try_from(symbol), Ok(pc));
+            assert_eq!(Piece::make_piece(pc.color_of(), pc.type_of()), pc);
+            assert_eq!(
+                pc.type_of().to_string(),
+                symbol.to_ascii_lowercase().to_string()
+            );
+            assert_eq!(symbol.is_ascii_uppercase(), pc.color_of() == Color::White);
+        }
+
+        for pt in PieceType::iter(PieceType::Pawn, PieceType::King) {
+            let white = Piece::make_piece(Color::White, pt);
+            let black = Piece::make_piece(Color::Black, pt);
+            assert_eq!(white.flip(), black);
+            assert_eq!(white.to_string().to_ascii_lowercase(), black.to_string());
+        }
+
+        assert!(Piece::try_from('x').is_err());
+        assert!(Piece::try_from(' ').is_err());
+    }
+}
@@ -1,43 +1,167 @@
+use std::io::{self, Write};
 use std::time::Duration;
 
 use super::board::*;
 use super::moov::*;
 use super::move_list::*;
+use super::move_picker::*;
 use super::move_sorter::*;
+use super::piece::*;
+use super::rng::*;
 use super::timer::*;
 use super::tt::*;
 use super::types::*;
 
+#[derive(Clone, Copy, Debug)]
+pub struct SearchOptions {
+    pub san_pv: bool,
+    pub analyse_mode: bool,
+    pub show_wdl: bool,
+    pub root_randomization: Value,
+    pub limit_strength: bool,
+    pub elo: u32,
+    pub contempt: Value,
+    pub params: SearchParams,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            san_pv: false,
+            analyse_mode: false,
+            show_wdl: false,
+            root_randomization: 0,
+            limit_strength: false,
+            elo: Search::DEFAULT_ELO,
+            contempt: 0,
+            params: SearchParams::default(),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////
+// The search constants worth tuning, exposed as UCI options. Every
+// thread gets its own copy along with the rest of the options.
+///////////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchParams {
+    pub aspiration_window: Value,
+    pub rfp_margin_multiplier: Value,
+    pub null_depth_divider: Depth,
+    pub lmr_base_reduction: f32,
+    pub lmr_move_divider: f32,
+    pub lmr_pv_reduction: Depth,
+    pub lmr_improving_reduction: Depth,
+    pub lmr_history_divider: Value,
+    pub sing_extend_depth_margin: Depth,
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        Self {
+            aspiration_window: Search::ASPIRATION_WINDOW,
+            rfp_margin_multiplier: Search::RFP_MARGIN_MULTIPLIER,
+            null_depth_divider: Search::NULL_DEPTH_DIVIDER,
+            lmr_base_reduction: Search::LMR_BASE_REDUCTION,
+            lmr_move_divider: Search::LMR_MOVE_DIVIDER,
+            lmr_pv_reduction: Search::LMR_PV_REDUCTION,
+            lmr_improving_reduction: Search::LMR_IMPROVING_REDUCTION,
+            lmr_history_divider: Search::LMR_HISTORY_DIVIDER,
+            sing_extend_depth_margin: Search::SING_EXTEND_DEPTH_MARGIN,
+        }
+    }
+}
+
 pub struct Search<'a> {
     id: u16,
+    options: SearchOptions,
+    lmr_table: [[Depth; 64]; 64],
+    lmp_table: [[usize; 64]; 2],
     sel_depth: Ply,
+    root_depth: Depth,
     timer: Timer,
     tt: &'a TT,
     move_sorter: MoveSorter,
     excluded_moves: [Option<Move>; MAX_MOVES],
+    // How many double extensions the line leading to each ply took.
+    double_extensions: [u8; MAX_MOVES],
+    static_evals: [Option<Value>; MAX_MOVES],
+    // Indexed by the pawn hash, then the side to move.
+    correction_history: Box<[ColorMap<Value>]>,
+    current_line: [Option<Move>; MAX_MOVES],
+    current_line_len: usize,
+    currline_counter: u64,
+    rng: Rng,
+    root_candidates: Vec<(Move, Value)>,
+    // The root moves to choose from, or all of them when empty.
+    search_moves: Vec<Move>,
+    ply_cap_reached: bool,
+    silent: bool,
 }
 
 impl<'a> Search<'a> {
-    pub fn new(timer: Timer, tt: &'a TT, id: u16) -> Self {
+    pub fn new(timer: Timer, tt: &'a TT, options: SearchOptions, id: u16) -> Self {
         Self {
             id,
+            options,
+            lmr_table: Self::lmr_table(&options.params),
+            lmp_table: Self::lmp_table(),
             timer,
             tt,
             sel_depth: 0,
+            root_depth: 0,
             move_sorter: MoveSorter::new(),
             excluded_moves: [None; MAX_MOVES],
+            double_extensions: [0; MAX_MOVES],
+            static_evals: [None; MAX_MOVES],
+            correction_history: vec![
+                ColorMap::new([0; Color::N_COLORS]);
+                Self::CORRECTION_HISTORY_SIZE
+            ]
+            .into_boxed_slice(),
+            current_line: [None; MAX_MOVES],
+            current_line_len: 0,
+            currline_counter: 0,
+            rng: Rng::new(0),
+            root_candidates: Vec::new(),
+            search_moves: Vec::new(),
+            ply_cap_reached: false,
+            silent: false,
         }
     }
 
-    pub fn go(&mut self, mut board: Board) -> Option<Move> {
+    pub fn with_rng(mut self, rng: Rng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    pub fn with_search_moves(mut self, search_moves: Vec<Move>) -> Self {
+        self.search_moves = search_moves;
+        self
+    }
+
+    pub fn silent(mut self) -> Self {
+        self.silent = true;
+        self
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.timer.nodes()
+    }
+
+    pub fn go(&mut self, mut board: Board) -> (Option<Move>, Value) {
         ///////////////////////////////////////////////////////////////////
         // Start iterative deepening.
         ///////////////////////////////////////////////////////////////////
         let mut alpha = -Self::MATE;
         let mut beta = Self::MATE;
+        let mut delta = self.options.params.aspiration_window;
         let mut best_move = None;
         let mut value = 0;
         let mut depth = 1;
+        let mut stability = 0;
+        let mut last_best_move = None;
+        let mut random_moves = Vec::new();
 
         ///////////////////////////////////////////////////////////////////
         // If there's only one legal move, just play
@@ -46,48 +170,198 @@ impl<'a> Search<'a> {
         let moves = MoveList::from(&board);
 
         if moves.len() == 0 {
-            return None;
+            return (None, if board.in_check() { -Self::MATE } else { 0 });
         }
 
         if moves.len() == 1 {
-            return Some(moves[0].m);
+            if self.id == 0 {
+                self.timer.wait_min_think_time();
+            }
+            return (Some(moves[0].m), value);
         }
 
-        while self.timer.start_check(depth) && !Self::is_checkmate(value) && depth < Depth::MAX {
-            (best_move, value) = self.search_root(&mut board, depth, alpha, beta);
+        while self.timer.start_check(depth, stability)
+            && !self.mate_found(value)
+            && self.strength_allows(depth)
+            && depth < Depth::MAX
+        {
+            self.root_depth = depth;
+            let (root_move, root_value) = self.search_root(&mut board, depth, alpha, beta);
 
             ///////////////////////////////////////////////////////////////////
-            // Update the clock if the score is changing
-            // by a lot.
+            // A stop can cut an iteration short. A move that raised alpha
+            // before that has been searched in full and is at least as good
+            // as the last best move, but the rest of the result is unusable.
             ///////////////////////////////////////////////////////////////////
-            if depth >= Self::SEARCHES_WO_TIMER_UPDATE {
-                self.timer.update(best_move);
+            if self.timer.local_stop() {
+                if root_move.is_some() {
+                    (best_move, value) = (root_move, root_value);
+                }
+                break;
+            }
+
+            value = root_value;
+            best_move = root_move.or(best_move);
+            let bound = Self::score_bound(value, alpha, beta);
+
+            if self.should_print_info(bound) {
+                best_move.inspect(|&m| self.print_info(&mut board, depth, m, value, bound));
             }
 
             ///////////////////////////////////////////////////////////////////
-            // Widen aspiration windows.
+            // Widen aspiration windows. The bound that failed moves away
+            // from the score twice as far each time, until it's far enough
+            // out that we might as well search the full window.
             ///////////////////////////////////////////////////////////////////
-            if value <= alpha {
-                alpha = -Self::MATE;
-            } else if value >= beta {
-                beta = Self::MATE;
+            if bound != Bound::Exact {
+                delta = delta.saturating_mul(2);
+            }
+
+            if bound == Bound::Upper {
+                alpha = Self::widen(value, -delta);
+            } else if bound == Bound::Lower {
+                beta = Self::widen(value, delta);
             } else {
-                // Only print info if we're in the main thread
-                if self.id == 0 && !self.timer.local_stop() {
-                    best_move.inspect(|&m| self.print_info(&mut board, depth, m, value));
+                ///////////////////////////////////////////////////////////////////
+                // Count how many iterations the best move has held, which the
+                // timer uses to spend more or less of its time target.
+                ///////////////////////////////////////////////////////////////////
+                stability = Self::update_stability(stability, last_best_move, best_move);
+                last_best_move = best_move;
+
+                if !self.timer.local_stop() {
+                    random_moves = self.root_moves_within_margin(value);
                 }
-                alpha = value - Self::ASPIRATION_WINDOW;
-                beta = value + Self::ASPIRATION_WINDOW;
+
+                delta = self.options.params.aspiration_window;
+                alpha = value - delta;
+                beta = value + delta;
                 depth += 1;
                 self.sel_depth = 0;
             }
         }
 
         if self.id == 0 {
+            self.timer.wait_min_think_time();
             self.timer.stop();
         }
 
-        best_move
+        // Stopped before any move at depth 1 finished.
+        best_move = best_move.or_else(|| self.root_moves(&board).iter_moves().next());
+
+        ///////////////////////////////////////////////////////////////////
+        // Pick randomly among the moves of the last completed iteration
+        // that score within the randomization margin of the best move.
+        ///////////////////////////////////////////////////////////////////
+        if best_move.is_some_and(|m| random_moves.iter().any(|&(candidate, _)| candidate == m)) {
+            best_move = Some(self.pick_random_move(&random_moves));
+        }
+
+        (best_move, value)
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Root move randomization picks any of the candidates. A limited
+    // strength favours the ones closer to the best move instead, each
+    // weighted by how far inside the margin it scored.
+    ///////////////////////////////////////////////////////////////////
+    fn pick_random_move(&mut self, candidates: &[(Move, Value)]) -> Move {
+        if !self.options.limit_strength {
+            return candidates[self.rng.below(candidates.len() as u64) as usize].0;
+        }
+
+        let margin = self.randomization_margin();
+        let best = candidates.iter().map(|&(_, score)| score).max().unwrap();
+        let weights = candidates
+            .iter()
+            .map(|&(_, score)| (margin - (best - score)).max(1) as u64)
+            .collect::<Vec<u64>>();
+
+        let mut pick = self.rng.below(weights.iter().sum());
+        for (&(m, _), &weight) in candidates.iter().zip(&weights) {
+            if pick < weight {
+                return m;
+            }
+            pick -= weight;
+        }
+        unreachable!()
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // A limited strength searches shallower and fewer nodes the lower
+    // the Elo, on top of whatever the time control allows.
+    ///////////////////////////////////////////////////////////////////
+    fn strength_allows(&self, depth: Depth) -> bool {
+        if !self.options.limit_strength {
+            return true;
+        }
+
+        let steps = self.elo() - Self::MIN_ELO;
+        depth <= 1 + (steps / Self::ELO_PER_DEPTH) as Depth
+            && self.timer.nodes() < Self::MIN_ELO_NODES << (steps / Self::ELO_PER_NODES_DOUBLING)
+    }
+
+    fn elo(&self) -> u32 {
+        self.options.elo.clamp(Self::MIN_ELO, Self::MAX_ELO)
+    }
+
+    fn randomization_margin(&self) -> Value {
+        if self.options.limit_strength {
+            return Self::MAX_ELO_MARGIN
+                + ((Self::MAX_ELO - self.elo()) / Self::ELO_PER_MARGIN_CP) as Value;
+        }
+        self.options.root_randomization
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Any mate score ends a normal search. With go mate we keep going
+    // until we find a mate that's short enough.
+    ///////////////////////////////////////////////////////////////////
+    fn widen(value: Value, delta: Value) -> Value {
+        if delta.abs() > Self::MAX_ASPIRATION_DELTA {
+            delta.signum() * Self::MATE
+        } else {
+            (value + delta).clamp(-Self::MATE, Self::MATE)
+        }
+    }
+
+    fn mate_found(&self, value: Value) -> bool {
+        match self.timer.mate_limit() {
+            Some(moves) => {
+                Self::is_checkmate(value) && value > 0 && Self::mate_moves(value) <= moves as Value
+            }
+            None => Self::is_checkmate(value),
+        }
+    }
+
+    // Moves until mate, negative when we're the one getting mated.
+    fn mate_moves(value: Value) -> Value {
+        if value > 0 {
+            (Self::MATE - value + 1) / 2
+        } else {
+            -(value + Self::MATE) / 2
+        }
+    }
+
+    fn root_moves(&self, board: &Board) -> MoveList {
+        let mut moves = MoveList::from(board);
+        if !self.search_moves.is_empty() {
+            moves.retain(|m| self.search_moves.contains(&m));
+        }
+        moves
+    }
+
+    fn root_moves_within_margin(&self, value: Value) -> Vec<(Move, Value)> {
+        let margin = self.randomization_margin();
+        if margin <= 0 || Self::is_checkmate(value) {
+            return Vec::new();
+        }
+
+        self.root_candidates
+            .iter()
+            .filter(|&&(_, lower_bound)| lower_bound >= value - margin)
+            .copied()
+            .collect()
     }
 
     fn search_root(
@@ -108,57 +382,95 @@ impl<'a> Search<'a> {
         // Check the hash table for the current
         // position, primarily for move ordering.
         ///////////////////////////////////////////////////////////////////
-        let hash_move = self.tt.probe(board).and_then(|entry| entry.best_move());
+        let tt_entry = self.tt.probe(board);
+        let hash_move = tt_entry.and_then(|entry| entry.best_move());
+        let raw_eval = tt_entry.map_or_else(|| board.eval(), |entry| entry.static_eval());
 
         ///////////////////////////////////////////////////////////////////
         // Score moves and begin searching recursively.
         ///////////////////////////////////////////////////////////////////
         let ply = 0;
         let mut value = -Self::MATE;
+        self.double_extensions[ply + 1] = 0;
         let mut best_move = None;
         let mut idx = 0;
 
-        let mut moves = MoveList::from(board);
+        let mut moves = self.root_moves(board);
         self.move_sorter
             .score_moves(&mut moves, board, ply, hash_move);
 
+        let margin = self.randomization_margin();
+        self.root_candidates.clear();
+
         while let Some(m) = moves.next_best(idx) {
-            if self.id == 0 && self.timer.elapsed() >= Self::PRINT_CURRMOVENUMBER_TIME {
+            if self.prints() && self.timer.elapsed() >= Self::PRINT_CURRMOVENUMBER_TIME {
                 Self::print_currmovenumber(depth, m, idx);
             }
 
+            self.push_line(Some(m));
             board.push(m);
+
+            ///////////////////////////////////////////////////////////////////
+            // With root move randomization on, also test each move against
+            // the margin below alpha to find moves that are nearly as good.
+            // A limited strength weighs them by score, so it searches the
+            // whole margin to get one.
+            ///////////////////////////////////////////////////////////////////
+            let mut lower_bound = None;
+            if idx > 0 && margin > 0 {
+                let threshold = alpha - margin;
+                if self.options.limit_strength {
+                    let score = -self.search(board, depth - 1, -alpha, -threshold, ply + 1);
+                    if score > threshold {
+                        lower_bound = Some(score);
+                    }
+                } else if -self.search(board, depth - 1, -threshold - 1, -threshold, ply + 1)
+                    > threshold
+                {
+                    lower_bound = Some(threshold + 1);
+                }
+            }
+
             if idx == 0 || -self.search(board, depth - 1, -alpha - 1, -alpha, ply + 1) > alpha {
                 value = -self.search(board, depth - 1, -beta, -alpha, ply + 1);
             }
             board.pop();
+            self.pop_line();
 
             if self.timer.local_stop() {
                 break;
             }
 
+            if value > alpha {
+                lower_bound = Some(value);
+            }
+
+            if let Some(lower_bound) = lower_bound.filter(|_| margin > 0) {
+                self.root_candidates.push((m, lower_bound));
+            }
+
             if value > alpha {
                 best_move = Some(m);
                 if value >= beta {
-                    self.tt.insert(board, depth, beta, best_move, Bound::Lower);
+                    self.tt
+                        .insert(board, depth, beta, best_move, Bound::Lower, raw_eval);
                     return (best_move, beta);
                 }
                 alpha = value;
-                self.tt.insert(board, depth, alpha, best_move, Bound::Upper);
+                self.tt
+                    .insert(board, depth, alpha, best_move, Bound::Upper, raw_eval);
             }
             idx += 1;
         }
 
-        best_move = best_move.or_else(|| {
-            if moves.len() > 0 {
-                Some(moves[0].m)
-            } else {
-                None
-            }
-        });
-
+        ///////////////////////////////////////////////////////////////////
+        // Only a move that raised alpha is returned. After a fail low the
+        // hash table still gets the first move, for ordering next time.
+        ///////////////////////////////////////////////////////////////////
         if !self.timer.local_stop() {
-            self.tt.insert(board, depth, alpha, best_move, Bound::Exact);
+            let tt_move = best_move.or_else(|| moves.iter_moves().next());
+            self.tt
+                .insert(board, depth, alpha, tt_move, Bound::Exact, raw_eval);
         }
         (best_move, alpha)
     }
@@ -171,6 +483,14 @@ impl<'a> Search<'a> {
         mut beta: Value,
         ply: Ply,
     ) -> Value {
+        ///////////////////////////////////////////////////////////////////
+        // Stop extending the line once the search stack or the board
+        // history is about to run out of room.
+        ///////////////////////////////////////////////////////////////////
+        if self.at_ply_cap(ply) {
+            return board.eval();
+        }
+
         ///////////////////////////////////////////////////////////////////
         // Mate distance pruning - will help reduce
         // some nodes when checkmate is near.
@@ -196,15 +516,24 @@ impl<'a> Search<'a> {
         // horizon effect.
         ///////////////////////////////////////////////////////////////////
         if depth <= 0 {
-            return self.q_search(board, alpha, beta, ply);
+            return self.q_search(board, 0, alpha, beta, ply);
         }
 
         if self.timer.stop_check() {
             return 0;
         }
 
+        if self.prints() && self.should_print_currline() {
+            self.print_currline();
+        }
+
+        ///////////////////////////////////////////////////////////////////
+        // This has to come before the hash table probe. A position that has
+        // already occurred is scored as a draw, and a score stored for it
+        // from a line without the repetition can't be trusted.
+        ///////////////////////////////////////////////////////////////////
         if board.is_draw() {
-            return 0;
+            return self.draw_value(ply);
         }
 
         ///////////////////////////////////////////////////////////////////
@@ -229,29 +558,79 @@ impl<'a> Search<'a> {
                     return tt_entry.value();
                 }
             }
-        } else if Self::can_apply_iid(depth, in_check, is_pv, excluded_move) {
-            depth -= Self::IID_DEPTH_REDUCTION;
         }
 
+        ///////////////////////////////////////////////////////////////////
+        // Internal iterative reductions. Without a hash move our move
+        // ordering is poor, so search a bit shallower.
+        ///////////////////////////////////////////////////////////////////
+        if Self::can_apply_iir(tt_entry, depth) {
+            depth -= 1;
+        }
+
+        ///////////////////////////////////////////////////////////////////
+        // The hash table keeps the evaluation of the position, so on a hit
+        // it doesn't have to be computed again.
+        ///////////////////////////////////////////////////////////////////
+        let raw_eval = tt_entry.map_or_else(|| board.eval(), |entry| entry.static_eval());
+        let static_eval = self.correct_eval(board, raw_eval);
+
+        ///////////////////////////////////////////////////////////////////
+        // The pruning below works from the static eval. A stored search
+        // value is better when its bound says the real value lies beyond
+        // the static eval in that direction.
+        ///////////////////////////////////////////////////////////////////
+        let eval = tt_entry
+            .filter(|entry| match entry.flag() {
+                Bound::Exact => true,
+                Bound::Lower => entry.value() > static_eval,
+                Bound::Upper => entry.value() < static_eval,
+            })
+            .map_or(static_eval, |entry| entry.value());
+
+        ///////////////////////////////////////////////////////////////////
+        // We're improving if the static eval is better than it was on our
+        // last move, in which case late moves are reduced less.
+        ///////////////////////////////////////////////////////////////////
+        let improving = !in_check
+            && ply >= 2
+            && self.static_evals[ply - 2].is_some_and(|last_eval| static_eval > last_eval);
+        self.static_evals[ply] = (!in_check).then_some(static_eval);
+
         ///////////////////////////////////////////////////////////////////
         // Reverse Futility Pruning
         ///////////////////////////////////////////////////////////////////
-        if Self::can_apply_rfp(depth, in_check, is_pv, beta, excluded_move) {
-            let eval = tt_entry.map_or_else(|| board.eval(), |entry| entry.value());
+        if Self::can_apply_rfp(depth, in_check, is_pv, beta, excluded_move)
+            && eval - self.rfp_margin(depth, improving) >= beta
+        {
+            return eval;
+        }
 
-            if eval - Self::rfp_margin(depth) >= beta {
-                return eval;
+        ///////////////////////////////////////////////////////////////////
+        // Razoring. If even a big margin over the evaluation can't reach
+        // alpha, see whether the captures can before searching anything
+        // else. Null move needs eval >= beta, so the two never overlap.
+        ///////////////////////////////////////////////////////////////////
+        if Self::can_apply_razor(depth, in_check, is_pv, beta, excluded_move)
+            && eval + Self::razor_margin(depth) <= alpha
+        {
+            let value = self.q_search(board, 0, alpha, beta, ply);
+            if value <= alpha {
+                return value;
             }
         }
 
         ///////////////////////////////////////////////////////////////////
         // Null move pruning.
         ///////////////////////////////////////////////////////////////////
-        if Self::can_apply_null(board, depth, beta, in_check, is_pv, excluded_move) {
-            let r = Self::null_reduction(depth);
+        if Self::can_apply_null(board, depth, eval, beta, in_check, is_pv, excluded_move) {
+            let r = self.null_reduction(depth);
+            self.push_line(None);
+            self.double_extensions[ply + 1] = self.double_extensions[ply];
             board.push_null();
-            let value = -self.search(board, depth - r - 1, -beta, -beta + 1, ply);
+            let value = -self.search(board, depth - r - 1, -beta, -beta + 1, ply + 1);
             board.pop_null();
+            self.pop_line();
             if self.timer.local_stop() {
                 return 0;
             }
@@ -261,51 +640,90 @@ impl<'a> Search<'a> {
         }
 
         ///////////////////////////////////////////////////////////////////
-        // Generate moves, score, and begin searching
+        // Pick moves in stages and begin searching
         // recursively.
         ///////////////////////////////////////////////////////////////////
         let mut tt_flag = Bound::Upper;
         let mut best_move = None;
+        let mut first_move = None;
         let mut idx = 0;
+        let mut quiets_tried = [None; Self::MAX_QUIETS_TRIED];
+        let mut n_quiets_tried = 0;
+        let mut captures_tried = MoveList::new();
 
-        let mut moves = MoveList::from(board);
-        self.move_sorter.score_moves(
-            &mut moves,
-            board,
-            ply,
-            tt_entry.and_then(|entry| entry.best_move()),
-        );
+        let tt_move = tt_entry.and_then(|entry| entry.best_move());
+        let mut moves = MovePicker::new(tt_move, ply);
+
+        while let Some(m) = moves.next(&self.move_sorter, board) {
+            first_move = first_move.or(Some(m));
 
-        while let Some(m) = moves.next_best(idx) {
             if Some(m) == excluded_move {
                 idx += 1;
                 continue;
             }
 
+            ///////////////////////////////////////////////////////////////////
+            // Late move pruning. Quiets come after the good captures and
+            // killers, so past a certain point at low depth we stop trying
+            // them.
+            ///////////////////////////////////////////////////////////////////
+            if Self::can_apply_lmp(m, depth, in_check, is_pv, tt_move)
+                && idx >= self.lmp_table[improving as usize][depth as usize]
+            {
+                idx += 1;
+                continue;
+            }
+
+            ///////////////////////////////////////////////////////////////////
+            // SEE pruning. Skip captures that lose too much material.
+            ///////////////////////////////////////////////////////////////////
+            if Self::can_apply_see_pruning(m, depth, idx, in_check, is_pv, excluded_move)
+                && !MoveSorter::see_ge(board, m, -Self::SEE_CAPTURE_MARGIN * depth as Value)
+            {
+                idx += 1;
+                continue;
+            }
+
+            ///////////////////////////////////////////////////////////////////
+            // Singular extensions. If no other move comes close to the hash
+            // move it gets an extra ply, or two if the rest fall well short.
+            // If another move beats beta as well, the hash move isn't the
+            // only good one and gets a ply less.
+            ///////////////////////////////////////////////////////////////////
             let extension = tt_entry
-                .filter(|&entry| Self::can_singular_extend(entry, m, depth, excluded_move))
+                .filter(|&entry| self.can_singular_extend(entry, m, depth, ply, excluded_move))
                 .map_or(0, |entry| {
                     let target = entry.value() - (2 * depth as Value);
                     self.excluded_moves[ply] = Some(m);
-                    let extension =
-                        if self.search(board, (depth - 1) / 2, target - 1, target, ply) < target {
-                            1
-                        } else {
-                            0
-                        };
+                    let value = self.search(board, (depth - 1) / 2, target - 1, target, ply);
                     self.excluded_moves[ply] = None;
-                    extension
+
+                    if value < target {
+                        if self.can_double_extend(value, target, is_pv, ply) {
+                            2
+                        } else {
+                            1
+                        }
+                    } else if value >= beta {
+                        -1
+                    } else {
+                        0
+                    }
                 });
+            self.double_extensions[ply + 1] = self.double_extensions[ply] + (extension == 2) as u8;
+
+            let history_score = self.move_sorter.history_score(board, m);
 
             ///////////////////////////////////////////////////////////////////
             // Make move and deepen search via principal variation search.
             ///////////////////////////////////////////////////////////////////
-            board.push(m);
-
+            self.push_line(Some(m));
             if depth > 1 {
-                self.tt.prefetch(board);
+                self.tt.prefetch(board.key_after(m));
             }
 
+            board.push(m);
+
             let mut value;
             if idx == 0 {
                 value = -self.search(board, depth + extension - 1, -beta, -alpha, ply + 1);
@@ -314,7 +732,7 @@ impl<'a> Search<'a> {
                 // Late move reductions.
                 ///////////////////////////////////////////////////////////////////
                 let mut reduction = if Self::can_apply_lmr(m, depth, idx) {
-                    Self::late_move_reduction(depth, idx)
+                    self.lmr_reduction(depth, idx, is_pv, improving, history_score)
                 } else {
                     0
                 };
@@ -350,6 +768,7 @@ impl<'a> Search<'a> {
             }
 
             board.pop();
+            self.pop_line();
 
             if self.timer.local_stop() {
                 return 0;
@@ -363,7 +782,17 @@ impl<'a> Search<'a> {
                 if value >= beta {
                     if m.is_quiet() {
                         self.move_sorter.add_killer(board, m, ply);
-                        self.move_sorter.add_history(m, depth);
+                        self.move_sorter.apply_history(
+                            board,
+                            m,
+                            quiets_tried[..n_quiets_tried].iter().flatten().copied(),
+                            depth,
+                        );
+                    } else if m.is_capture() {
+                        self.move_sorter.add_capture_history(board, m, depth);
+                    }
+                    for tried in captures_tried.iter_moves() {
+                        self.move_sorter.penalize_capture(board, tried, depth);
                     }
                     tt_flag = Bound::Lower;
                     alpha = beta;
@@ -372,30 +801,38 @@ impl<'a> Search<'a> {
                 tt_flag = Bound::Exact;
                 alpha = value;
             }
+
+            if m.is_quiet() && n_quiets_tried < Self::MAX_QUIETS_TRIED {
+                quiets_tried[n_quiets_tried] = Some(m);
+                n_quiets_tried += 1;
+            } else if m.is_capture() {
+                captures_tried.push(m);
+            }
             idx += 1;
         }
 
         ///////////////////////////////////////////////////////////////////
         // Checkmate and stalemate check.
         ///////////////////////////////////////////////////////////////////
-        if moves.len() == 0 && excluded_move.is_none() {
+        if first_move.is_none() {
             if in_check {
                 alpha = -mate_value;
             } else {
-                alpha = 0;
+                alpha = self.draw_value(ply);
             }
         }
 
         if !self.timer.local_stop() {
-            best_move = best_move.or_else(|| {
-                if moves.len() > 0 {
-                    Some(moves[0].m)
-                } else {
-                    None
-                }
-            });
+            if Self::can_update_correction(best_move, alpha, static_eval, tt_flag, in_check)
+                && excluded_move.is_none()
+            {
+                self.update_correction(board, alpha - static_eval, depth);
+            }
+
+            best_move = best_move.or(first_move);
 
-            self.tt.insert(board, depth, alpha, best_move, tt_flag);
+            self.tt
+                .insert(board, depth, alpha, best_move, tt_flag, raw_eval);
         }
         alpha
     }
@@ -403,21 +840,26 @@ impl<'a> Search<'a> {
     fn q_search(
         &mut self,
         board: &mut Board,
+        depth: Depth,
         mut alpha: Value,
         mut beta: Value,
         ply: Ply,
     ) -> Value {
+        if self.at_ply_cap(ply) {
+            return board.eval();
+        }
+
         if self.timer.stop_check() {
             return 0;
         }
 
         if board.is_draw() {
-            return 0;
+            return self.draw_value(ply);
         }
 
         self.sel_depth = self.sel_depth.max(ply);
 
-        let eval = board.eval();
+        let eval = self.corrected_eval(board);
 
         if eval >= beta {
             return beta;
@@ -436,7 +878,15 @@ impl<'a> Search<'a> {
             }
         }
 
-        let mut moves = MoveList::from_q(board);
+        ///////////////////////////////////////////////////////////////////
+        // At the first ply past the horizon, quiet checks are searched too
+        // so that we don't miss simple mating or forking tactics.
+        ///////////////////////////////////////////////////////////////////
+        let mut moves = if depth == 0 && !board.in_check() {
+            MoveList::from_q_checks(board)
+        } else {
+            MoveList::from_q(board)
+        };
         self.move_sorter.score_moves(
             &mut moves,
             board,
@@ -449,14 +899,30 @@ impl<'a> Search<'a> {
             ///////////////////////////////////////////////////////////////////
             // Effectively a SEE check. Bad captures will have a score < 0
             // given by the SEE + the bad capture offset,
-            // and here we skip bad captures.
+            // and here we skip bad captures. Quiet checks have to be safe.
             ///////////////////////////////////////////////////////////////////
-            if moves[idx].score < 0 {
-                break;
+            if m.is_quiet() {
+                if !MoveSorter::see_ge(board, m, 0) {
+                    idx += 1;
+                    continue;
+                }
+            } else if moves[idx].score < 0 {
+                idx += 1;
+                continue;
+            }
+
+            ///////////////////////////////////////////////////////////////////
+            // The further past the horizon, the more a capture has to win
+            // to be worth looking at.
+            ///////////////////////////////////////////////////////////////////
+            let threshold = Self::q_see_threshold(depth);
+            if threshold > 0 && m.is_capture() && !MoveSorter::see_ge(board, m, threshold) {
+                idx += 1;
+                continue;
             }
 
             board.push(m);
-            let value = -self.q_search(board, -beta, -alpha, ply + 1);
+            let value = -self.q_search(board, depth - 1, -beta, -alpha, ply + 1);
             board.pop();
 
             if self.timer.local_stop() {
@@ -474,9 +940,11 @@ impl<'a> Search<'a> {
         alpha
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn can_apply_null(
         board: &Board,
         depth: Depth,
+        eval: Value,
         beta: Value,
         in_check: bool,
         is_pv: bool,
@@ -487,67 +955,255 @@ impl<'a> Search<'a> {
             && board.peek().is_some()
             && depth >= Self::NULL_MIN_DEPTH
             && board.has_non_pawn_material()
-            && board.eval() >= beta
+            && eval >= beta
             && !Self::is_checkmate(beta)
             && excluded_move.is_none()
     }
 
-    fn can_apply_iid(
+    fn at_ply_cap(&mut self, ply: Ply) -> bool {
+        let at_cap = ply >= Self::MAX_PLY || self.current_line_len >= Self::MAX_PLY;
+
+        if at_cap && self.prints() && !self.ply_cap_reached {
+            self.ply_cap_reached = true;
+            println!(
+                "info string Search reached the ply limit of {}, truncating the line",
+                Self::MAX_PLY
+            );
+        }
+        at_cap
+    }
+
+    fn can_apply_iir(tt_entry: Option<TTEntry>, depth: Depth) -> bool {
+        depth >= Self::IIR_MIN_DEPTH && tt_entry.and_then(|entry| entry.best_move()).is_none()
+    }
+
+    fn can_apply_rfp(
         depth: Depth,
         in_check: bool,
         is_pv: bool,
+        beta: Value,
         excluded_move: Option<Move>,
     ) -> bool {
-        depth >= Self::IID_MIN_DEPTH && !in_check && !is_pv && excluded_move.is_none()
+        depth <= Self::RFP_MAX_DEPTH
+            && !in_check
+            && !is_pv
+            && !Self::is_checkmate(beta)
+            && excluded_move.is_none()
     }
 
-    fn can_apply_rfp(
+    fn can_apply_razor(
         depth: Depth,
         in_check: bool,
         is_pv: bool,
         beta: Value,
         excluded_move: Option<Move>,
     ) -> bool {
-        depth <= Self::RFP_MAX_DEPTH
+        depth <= Self::RAZOR_MAX_DEPTH
             && !in_check
             && !is_pv
             && !Self::is_checkmate(beta)
             && excluded_move.is_none()
     }
 
+    fn can_apply_lmp(
+        m: Move,
+        depth: Depth,
+        in_check: bool,
+        is_pv: bool,
+        tt_move: Option<Move>,
+    ) -> bool {
+        depth <= Self::LMP_MAX_DEPTH && !in_check && !is_pv && m.is_quiet() && Some(m) != tt_move
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // The evaluation tends to be off by about the same amount in
+    // positions with the same pawn structure, so we keep a running
+    // average of how far the search result was from it.
+    ///////////////////////////////////////////////////////////////////
+    fn correction_index(board: &Board) -> usize {
+        (board.pawn_hash() % Self::CORRECTION_HISTORY_SIZE as Hash) as usize
+    }
+
+    fn corrected_eval(&self, board: &Board) -> Value {
+        self.correct_eval(board, board.eval())
+    }
+
+    fn correct_eval(&self, board: &Board, raw_eval: Value) -> Value {
+        let correction = self.correction_history[Self::correction_index(board)][board.ctm()];
+        (raw_eval + correction / Self::CORRECTION_GRAIN)
+            .clamp(-Self::MAX_CORRECTED_EVAL, Self::MAX_CORRECTED_EVAL)
+    }
+
+    // Only when the result says something about the evaluation. A bound on
+    // the wrong side of it, a capture or a mate score doesn't.
+    fn can_update_correction(
+        best_move: Option<Move>,
+        value: Value,
+        static_eval: Value,
+        flag: Bound,
+        in_check: bool,
+    ) -> bool {
+        !in_check
+            && best_move.map_or(true, |m| m.is_quiet())
+            && !Self::is_checkmate(value)
+            && match flag {
+                Bound::Exact => true,
+                Bound::Lower => value > static_eval,
+                Bound::Upper => value < static_eval,
+            }
+    }
+
+    fn update_correction(&mut self, board: &Board, diff: Value, depth: Depth) {
+        let weight = (depth as Value + 1).min(Self::CORRECTION_MAX_WEIGHT);
+        let correction = &mut self.correction_history[Self::correction_index(board)][board.ctm()];
+        *correction = ((*correction * (Self::CORRECTION_WEIGHT_SCALE - weight)
+            + diff * Self::CORRECTION_GRAIN * weight)
+            / Self::CORRECTION_WEIGHT_SCALE)
+            .clamp(-Self::CORRECTION_HISTORY_MAX, Self::CORRECTION_HISTORY_MAX);
+    }
+
+    fn can_apply_see_pruning(
+        m: Move,
+        depth: Depth,
+        move_index: usize,
+        in_check: bool,
+        is_pv: bool,
+        excluded_move: Option<Move>,
+    ) -> bool {
+        !is_pv
+            && !in_check
+            && move_index > 0
+            && depth <= Self::SEE_PRUNING_MAX_DEPTH
+            && m.is_capture()
+            && excluded_move.is_none()
+    }
+
+    fn q_see_threshold(depth: Depth) -> Value {
+        -(depth as Value) * Self::Q_SEE_MARGIN
+    }
+
     fn can_apply_lmr(m: Move, depth: Depth, move_index: usize) -> bool {
         depth >= Self::LMR_MIN_DEPTH && move_index >= Self::LMR_MOVE_WO_REDUCTION && m.is_quiet()
     }
 
     fn can_singular_extend(
+        &self,
         entry: TTEntry,
         m: Move,
         depth: Depth,
+        ply: Ply,
         excluded_move: Option<Move>,
     ) -> bool {
         entry.best_move() == Some(m)
             && depth >= 4
+            && ply < 2 * self.root_depth as Ply
             && !Self::is_checkmate(entry.value())
             && excluded_move.is_none()
-            && entry.depth() + 2 >= depth
+            && entry.depth() + self.options.params.sing_extend_depth_margin >= depth
             && matches!(entry.flag(), Bound::Lower | Bound::Exact)
     }
 
-    fn null_reduction(depth: Depth) -> Depth {
+    // Double extensions are capped along a line, and together with
+    // stopping singular extensions past twice the root depth that keeps
+    // forcing lines from growing without end.
+    fn can_double_extend(&self, value: Value, target: Value, is_pv: bool, ply: Ply) -> bool {
+        !is_pv
+            && value < target - Self::SING_DOUBLE_MARGIN
+            && self.double_extensions[ply] < Self::MAX_DOUBLE_EXTENSIONS
+    }
+
+    fn null_reduction(&self, depth: Depth) -> Depth {
         // Idea of dividing in null move depth taken from Cosette
-        Self::NULL_MIN_DEPTH_REDUCTION + (depth - Self::NULL_MIN_DEPTH) / Self::NULL_DEPTH_DIVIDER
+        Self::NULL_MIN_DEPTH_REDUCTION
+            + (depth - Self::NULL_MIN_DEPTH) / self.options.params.null_depth_divider.max(1)
+    }
+
+    // When improving we trust the evaluation more and prune a depth earlier.
+    fn rfp_margin(&self, depth: Depth, improving: bool) -> Value {
+        self.options.params.rfp_margin_multiplier * ((depth - improving as Depth) as Value)
+    }
+
+    // Indexed by whether we're improving, then by depth.
+    fn lmp_table() -> [[usize; 64]; 2] {
+        let mut lmp_table = [[0; 64]; 2];
+        for depth in 1..=Self::LMP_MAX_DEPTH as usize {
+            let improving = Self::LMP_BASE_MOVES + depth * depth;
+            lmp_table[0][depth] = improving / 2;
+            lmp_table[1][depth] = improving;
+        }
+        lmp_table
+    }
+
+    fn razor_margin(depth: Depth) -> Value {
+        Self::RAZOR_MARGIN_MULTIPLIER * (depth as Value)
+    }
+
+    // LMR table idea from Ethereal
+    fn lmr_table(params: &SearchParams) -> [[Depth; 64]; 64] {
+        let mut lmr_table = [[0; 64]; 64];
+        for depth in 1..64 {
+            for move_number in 1..64 {
+                lmr_table[depth][move_number] = (params.lmr_base_reduction
+                    + f32::ln(depth as f32) * f32::ln(move_number as f32) / params.lmr_move_divider)
+                    as Depth;
+            }
+        }
+        lmr_table
+    }
+
+    fn lmr_reduction(
+        &self,
+        depth: Depth,
+        move_index: usize,
+        is_pv: bool,
+        improving: bool,
+        history_score: Value,
+    ) -> Depth {
+        let mut reduction = self.lmr_table[depth.min(63) as usize][move_index.min(63)] as Value;
+
+        ///////////////////////////////////////////////////////////////////
+        // Reduce less in pv nodes and for moves with a good history, and
+        // more when the position isn't getting any better. A small history
+        // divider can push this well outside of Depth, so it's only
+        // converted back once clamped.
+        ///////////////////////////////////////////////////////////////////
+        if is_pv {
+            reduction -= self.options.params.lmr_pv_reduction as Value;
+        }
+        if !improving {
+            reduction += self.options.params.lmr_improving_reduction as Value;
+        }
+        reduction -= history_score / self.options.params.lmr_history_divider.max(1);
+
+        reduction.clamp(0, (depth - 1).max(0) as Value) as Depth
     }
 
-    fn rfp_margin(depth: Depth) -> Value {
-        Self::RFP_MARGIN_MULTIPLIER * (depth as Value)
+    fn update_stability(
+        stability: u32,
+        last_best_move: Option<Move>,
+        best_move: Option<Move>,
+    ) -> u32 {
+        if last_best_move.is_some() && last_best_move == best_move {
+            stability + 1
+        } else {
+            0
+        }
     }
 
-    fn late_move_reduction(depth: Depth, move_index: usize) -> Depth {
-        // LMR table idea from Ethereal
-        unsafe { LMR_TABLE[depth.min(63) as usize][move_index.min(63)] }
+    ///////////////////////////////////////////////////////////////////
+    // A positive contempt makes a draw worse than equal for the side
+    // we're searching for, and better for the other side. Even plies
+    // are our moves.
+    ///////////////////////////////////////////////////////////////////
+    fn draw_value(&self, ply: Ply) -> Value {
+        if ply % 2 == 0 {
+            -self.options.contempt
+        } else {
+            self.options.contempt
+        }
     }
 
-    fn is_checkmate(value: Value) -> bool {
+    pub fn is_checkmate(value: Value) -> bool {
         value.abs() >= Self::MATE >> 1
     }
 
@@ -558,12 +1214,14 @@ impl<'a> Search<'a> {
 
         if let Some(tt_entry) = self.tt.probe(board) {
             let mut pv = String::new();
-            if let Some(m) = tt_entry
-                .best_move()
-                .filter(|&m| MoveList::from(board).contains(m))
-            {
+            if let Some(m) = tt_entry.best_move().filter(|&m| board.is_legal(m)) {
+                let move_str = if self.options.san_pv {
+                    board.move_to_san(m)
+                } else {
+                    m.to_string()
+                };
                 board.push(m);
-                pv = format!("{} {}", m, self.get_pv(board, depth - 1));
+                pv = format!("{} {}", move_str, self.get_pv(board, depth - 1));
                 board.pop();
             }
             return pv;
@@ -571,30 +1229,120 @@ impl<'a> Search<'a> {
         String::new()
     }
 
-    fn print_info(&self, board: &mut Board, depth: Depth, m: Move, value: Value) {
-        let score_str = if Self::is_checkmate(value) {
-            let mate_value = if value > 0 {
-                (Self::MATE - value + 1) / 2
-            } else {
-                -(value + Self::MATE) / 2
-            };
-            format!("mate {}", mate_value)
+    ///////////////////////////////////////////////////////////////////
+    // A root search that fails low or high only gives a bound on the
+    // score, which the info line has to say.
+    ///////////////////////////////////////////////////////////////////
+    fn score_bound(value: Value, alpha: Value, beta: Value) -> Bound {
+        if value <= alpha {
+            Bound::Upper
+        } else if value >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        }
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Only print info if we're in the main thread. Searches that fail
+    // outside the aspiration window are only shown when analysing.
+    ///////////////////////////////////////////////////////////////////
+    fn should_print_info(&self, bound: Bound) -> bool {
+        self.prints()
+            && !self.timer.local_stop()
+            && (bound == Bound::Exact || self.options.analyse_mode)
+    }
+
+    fn prints(&self) -> bool {
+        self.id == 0 && !self.silent
+    }
+
+    fn print_info(&self, board: &mut Board, depth: Depth, m: Move, value: Value, bound: Bound) {
+        println!("{}", self.info_string(board, depth, m, value, bound));
+    }
+
+    fn info_string(
+        &self,
+        board: &mut Board,
+        depth: Depth,
+        m: Move,
+        value: Value,
+        bound: Bound,
+    ) -> String {
+        let mut score_str = if Self::is_checkmate(value) {
+            format!("mate {}", Self::mate_moves(value))
         } else {
             format!("cp {}", value)
         };
 
+        match bound {
+            Bound::Lower => score_str.push_str(" lowerbound"),
+            Bound::Upper => score_str.push_str(" upperbound"),
+            Bound::Exact => (),
+        }
+
+        if self.options.show_wdl {
+            let (win, draw, loss) = if Self::is_checkmate(value) {
+                if value > 0 {
+                    (1000, 0, 0)
+                } else {
+                    (0, 0, 1000)
+                }
+            } else {
+                Self::wdl_model(value, Self::wdl_material(board))
+            };
+            score_str.push_str(&format!(" wdl {} {} {}", win, draw, loss));
+        }
+
         let elapsed = self.timer.elapsed();
         let nodes = self.timer.nodes();
 
-        println!("info currmove {m} depth {depth} seldepth {sel_depth} time {time} score {score_str} nodes {nodes} nps {nps} pv {pv}",
-                 m = m,
-                 depth = depth,
-                 sel_depth = self.sel_depth,
-                 time = elapsed.as_millis(),
-                 score_str = score_str,
-                 nodes = nodes,
-                 nps = (nodes as f64 / elapsed.as_secs_f64()) as u64,
-                 pv = self.get_pv(board, depth));
+        format!("info currmove {m} depth {depth} seldepth {sel_depth} time {time} score {score_str} nodes {nodes} nps {nps} hashfull {hashfull} tbhits {tbhits} pv {pv}",
+                m = m,
+                depth = depth,
+                sel_depth = self.sel_depth,
+                time = elapsed.as_millis(),
+                score_str = score_str,
+                nodes = nodes,
+                nps = Self::nps(nodes, elapsed),
+                hashfull = self.tt.hashfull(),
+                // There are no tablebases yet.
+                tbhits = 0,
+                pv = self.get_pv(board, depth))
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Win, draw and loss chances in permille for a score, from a
+    // logistic curve whose centre and spread depend on the material
+    // left. With fewer pieces on the board an even score is more of a
+    // draw. The constants are Stockfish's, rescaled to our centipawns.
+    ///////////////////////////////////////////////////////////////////
+    pub fn wdl_model(score: Value, material: Value) -> (u32, u32, u32) {
+        const A: [f64; 4] = [-45.97, 120.42, -98.09, 123.83];
+        const B: [f64; 4] = [19.00, -27.75, 13.99, 15.74];
+
+        let m = material.clamp(17, 78) as f64 / 58.0;
+        let a = ((A[0] * m + A[1]) * m + A[2]) * m + A[3];
+        let b = ((B[0] * m + B[1]) * m + B[2]) * m + B[3];
+        let x = score.clamp(-4000, 4000) as f64;
+
+        let win = (1000.0 / (1.0 + ((a - x) / b).exp())).round() as u32;
+        let loss = ((1000.0 / (1.0 + ((a + x) / b).exp())).round() as u32).min(1000 - win);
+        (win, 1000 - win - loss, loss)
+    }
+
+    // Pawns count 1, minors 3, rooks 5 and queens 9, for both sides.
+    fn wdl_material(board: &Board) -> Value {
+        const PIECE_TYPE_MATERIAL: PieceTypeMap<Value> = PieceTypeMap::new([1, 3, 3, 5, 9, 0]);
+
+        PieceType::iter(PieceType::Pawn, PieceType::Queen)
+            .map(|pt| board.bitboard_of_pt(pt).pop_count() * PIECE_TYPE_MATERIAL[pt])
+            .sum()
+    }
+
+    fn nps(nodes: u64, elapsed: Duration) -> u64 {
+        // Clamp to a microsecond so a search that finishes instantly doesn't divide by zero.
+        (nodes as u128 * 1_000_000 / elapsed.as_micros().max(1)) as u64
     }
 
     fn print_currmovenumber(depth: Depth, m: Move, idx: usize) {
@@ -605,24 +1353,89 @@ impl<'a> Search<'a> {
             currmovenumber = idx + 1,
         )
     }
+
+    fn should_print_currline(&mut self) -> bool {
+        self.currline_counter += 1;
+        if self.currline_counter < Self::PRINT_CURRLINE_FREQ {
+            return false;
+        }
+        self.currline_counter = 0;
+        self.timer.elapsed() >= Self::PRINT_CURRMOVENUMBER_TIME
+    }
+
+    fn push_line(&mut self, m: Option<Move>) {
+        self.current_line[self.current_line_len] = m;
+        self.current_line_len += 1;
+    }
+
+    fn pop_line(&mut self) {
+        self.current_line_len -= 1;
+    }
+
+    fn print_currline(&self) {
+        ///////////////////////////////////////////////////////////////////
+        // Written straight from the move stack so printing doesn't
+        // allocate in the middle of the search. Null moves are pushed
+        // as None, and the line is cut off at the first one.
+        ///////////////////////////////////////////////////////////////////
+        let mut stdout = io::stdout().lock();
+        let _ = write!(stdout, "info currline");
+        for m in self.current_line[..self.current_line_len]
+            .iter()
+            .map_while(|&m| m)
+        {
+            let _ = write!(stdout, " {}", m);
+        }
+        let _ = writeln!(stdout);
+    }
 }
 
 impl Search<'_> {
     const PRINT_CURRMOVENUMBER_TIME: Duration = Duration::from_millis(3000);
-    const SEARCHES_WO_TIMER_UPDATE: Depth = 8;
+    const PRINT_CURRLINE_FREQ: u64 = 1 << 20;
+    const MAX_PLY: Ply = MAX_MOVES - 1;
     const RFP_MAX_DEPTH: Depth = 9;
     const RFP_MARGIN_MULTIPLIER: Value = 63;
+    const RAZOR_MAX_DEPTH: Depth = 3;
+    const RAZOR_MARGIN_MULTIPLIER: Value = 200;
     const ASPIRATION_WINDOW: Value = 61;
+    const MAX_ASPIRATION_DELTA: Value = 1000;
     const NULL_MIN_DEPTH: Depth = 2;
     const NULL_MIN_DEPTH_REDUCTION: Depth = 1;
     const NULL_DEPTH_DIVIDER: Depth = 2;
-    const IID_MIN_DEPTH: Depth = 7;
-    const IID_DEPTH_REDUCTION: Depth = 2;
+    const IIR_MIN_DEPTH: Depth = 4;
+    const MAX_QUIETS_TRIED: usize = 64;
+    const LMP_MAX_DEPTH: Depth = 8;
+    const LMP_BASE_MOVES: usize = 3;
+    const SEE_PRUNING_MAX_DEPTH: Depth = 6;
+    const SEE_CAPTURE_MARGIN: Value = 100;
+    const Q_SEE_MARGIN: Value = 20;
     const LMR_MOVE_WO_REDUCTION: usize = 3;
     const LMR_MIN_DEPTH: Depth = 2;
     const LMR_BASE_REDUCTION: f32 = 0.11;
     const LMR_MOVE_DIVIDER: f32 = 1.56;
+    const LMR_PV_REDUCTION: Depth = 1;
+    const LMR_IMPROVING_REDUCTION: Depth = 1;
+    const LMR_HISTORY_DIVIDER: Value = 8192;
+    const SING_EXTEND_DEPTH_MARGIN: Depth = 2;
+    const SING_DOUBLE_MARGIN: Value = 20;
+    const MAX_DOUBLE_EXTENSIONS: u8 = 6;
+    const CORRECTION_HISTORY_SIZE: usize = 16384;
+    const CORRECTION_GRAIN: Value = 256;
+    const CORRECTION_WEIGHT_SCALE: Value = 256;
+    const CORRECTION_MAX_WEIGHT: Value = 16;
+    const CORRECTION_HISTORY_MAX: Value = 64 * Self::CORRECTION_GRAIN;
+    const MAX_CORRECTED_EVAL: Value = (Self::MATE >> 1) - 1;
     const MATE: Value = 32000;
+    pub const MIN_ELO: u32 = 1000;
+    pub const MAX_ELO: u32 = 2800;
+    const DEFAULT_ELO: u32 = 1500;
+    const ELO_PER_DEPTH: u32 = 150;
+    const MIN_ELO_NODES: u64 = 1000;
+    const ELO_PER_NODES_DOUBLING: u32 = 200;
+    const MAX_ELO_MARGIN: Value = 10;
+    const ELO_PER_MARGIN_CP: u32 = 4;
+    pub const MAX_CONTEMPT: Value = 100;
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -632,22 +1445,744 @@ pub enum Bound {
     Upper,
 }
 
-pub static mut LMR_TABLE: [[Depth; 64]; 64] = [[0; 64]; 64];
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicU64};
+    use std::sync::Arc;
+
+    use crate::bitboard::*;
+    use crate::magics::*;
+    use crate::search::*;
+    use crate::square::*;
+
+    fn timer(board: &Board, control: TimeControl) -> Timer {
+        Timer::new(
+            board,
+            control,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicU64::new(0)),
+            TimerOptions::default(),
+        )
+    }
+
+    #[test]
+    fn repetition_ignores_tt_score() {
+        init_magics();
+        init_bb();
+
+        let mut board = Board::new();
+        let tt = TT::new(1);
+        tt.insert(&board, 20, 500, None, Bound::Exact, 0);
+
+        for m in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            board.push_str(m).unwrap();
+        }
+        assert_eq!(board.repetition_count(), 1);
+        assert!(tt.probe(&board).is_some_and(|entry| entry.value() == 500));
+
+        let mut search = Search::new(
+            timer(&board, TimeControl::Infinite),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+        assert_eq!(search.search(&mut board, 4, 499, 500, 4), 0);
+    }
 
-fn init_lmr_table() -> [[Depth; 64]; 64] {
-    let mut lmr_table = [[0; 64]; 64];
-    for depth in 1..64 {
-        for move_number in 1..64 {
-            lmr_table[depth][move_number] = (Search::LMR_BASE_REDUCTION
-                + f32::ln(depth as f32) * f32::ln(move_number as f32) / Search::LMR_MOVE_DIVIDER)
-                as Depth;
+    #[test]
+    fn insufficient_material_is_immediate_draw() {
+        init_magics();
+        init_bb();
+
+        let tt = TT::new(1);
+        for fen in [
+            "8/8/3k4/8/8/4K3/8/8 w - - 0 1",
+            "8/8/3k4/8/8/4KN2/8/8 b - - 0 1",
+            "8/8/3kb3/8/8/4K3/8/8 w - - 0 1",
+            "8/8/3k4/8/8/3NKN2/8/8 b - - 0 1",
+            "8/8/3kb3/8/8/4KB2/8/8 w - - 0 1",
+        ] {
+            let mut board = Board::try_from(fen).unwrap();
+            let mut search = Search::new(
+                timer(&board, TimeControl::Infinite),
+                &tt,
+                SearchOptions::default(),
+                0,
+            );
+            assert_eq!(
+                search.search(&mut board, 20, -Search::MATE, Search::MATE, 1),
+                0
+            );
+            assert_eq!(search.timer.nodes(), 1);
+            assert!(tt.probe(&board).is_none());
         }
     }
-    lmr_table
-}
 
-pub fn init_search() {
-    unsafe {
-        LMR_TABLE = init_lmr_table();
+    #[test]
+    fn best_move_stability() {
+        let e2e4 = Some(Move::new(SQ::E2, SQ::E4, MoveFlags::DoublePush));
+        let d2d4 = Some(Move::new(SQ::D2, SQ::D4, MoveFlags::DoublePush));
+
+        let mut stability = 0;
+        let mut last_best_move = None;
+        let mut history = Vec::new();
+        for best_move in [e2e4, e2e4, e2e4, d2d4, d2d4, e2e4] {
+            stability = Search::update_stability(stability, last_best_move, best_move);
+            last_best_move = best_move;
+            history.push(stability);
+        }
+        assert_eq!(history, vec![0, 1, 2, 0, 1, 0]);
+
+        assert!((0..10)
+            .collect::<Vec<_>>()
+            .windows(2)
+            .all(|w| Timer::stability_factor(w[0]) >= Timer::stability_factor(w[1])));
+        assert!(Timer::stability_factor(0) > 100);
+        assert!(Timer::stability_factor(10) < 100);
+    }
+
+    #[test]
+    fn no_legal_moves_at_root() {
+        init_magics();
+        init_bb();
+
+        let tt = TT::new(1);
+
+        // Fool's mate.
+        let board =
+            Board::try_from("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3")
+                .unwrap();
+        let mut search = Search::new(
+            timer(&board, TimeControl::FixedDepth(5)),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+        assert_eq!(search.go(board), (None, -Search::MATE));
+
+        let board = Board::try_from("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        let mut search = Search::new(
+            timer(&board, TimeControl::FixedDepth(5)),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+        assert_eq!(search.go(board), (None, 0));
+    }
+
+    #[test]
+    fn info_without_elapsed_time() {
+        init_magics();
+        init_bb();
+
+        assert_eq!(Search::nps(1000, Duration::ZERO), 1_000_000_000);
+        assert_eq!(Search::nps(1000, Duration::from_secs(2)), 500);
+
+        let mut board = Board::new();
+        let tt = TT::new(1);
+        let search = Search::new(
+            timer(&board, TimeControl::Infinite),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+        let m = MoveList::from(&board)[0].m;
+
+        let info = search.info_string(&mut board, 1, m, 0, Bound::Exact);
+        let field = |name: &str| {
+            info.split_whitespace()
+                .skip_while(|&token| token != name)
+                .nth(1)
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        assert_eq!(field("nps"), Some(0));
+        assert_eq!(field("hashfull"), Some(0));
+        assert_eq!(field("tbhits"), Some(0));
+    }
+
+    #[test]
+    fn ply_cap() {
+        init_magics();
+        init_bb();
+
+        let mut board = Board::new();
+        while board.ply() + 8 < Board::N_HISTORIES {
+            for m in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                board.push_str(m).unwrap();
+            }
+        }
+        for m in ["e2e4", "e7e5", "d2d4", "d7d6"] {
+            board.push_str(m).unwrap();
+        }
+
+        let tt = TT::new(1);
+        let mut search = Search::new(
+            timer(&board, TimeControl::FixedDepth(5)),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+        assert!(search.go(board.clone()).0.is_some());
+        assert!(!search.ply_cap_reached);
+
+        let mut search = Search::new(
+            timer(&board, TimeControl::Infinite),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+        search.search(
+            &mut board,
+            5,
+            -Search::MATE,
+            Search::MATE,
+            Search::MAX_PLY - 2,
+        );
+        assert!(search.ply_cap_reached);
+    }
+
+    #[test]
+    fn lmr_reduction() {
+        init_magics();
+        init_bb();
+
+        let board = Board::new();
+        let tt = TT::new(1);
+        let search = Search::new(
+            timer(&board, TimeControl::Infinite),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+
+        let base = search.lmr_reduction(10, 20, false, true, 0);
+        assert!(base > 0);
+        assert_eq!(search.lmr_reduction(10, 20, true, true, 0), base - 1);
+        assert_eq!(search.lmr_reduction(10, 20, false, false, 0), base + 1);
+        assert!(search.lmr_reduction(10, 20, false, true, 30000) < base);
+
+        for depth in 1..40 {
+            for idx in 1..80 {
+                for (is_pv, improving) in [(false, false), (true, true)] {
+                    for history_score in [0, 30000] {
+                        let reduction =
+                            search.lmr_reduction(depth, idx, is_pv, improving, history_score);
+                        assert!((0..depth).contains(&reduction));
+                    }
+                }
+            }
+        }
+
+        // The smallest divider the option allows, with history at either end.
+        let mut options = SearchOptions::default();
+        options.params.lmr_history_divider = 1;
+        let search = Search::new(timer(&board, TimeControl::Infinite), &tt, options, 0);
+        assert_eq!(search.lmr_reduction(10, 20, false, true, 30000), 0);
+        assert_eq!(search.lmr_reduction(10, 20, false, true, -30000), 9);
+    }
+
+    #[test]
+    fn razor_conditions() {
+        let m = Move::new(SQ::E2, SQ::E4, MoveFlags::Quiet);
+
+        assert!(Search::can_apply_razor(1, false, false, 0, None));
+        assert!(Search::can_apply_razor(
+            Search::RAZOR_MAX_DEPTH,
+            false,
+            false,
+            0,
+            None
+        ));
+        assert!(!Search::can_apply_razor(
+            Search::RAZOR_MAX_DEPTH + 1,
+            false,
+            false,
+            0,
+            None
+        ));
+        assert!(!Search::can_apply_razor(1, true, false, 0, None));
+        assert!(!Search::can_apply_razor(1, false, true, 0, None));
+        assert!(!Search::can_apply_razor(
+            1,
+            false,
+            false,
+            Search::MATE - 1,
+            None
+        ));
+        assert!(!Search::can_apply_razor(1, false, false, 0, Some(m)));
+        assert!(Search::razor_margin(2) > Search::razor_margin(1));
+    }
+
+    #[test]
+    fn rfp_margin_improving() {
+        init_magics();
+        init_bb();
+
+        let board = Board::new();
+        let tt = TT::new(1);
+        let search = Search::new(
+            timer(&board, TimeControl::Infinite),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+
+        let multiplier = Search::RFP_MARGIN_MULTIPLIER;
+        assert_eq!(search.rfp_margin(4, false), 4 * multiplier);
+        assert_eq!(search.rfp_margin(4, true), 3 * multiplier);
+    }
+
+    #[test]
+    fn double_extension_conditions() {
+        init_magics();
+        init_bb();
+
+        let board = Board::new();
+        let tt = TT::new(1);
+        let mut search = Search::new(
+            timer(&board, TimeControl::Infinite),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+        let target = 100;
+        let far_below = target - Search::SING_DOUBLE_MARGIN - 1;
+
+        assert!(search.can_double_extend(far_below, target, false, 3));
+        assert!(!search.can_double_extend(target - 1, target, false, 3));
+        assert!(!search.can_double_extend(far_below, target, true, 3));
+
+        search.double_extensions[3] = Search::MAX_DOUBLE_EXTENSIONS;
+        assert!(!search.can_double_extend(far_below, target, false, 3));
+    }
+
+    #[test]
+    fn tt_keeps_static_eval() {
+        init_magics();
+        init_bb();
+
+        let mut board =
+            Board::try_from("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+        let tt = TT::new(1);
+        let mut search = Search::new(
+            timer(&board, TimeControl::Infinite),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+        search.search(&mut board, 3, -Search::MATE, Search::MATE, 1);
+
+        let entry = tt.probe(&board).unwrap();
+        assert_eq!(entry.static_eval(), board.eval());
+    }
+
+    #[test]
+    fn iir_conditions() {
+        let m = Move::new(SQ::E2, SQ::E4, MoveFlags::Quiet);
+        let with_move = TTEntry::new(0, Some(m), 1, Bound::Exact, 0);
+        let without_move = TTEntry::new(0, None, 1, Bound::Upper, 0);
+
+        assert!(Search::can_apply_iir(None, Search::IIR_MIN_DEPTH));
+        assert!(Search::can_apply_iir(
+            Some(without_move),
+            Search::IIR_MIN_DEPTH
+        ));
+        assert!(!Search::can_apply_iir(
+            Some(with_move),
+            Search::IIR_MIN_DEPTH
+        ));
+        assert!(!Search::can_apply_iir(None, Search::IIR_MIN_DEPTH - 1));
+    }
+
+    #[test]
+    fn correction_history() {
+        init_magics();
+        init_bb();
+
+        let mut board = Board::new();
+        let tt = TT::new(1);
+        let mut search = Search::new(
+            timer(&board, TimeControl::Infinite),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+        let raw_eval = board.eval();
+        assert_eq!(search.corrected_eval(&board), raw_eval);
+
+        for _ in 0..100 {
+            search.update_correction(&board, 1000, 10);
+        }
+        let corrected = search.corrected_eval(&board);
+        assert_eq!(
+            corrected,
+            raw_eval + Search::CORRECTION_HISTORY_MAX / Search::CORRECTION_GRAIN
+        );
+
+        // Same pawns, other side to move.
+        board.push_null();
+        assert_eq!(search.corrected_eval(&board), board.eval());
+        board.pop_null();
+
+        // Same pawns, different pieces.
+        board.push_str("g1f3").unwrap();
+        board.push_str("g8f6").unwrap();
+        assert_eq!(
+            search.corrected_eval(&board) - board.eval(),
+            corrected - raw_eval
+        );
+        board.pop();
+        board.pop();
+
+        let quiet = Move::new(SQ::E2, SQ::E4, MoveFlags::Quiet);
+        let capture = Move::new(SQ::E4, SQ::D5, MoveFlags::Capture);
+        assert!(Search::can_update_correction(
+            Some(quiet),
+            50,
+            0,
+            Bound::Exact,
+            false
+        ));
+        assert!(Search::can_update_correction(
+            None,
+            -50,
+            0,
+            Bound::Upper,
+            false
+        ));
+        assert!(Search::can_update_correction(
+            Some(quiet),
+            50,
+            0,
+            Bound::Lower,
+            false
+        ));
+        assert!(!Search::can_update_correction(
+            Some(quiet),
+            -50,
+            0,
+            Bound::Lower,
+            false
+        ));
+        assert!(!Search::can_update_correction(
+            None,
+            50,
+            0,
+            Bound::Upper,
+            false
+        ));
+        assert!(!Search::can_update_correction(
+            Some(capture),
+            50,
+            0,
+            Bound::Exact,
+            false
+        ));
+        assert!(!Search::can_update_correction(
+            Some(quiet),
+            50,
+            0,
+            Bound::Exact,
+            true
+        ));
+        assert!(!Search::can_update_correction(
+            Some(quiet),
+            Search::MATE - 1,
+            0,
+            Bound::Exact,
+            false
+        ));
+    }
+
+    #[test]
+    fn late_move_pruning() {
+        init_magics();
+        init_bb();
+
+        let board = Board::new();
+        let tt = TT::new(1);
+        let search = Search::new(
+            timer(&board, TimeControl::Infinite),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+
+        assert_eq!(search.lmp_table[1][2], 7);
+        for depth in 1..=Search::LMP_MAX_DEPTH as usize {
+            assert!(search.lmp_table[0][depth] > 0);
+            assert!(search.lmp_table[0][depth] < search.lmp_table[1][depth]);
+            assert!(search.lmp_table[1][depth] > search.lmp_table[1][depth - 1]);
+        }
+
+        let quiet = Move::new(SQ::E2, SQ::E4, MoveFlags::Quiet);
+        let capture = Move::new(SQ::E4, SQ::D5, MoveFlags::Capture);
+        let promotion = Move::new(SQ::E7, SQ::E8, MoveFlags::PrQueen);
+        assert!(Search::can_apply_lmp(quiet, 1, false, false, None));
+        assert!(!Search::can_apply_lmp(
+            quiet,
+            Search::LMP_MAX_DEPTH + 1,
+            false,
+            false,
+            None
+        ));
+        assert!(!Search::can_apply_lmp(quiet, 1, true, false, None));
+        assert!(!Search::can_apply_lmp(quiet, 1, false, true, None));
+        assert!(!Search::can_apply_lmp(quiet, 1, false, false, Some(quiet)));
+        assert!(!Search::can_apply_lmp(capture, 1, false, false, None));
+        assert!(!Search::can_apply_lmp(promotion, 1, false, false, None));
+    }
+
+    #[test]
+    fn lmr_table_follows_params() {
+        init_magics();
+        init_bb();
+
+        let board = Board::new();
+        let tt = TT::new(1);
+        let search = |params| {
+            Search::new(
+                timer(&board, TimeControl::Infinite),
+                &tt,
+                SearchOptions {
+                    params,
+                    ..SearchOptions::default()
+                },
+                0,
+            )
+        };
+
+        let default = search(SearchParams::default());
+        assert_eq!(default.lmr_table[10][20], 4);
+
+        let reduced = search(SearchParams {
+            lmr_base_reduction: 1.11,
+            ..SearchParams::default()
+        });
+        assert_eq!(reduced.lmr_table[10][20], 5);
+
+        let divided = search(SearchParams {
+            lmr_move_divider: 3.12,
+            ..SearchParams::default()
+        });
+        assert_eq!(divided.lmr_table[10][20], 2);
+    }
+
+    #[test]
+    fn aspiration_widening() {
+        assert_eq!(Search::widen(50, -100), -50);
+        assert_eq!(Search::widen(50, 100), 150);
+        assert_eq!(Search::widen(-Search::MATE + 10, -100), -Search::MATE);
+        assert_eq!(Search::widen(Search::MATE - 10, 100), Search::MATE);
+
+        let past_max = Search::MAX_ASPIRATION_DELTA + 1;
+        assert_eq!(Search::widen(50, -past_max), -Search::MATE);
+        assert_eq!(Search::widen(50, past_max), Search::MATE);
+    }
+
+    #[test]
+    fn score_bound() {
+        init_magics();
+        init_bb();
+
+        assert_eq!(Search::score_bound(-50, -40, 40), Bound::Upper);
+        assert_eq!(Search::score_bound(-40, -40, 40), Bound::Upper);
+        assert_eq!(Search::score_bound(10, -40, 40), Bound::Exact);
+        assert_eq!(Search::score_bound(40, -40, 40), Bound::Lower);
+        assert_eq!(Search::score_bound(50, -40, 40), Bound::Lower);
+
+        let mut board = Board::new();
+        let tt = TT::new(1);
+        let search = Search::new(
+            timer(&board, TimeControl::Infinite),
+            &tt,
+            SearchOptions::default(),
+            0,
+        );
+        let m = MoveList::from(&board)[0].m;
+        for (bound, expected) in [
+            (Bound::Exact, " score cp 30 nodes"),
+            (Bound::Lower, " score cp 30 lowerbound nodes"),
+            (Bound::Upper, " score cp 30 upperbound nodes"),
+        ] {
+            let info = search.info_string(&mut board, 1, m, 30, bound);
+            assert!(info.contains(expected), "{}", info);
+        }
+    }
+
+    #[test]
+    fn wdl() {
+        init_magics();
+        init_bb();
+
+        for material in [0, 17, 40, 78, 100] {
+            let mut last_win = 0;
+            for score in (-5000..=5000).step_by(50) {
+                let (win, draw, loss) = Search::wdl_model(score, material);
+                assert_eq!(win + draw + loss, 1000);
+                assert!(win >= last_win);
+                assert_eq!(Search::wdl_model(-score, material), (loss, draw, win));
+                last_win = win;
+            }
+            assert!(Search::wdl_model(0, material).1 > 900);
+            assert!(Search::wdl_model(2000, material).0 > 990);
+        }
+        assert!(Search::wdl_model(0, 20).1 > Search::wdl_model(0, 78).1);
+
+        let mut board = Board::new();
+        assert_eq!(Search::wdl_material(&board), 78);
+        let tt = TT::new(1);
+        let m = MoveList::from(&board)[0].m;
+        for show_wdl in [false, true] {
+            let search = Search::new(
+                timer(&board, TimeControl::Infinite),
+                &tt,
+                SearchOptions {
+                    show_wdl,
+                    ..SearchOptions::default()
+                },
+                0,
+            );
+            let info = search.info_string(&mut board, 1, m, 30, Bound::Exact);
+            assert_eq!(info.contains(" wdl "), show_wdl, "{}", info);
+            if show_wdl {
+                let (win, draw, loss) = Search::wdl_model(30, 78);
+                assert!(info.contains(&format!(" score cp 30 wdl {} {} {} nodes", win, draw, loss)));
+                let mate = search.info_string(&mut board, 1, m, Search::MATE - 3, Bound::Exact);
+                assert!(
+                    mate.contains(" score mate 2 wdl 1000 0 0 nodes"),
+                    "{}",
+                    mate
+                );
+                let mated = search.info_string(&mut board, 1, m, -Search::MATE + 2, Bound::Exact);
+                assert!(mated.contains(" wdl 0 0 1000 nodes"), "{}", mated);
+            }
+        }
+    }
+
+    #[test]
+    fn analyse_mode_info() {
+        init_magics();
+        init_bb();
+
+        let board = Board::new();
+        let tt = TT::new(1);
+        for analyse_mode in [false, true] {
+            let options = SearchOptions {
+                analyse_mode,
+                ..Default::default()
+            };
+            let search = Search::new(timer(&board, TimeControl::Infinite), &tt, options, 0);
+            assert!(search.should_print_info(Bound::Exact));
+            assert_eq!(search.should_print_info(Bound::Lower), analyse_mode);
+            assert_eq!(search.should_print_info(Bound::Upper), analyse_mode);
+
+            let helper = Search::new(timer(&board, TimeControl::Infinite), &tt, options, 1);
+            assert!(!helper.should_print_info(Bound::Exact));
+        }
+    }
+
+    #[test]
+    fn san_pv() {
+        init_magics();
+        init_bb();
+
+        let mut board = Board::new();
+        let tt = TT::new(1);
+        let options = SearchOptions {
+            san_pv: true,
+            ..Default::default()
+        };
+        let (best_move, value) =
+            Search::new(timer(&board, TimeControl::FixedDepth(4)), &tt, options, 0)
+                .go(board.clone());
+
+        let search = Search::new(timer(&board, TimeControl::Infinite), &tt, options, 0);
+        let fen = board.to_string();
+        let hash = board.hash();
+        let info = search.info_string(&mut board, 4, best_move.unwrap(), value, Bound::Exact);
+        assert_eq!(board.to_string(), fen);
+        assert_eq!(board.hash(), hash);
+
+        let pv = info.split(" pv ").nth(1).unwrap();
+        let mut replay = Board::new();
+        for san in pv.split_whitespace() {
+            replay.push(replay.san_to_move(san).unwrap());
+        }
+        assert!(replay.ply() > 0);
+    }
+
+    #[test]
+    fn go_mate_finds_mate_in_two() {
+        init_magics();
+        init_bb();
+
+        // 1. Nf6+ gxf6 2. Bxf7#
+        let board =
+            Board::try_from("r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 1")
+                .unwrap();
+        let tt = TT::new(1);
+        let mut search = Search::new(
+            timer(&board, TimeControl::Infinite).with_mate_limit(Some(2)),
+            &tt,
+            SearchOptions::default(),
+            0,
+        )
+        .silent();
+
+        let (best_move, value) = search.go(board);
+        assert_eq!(best_move.map(|m| m.to_string()), Some("d5f6".to_string()));
+        assert_eq!(value, Search::MATE - 3);
+        assert_eq!(Search::mate_moves(value), 2);
+
+        // Without a mate to find, the time control still ends the search.
+        let board = Board::new();
+        let mut search = Search::new(
+            timer(&board, TimeControl::FixedDepth(4)).with_mate_limit(Some(2)),
+            &tt,
+            SearchOptions::default(),
+            0,
+        )
+        .silent();
+        let (best_move, value) = search.go(board);
+        assert!(best_move.is_some());
+        assert!(!Search::is_checkmate(value));
+    }
+
+    #[test]
+    fn early_stop_returns_legal_move() {
+        init_magics();
+        init_bb();
+
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1",
+        ] {
+            let board = Board::try_from(fen).unwrap();
+            let legal = MoveList::from(&board);
+
+            ///////////////////////////////////////////////////////////////////
+            // A stop that's already up ends the search before depth 1, and
+            // node limits this low end it part way through an iteration.
+            ///////////////////////////////////////////////////////////////////
+            let stopped = Timer::new(
+                &board,
+                TimeControl::Infinite,
+                Arc::new(AtomicBool::new(true)),
+                Arc::new(AtomicU64::new(0)),
+                TimerOptions::default(),
+            );
+            let timers =
+                [1, 1000, 3000, 10000].map(|nodes| timer(&board, TimeControl::FixedNodes(nodes)));
+
+            for timer in std::iter::once(stopped).chain(timers) {
+                let tt = TT::new(1);
+                let mut search = Search::new(timer, &tt, SearchOptions::default(), 0).silent();
+                let (best_move, _) = search.go(board.clone());
+                assert!(best_move.is_some_and(|m| legal.contains(m)), "{}", fen);
+            }
+        }
     }
 }
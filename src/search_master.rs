@@ -1,32 +1,24 @@
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::fs;
+use std::sync::atomic::AtomicBool;
 use std::sync::mpsc::Receiver;
 use std::sync::Arc;
-use std::thread;
 use std::time::Duration;
 
-use super::board::*;
-use super::perft::*;
-use super::search::*;
-use super::timer::*;
-use super::tt::*;
+use weiawaga::{
+    print_bench, print_divide, print_perft, Depth, Engine, EngineOption, EpdPosition, TimeControl,
+    Value,
+};
+
 use super::uci::*;
 
 pub struct SearchMaster {
-    stop: Arc<AtomicBool>,
-    board: Board,
-    num_threads: u16,
-    tt: TT,
-    overhead: Duration,
+    engine: Engine,
 }
 
 impl SearchMaster {
-    pub fn new(stop: Arc<AtomicBool>) -> Self {
+    pub fn new(stop: Arc<AtomicBool>, pondering: Arc<AtomicBool>) -> Self {
         Self {
-            stop,
-            board: Board::new(),
-            num_threads: 1,
-            tt: TT::new(16),
-            overhead: Duration::ZERO,
+            engine: Engine::new(stop).with_pondering(pondering),
         }
     }
 
@@ -37,38 +29,109 @@ impl SearchMaster {
                     println!("readyok");
                 }
                 UCICommand::UCINewGame => {
-                    self.board.reset();
-                    self.tt.clear();
+                    self.engine.new_game();
                 }
                 UCICommand::UCI => {
                     println!("id name Weiawaga v{}", env!("CARGO_PKG_VERSION"));
                     println!("id author {}", env!("CARGO_PKG_AUTHORS"));
                     println!("option name Hash type spin default 16 min 1 max 65536");
+                    println!("option name Clear Hash type button");
                     println!("option name Threads type spin default 1 min 1 max 512");
                     println!("option name Overhead type spin default 0 min 0 max 5000");
+                    println!("option name MinThinkTime type spin default 0 min 0 max 60000");
+                    println!("option name MaxThinkTime type spin default 0 min 0 max 3600000");
+                    println!("option name nodestime type spin default 0 min 0 max 10000");
+                    println!("option name Seed type spin default 0 min 0 max 4294967295");
+                    println!("option name UCI_SanPV type check default false");
+                    println!("option name UCI_AnalyseMode type check default false");
+                    println!("option name UCI_ShowWDL type check default false");
+                    println!("option name UCI_Chess960 type check default false");
+                    println!("option name UseNNUE type check default true");
+                    println!("option name Ponder type check default false");
+                    println!("option name RootMoveRandomization type spin default 0 min 0 max 100");
+                    println!("option name UCI_LimitStrength type check default false");
+                    println!("option name UCI_Elo type spin default 1500 min 1000 max 2800");
+                    println!("option name Contempt type spin default 0 min -100 max 100");
+                    println!("option name AspirationWindow type spin default 61 min 1 max 500");
+                    println!("option name RFPMarginMultiplier type spin default 63 min 0 max 300");
+                    println!("option name NullDepthDivider type spin default 2 min 1 max 8");
+                    // The LMR table constants are in hundredths, since spins are integers.
+                    println!("option name LMRBaseReduction type spin default 11 min 0 max 300");
+                    println!("option name LMRMoveDivider type spin default 156 min 50 max 500");
+                    println!("option name LMRPVReduction type spin default 1 min 0 max 4");
+                    println!("option name LMRImprovingReduction type spin default 1 min 0 max 4");
+                    println!(
+                        "option name LMRHistoryDivider type spin default 8192 min 1 max 32768"
+                    );
+                    println!(
+                        "option name SingularExtensionDepthMargin type spin default 2 min 0 max 8"
+                    );
                     println!("uciok");
                 }
                 UCICommand::Position { fen, moves } => {
-                    match self.set_board(fen, moves) {
+                    match self.engine.set_position(fen.as_deref(), &moves) {
                         Ok(_) => (),
                         Err(err) => eprintln!("{}", err),
                     };
                 }
-                UCICommand::Go(time_control) => {
-                    self.go(time_control);
+                UCICommand::Go {
+                    time_control,
+                    mate_limit,
+                    search_moves,
+                    ..
+                } => {
+                    self.go(time_control, mate_limit, &search_moves);
+                }
+                UCICommand::Perft {
+                    depth,
+                    hashed,
+                    divide,
+                    mut board,
+                } => {
+                    let board = match board.as_deref_mut() {
+                        Some(board) => board,
+                        None => self.engine.board_mut(),
+                    };
+                    if divide {
+                        print_divide(board, depth, hashed);
+                    } else {
+                        print_perft(board, depth, hashed);
+                    }
                 }
-                UCICommand::Perft(depth) => {
-                    print_perft(&mut self.board, depth);
+                ///////////////////////////////////////////////////////////////////
+                // The history and killer tables belong to each search and
+                // start out empty anyway, so only the hash table is left.
+                ///////////////////////////////////////////////////////////////////
+                UCICommand::Option { name, .. } if name == "Clear Hash" => {
+                    self.engine.set_option(EngineOption::ClearHash);
+                    println!("info string cleared hash");
                 }
-                UCICommand::Option { name, value } => match self.set_option(name, value) {
-                    Ok(result) => println!("info string set {}", result),
-                    Err(_) => eprintln!("Option not recognized or parsing error."),
+                UCICommand::Option { name, value } => match self.set_option(&name, value) {
+                    Ok(Some(result)) => println!("info string set {}", result),
+                    Ok(None) => println!("info string ignoring unknown option {}", name),
+                    Err(_) => eprintln!("Unable to parse value for option {}.", name),
                 },
+                UCICommand::Bench { depth, signature } => {
+                    print_bench(depth, signature);
+                }
+                UCICommand::Epd {
+                    path,
+                    movetime_ms,
+                    margin,
+                } => {
+                    self.epd(&path, Duration::from_millis(movetime_ms), margin);
+                }
                 UCICommand::Eval => {
-                    println!("{}", self.board.eval());
+                    println!("{}", self.engine.eval());
                 }
                 UCICommand::Fen => {
-                    println!("{}", self.board);
+                    println!("{}", self.engine.board());
+                }
+                UCICommand::Pgn => {
+                    println!("{}", self.engine.board().to_pgn());
+                }
+                UCICommand::Draw => {
+                    println!("{}", self.draw());
                 }
                 _ => {
                     eprintln!("Unexpected UCI Command.");
@@ -77,82 +140,661 @@ impl SearchMaster {
         }
     }
 
-    fn go(&mut self, time_control: TimeControl) {
-        self.stop.store(false, Ordering::SeqCst);
-        let nodes = Arc::new(AtomicU64::new(0));
+    fn go(&mut self, time_control: TimeControl, mate_limit: Option<u32>, search_moves: &[String]) {
+        println!("{}", self.bestmove(time_control, mate_limit, search_moves));
+    }
 
-        let best_move = thread::scope(|s| {
-            // Create main search thread with the actual time control. This thread controls self.stop.
-            let mut main_search_thread = Search::new(
-                Timer::new(
-                    &self.board,
-                    time_control,
-                    self.stop.clone(),
-                    nodes.clone(),
-                    self.overhead,
-                ),
-                &self.tt,
-                0,
-            );
+    ///////////////////////////////////////////////////////////////////
+    // Mated and stalemated positions have no move to search, which
+    // UCI reports as a null move.
+    ///////////////////////////////////////////////////////////////////
+    fn bestmove(
+        &mut self,
+        time_control: TimeControl,
+        mate_limit: Option<u32>,
+        search_moves: &[String],
+    ) -> String {
+        let Some(best_move) = self
+            .engine
+            .search_among(time_control, mate_limit, search_moves)
+            .0
+        else {
+            return "bestmove (none)".to_string();
+        };
 
-            // Create helper search threads which will stop when self.stop resolves to true.
-            for id in 1..self.num_threads {
-                let thread_board = self.board.clone();
-                let mut helper_search_thread = Search::new(
-                    Timer::new(
-                        &thread_board,
-                        TimeControl::Infinite,
-                        self.stop.clone(),
-                        nodes.clone(),
-                        self.overhead,
-                    ),
-                    &self.tt,
-                    id,
-                );
-                s.spawn(move || helper_search_thread.go(thread_board));
-            }
-            main_search_thread.go(self.board.clone())
-        });
+        let ponder_move = self
+            .engine
+            .ponder()
+            .then(|| self.engine.ponder_move(best_move))
+            .flatten();
 
-        match best_move {
-            Some(m) => println!("bestmove {}", m),
-            None => println!("bestmove (none)"),
+        match ponder_move {
+            Some(ponder_move) => format!("bestmove {} ponder {}", best_move, ponder_move),
+            None => format!("bestmove {}", best_move),
         }
     }
 
-    fn set_board(&mut self, fen: Option<String>, moves: Vec<String>) -> Result<(), &str> {
-        let mut board = Board::new();
-        if let Some(fen) = fen {
-            board.set_fen(&fen)?;
+    ///////////////////////////////////////////////////////////////////
+    // Searches every position of an EPD suite for a fixed time, each
+    // from a new game, and keeps a running tally. Lines that can't be
+    // parsed are reported and left out of it.
+    //
+    // With a margin, a position the engine misses is searched again
+    // among its best moves only. It still counts towards the margin
+    // tally when the engine's own score is within the margin of the
+    // best moves' score, since a suite's bm is rarely the only good
+    // move.
+    ///////////////////////////////////////////////////////////////////
+    fn epd(&mut self, path: &str, movetime: Duration, margin: Option<Value>) {
+        let suite = match fs::read_to_string(path) {
+            Ok(suite) => suite,
+            Err(err) => {
+                eprintln!("Unable to read {}: {}", path, err);
+                return;
+            }
+        };
+
+        let mut solved = 0;
+        let mut solved_within_margin = 0;
+        let mut total = 0;
+        for line in suite.lines().filter(|line| !line.trim().is_empty()) {
+            let position = match EpdPosition::try_from(line) {
+                Ok(position) => position,
+                Err(err) => {
+                    eprintln!("{} {}", err, line);
+                    continue;
+                }
+            };
+
+            self.engine.new_game();
+            if let Err(err) = self
+                .engine
+                .set_position(Some(&position.board.to_string()), &[])
+            {
+                eprintln!("{} {}", err, line);
+                continue;
+            }
+
+            let (best_move, score) = self.engine.search(TimeControl::FixedDuration(movetime));
+            let passed = best_move.is_some_and(|m| position.is_solved_by(m));
+            let passed_within_margin = passed
+                || margin.is_some_and(|margin| {
+                    best_move.is_some_and(|m| !position.avoid_moves.contains(&m))
+                        && !position.best_moves.is_empty()
+                        && score >= self.epd_best_score(&position, movetime) - margin
+                });
+
+            total += 1;
+            solved += passed as u32;
+            solved_within_margin += passed_within_margin as u32;
+            println!(
+                "info string epd {} {} {} solved {}/{}",
+                position.id.as_deref().unwrap_or(&total.to_string()),
+                match (passed, passed_within_margin) {
+                    (true, _) => "pass",
+                    (false, true) => "margin",
+                    (false, false) => "fail",
+                },
+                best_move.map_or("(none)".to_string(), |m| position.board.move_to_san(m)),
+                solved,
+                total
+            );
         }
 
-        for m in moves {
-            board.push_str(&m)?;
+        if total > 0 {
+            println!(
+                "info string epd solved {}/{} ({:.1}%)",
+                solved,
+                total,
+                100.0 * solved as f64 / total as f64
+            );
+            if let Some(margin) = margin {
+                println!(
+                    "info string epd solved within {}cp {}/{} ({:.1}%)",
+                    margin,
+                    solved_within_margin,
+                    total,
+                    100.0 * solved_within_margin as f64 / total as f64
+                );
+            }
         }
+    }
+
+    // The score of the position's best moves, searched on their own.
+    fn epd_best_score(&mut self, position: &EpdPosition, movetime: Duration) -> Value {
+        let best_moves = position
+            .best_moves
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<String>>();
+        self.engine
+            .search_among(TimeControl::FixedDuration(movetime), None, &best_moves)
+            .1
+    }
 
-        self.board = board;
-        Ok(())
+    fn draw(&self) -> String {
+        let board = self.engine.board();
+        format!(
+            "{:?}Fen: {}\nKey: {:016X}\nSide to move: {:?}\nCastling: {}\nEval: {}",
+            board,
+            board,
+            board.hash(),
+            board.ctm(),
+            board.castling_rights_str(),
+            self.engine.eval()
+        )
     }
 
-    fn set_option(&mut self, name: String, value: String) -> Result<String, ()> {
-        let result = match (name.as_str(), value.parse::<u128>()) {
+    ///////////////////////////////////////////////////////////////////
+    // Returns None for options we don't have, which GUIs are free to
+    // send anyway, and an error when the value can't be parsed.
+    ///////////////////////////////////////////////////////////////////
+    fn set_option(&mut self, name: &str, value: String) -> Result<Option<String>, ()> {
+        let result = match (name, value.parse::<u128>()) {
             ("Hash", Ok(parsed_value)) => {
-                self.tt = TT::new(parsed_value as usize);
-                format!("Hash to {}MB", self.tt.mb_size())
+                self.engine
+                    .set_option(EngineOption::Hash(parsed_value as usize));
+                format!("Hash to {}MB", self.engine.hash_mb())
             }
             ("Threads", Ok(parsed_value)) => {
-                self.num_threads = parsed_value as u16;
-                format!("Threads to {}", self.num_threads)
+                self.engine
+                    .set_option(EngineOption::Threads(parsed_value as u16));
+                format!("Threads to {}", self.engine.num_threads())
             }
             ("Overhead", Ok(parsed_value)) => {
-                self.overhead = Duration::from_millis(parsed_value as u64);
-                format!("Overhead to {}ms", self.overhead.as_millis())
+                self.engine
+                    .set_option(EngineOption::Overhead(Duration::from_millis(
+                        parsed_value as u64,
+                    )));
+                format!("Overhead to {}ms", self.engine.overhead().as_millis())
             }
-            _ => {
+            ("MinThinkTime", Ok(parsed_value)) => {
+                self.engine
+                    .set_option(EngineOption::MinThinkTime(Duration::from_millis(
+                        parsed_value as u64,
+                    )));
+                format!(
+                    "MinThinkTime to {}ms",
+                    self.engine.timer_options().min_think_time.as_millis()
+                )
+            }
+            ("MaxThinkTime", Ok(parsed_value)) => {
+                self.engine
+                    .set_option(EngineOption::MaxThinkTime(Duration::from_millis(
+                        parsed_value as u64,
+                    )));
+                format!(
+                    "MaxThinkTime to {}ms",
+                    self.engine.timer_options().max_think_time.as_millis()
+                )
+            }
+            ("nodestime", Ok(parsed_value)) => {
+                self.engine
+                    .set_option(EngineOption::NodesTime(parsed_value as u64));
+                format!("nodestime to {}", self.engine.timer_options().nodestime)
+            }
+            // 0 seeds from the clock, like an engine that was never given one.
+            ("Seed", Ok(parsed_value)) => {
+                self.engine
+                    .set_option(EngineOption::Seed(parsed_value.min(u64::MAX as u128) as u64));
+                format!("Seed to {}", self.engine.seed())
+            }
+            ("RootMoveRandomization", Ok(parsed_value)) => {
+                self.engine.set_option(EngineOption::RootMoveRandomization(
+                    parsed_value.min(Value::MAX as u128) as Value,
+                ));
+                format!(
+                    "RootMoveRandomization to {}",
+                    self.engine.search_options().root_randomization
+                )
+            }
+            ("UCI_Elo", Ok(parsed_value)) => {
+                self.engine
+                    .set_option(EngineOption::Elo(parsed_value.min(u32::MAX as u128) as u32));
+                format!("UCI_Elo to {}", self.engine.search_options().elo)
+            }
+            ("AspirationWindow", Ok(parsed_value)) => {
+                self.engine.set_option(EngineOption::AspirationWindow(
+                    parsed_value.min(Value::MAX as u128) as Value,
+                ));
+                format!(
+                    "AspirationWindow to {}",
+                    self.engine.search_options().params.aspiration_window
+                )
+            }
+            ("RFPMarginMultiplier", Ok(parsed_value)) => {
+                self.engine.set_option(EngineOption::RfpMarginMultiplier(
+                    parsed_value.min(Value::MAX as u128) as Value,
+                ));
+                format!(
+                    "RFPMarginMultiplier to {}",
+                    self.engine.search_options().params.rfp_margin_multiplier
+                )
+            }
+            ("NullDepthDivider", Ok(parsed_value)) => {
+                self.engine.set_option(EngineOption::NullDepthDivider(
+                    parsed_value.min(Depth::MAX as u128) as Depth,
+                ));
+                format!(
+                    "NullDepthDivider to {}",
+                    self.engine.search_options().params.null_depth_divider
+                )
+            }
+            ("LMRBaseReduction", Ok(parsed_value)) => {
+                self.engine
+                    .set_option(EngineOption::LmrBaseReduction(parsed_value as f32 / 100.0));
+                format!(
+                    "LMRBaseReduction to {}",
+                    self.engine.search_options().params.lmr_base_reduction
+                )
+            }
+            ("LMRMoveDivider", Ok(parsed_value)) => {
+                self.engine
+                    .set_option(EngineOption::LmrMoveDivider(parsed_value as f32 / 100.0));
+                format!(
+                    "LMRMoveDivider to {}",
+                    self.engine.search_options().params.lmr_move_divider
+                )
+            }
+            ("LMRPVReduction", Ok(parsed_value)) => {
+                self.engine.set_option(EngineOption::LmrPvReduction(
+                    parsed_value.min(Depth::MAX as u128) as Depth,
+                ));
+                format!(
+                    "LMRPVReduction to {}",
+                    self.engine.search_options().params.lmr_pv_reduction
+                )
+            }
+            ("LMRImprovingReduction", Ok(parsed_value)) => {
+                self.engine.set_option(EngineOption::LmrImprovingReduction(
+                    parsed_value.min(Depth::MAX as u128) as Depth,
+                ));
+                format!(
+                    "LMRImprovingReduction to {}",
+                    self.engine.search_options().params.lmr_improving_reduction
+                )
+            }
+            ("LMRHistoryDivider", Ok(parsed_value)) => {
+                self.engine.set_option(EngineOption::LmrHistoryDivider(
+                    parsed_value.min(Value::MAX as u128) as Value,
+                ));
+                format!(
+                    "LMRHistoryDivider to {}",
+                    self.engine.search_options().params.lmr_history_divider
+                )
+            }
+            ("SingularExtensionDepthMargin", Ok(parsed_value)) => {
+                self.engine.set_option(EngineOption::SingExtendDepthMargin(
+                    parsed_value.min(Depth::MAX as u128) as Depth,
+                ));
+                format!(
+                    "SingularExtensionDepthMargin to {}",
+                    self.engine.search_options().params.sing_extend_depth_margin
+                )
+            }
+            ("UCI_SanPV", _) => {
+                let san_pv = value.parse::<bool>().map_err(|_| ())?;
+                self.engine.set_option(EngineOption::SanPv(san_pv));
+                format!("UCI_SanPV to {}", self.engine.search_options().san_pv)
+            }
+            ("Ponder", _) => {
+                let ponder = value.parse::<bool>().map_err(|_| ())?;
+                self.engine.set_option(EngineOption::Ponder(ponder));
+                format!("Ponder to {}", self.engine.ponder())
+            }
+            ("UCI_Chess960", _) => {
+                let chess960 = value.parse::<bool>().map_err(|_| ())?;
+                self.engine.set_option(EngineOption::Chess960(chess960));
+                format!("UCI_Chess960 to {}", self.engine.board().chess960())
+            }
+            ("UseNNUE", _) => {
+                let use_nnue = value.parse::<bool>().map_err(|_| ())?;
+                self.engine.set_option(EngineOption::UseNnue(use_nnue));
+                format!("UseNNUE to {}", self.engine.board().use_nnue())
+            }
+            ("UCI_AnalyseMode", _) => {
+                let analyse_mode = value.parse::<bool>().map_err(|_| ())?;
+                self.engine
+                    .set_option(EngineOption::AnalyseMode(analyse_mode));
+                format!(
+                    "UCI_AnalyseMode to {}",
+                    self.engine.search_options().analyse_mode
+                )
+            }
+            ("UCI_ShowWDL", _) => {
+                let show_wdl = value.parse::<bool>().map_err(|_| ())?;
+                self.engine.set_option(EngineOption::ShowWdl(show_wdl));
+                format!("UCI_ShowWDL to {}", self.engine.search_options().show_wdl)
+            }
+            // The only spin that can go negative.
+            ("Contempt", _) => {
+                let contempt = value.parse::<Value>().map_err(|_| ())?;
+                self.engine.set_option(EngineOption::Contempt(contempt));
+                format!("Contempt to {}", self.engine.search_options().contempt)
+            }
+            ("UCI_LimitStrength", _) => {
+                let limit_strength = value.parse::<bool>().map_err(|_| ())?;
+                self.engine
+                    .set_option(EngineOption::LimitStrength(limit_strength));
+                format!(
+                    "UCI_LimitStrength to {}",
+                    self.engine.search_options().limit_strength
+                )
+            }
+            _ if Self::OPTION_NAMES.contains(&name) => {
                 return Err(());
             }
+            _ => {
+                return Ok(None);
+            }
+        };
+
+        Ok(Some(result))
+    }
+}
+
+impl SearchMaster {
+    const OPTION_NAMES: [&'static str; 26] = [
+        "Hash",
+        "Threads",
+        "Overhead",
+        "MinThinkTime",
+        "MaxThinkTime",
+        "nodestime",
+        "Seed",
+        "UCI_SanPV",
+        "UCI_AnalyseMode",
+        "UCI_ShowWDL",
+        "UCI_Chess960",
+        "UseNNUE",
+        "Ponder",
+        "RootMoveRandomization",
+        "UCI_LimitStrength",
+        "UCI_Elo",
+        "Contempt",
+        "AspirationWindow",
+        "RFPMarginMultiplier",
+        "NullDepthDivider",
+        "LMRBaseReduction",
+        "LMRMoveDivider",
+        "LMRPVReduction",
+        "LMRImprovingReduction",
+        "LMRHistoryDivider",
+        "SingularExtensionDepthMargin",
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use weiawaga::SearchParams;
+
+    use crate::search_master::*;
+
+    #[test]
+    fn unknown_option() {
+        let mut search_master = SearchMaster::new(
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        assert_eq!(
+            search_master.set_option("Style", "Aggressive".to_string()),
+            Ok(None)
+        );
+        assert_eq!(
+            search_master.set_option("UCI_Opponent", "none none human Someone".to_string()),
+            Ok(None)
+        );
+        assert_eq!(
+            search_master.set_option("Hash", "lots".to_string()),
+            Err(())
+        );
+        assert_eq!(
+            search_master.set_option("Threads", "2".to_string()),
+            Ok(Some("Threads to 2".to_string()))
+        );
+        assert_eq!(
+            search_master.set_option("Ponder", "true".to_string()),
+            Ok(Some("Ponder to true".to_string()))
+        );
+    }
+
+    #[test]
+    fn strength_options() {
+        let mut search_master = SearchMaster::new(
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        assert_eq!(
+            search_master.set_option("UCI_LimitStrength", "true".to_string()),
+            Ok(Some("UCI_LimitStrength to true".to_string()))
+        );
+        assert_eq!(
+            search_master.set_option("UCI_LimitStrength", "yes".to_string()),
+            Err(())
+        );
+        assert_eq!(
+            search_master.set_option("UCI_Elo", "1800".to_string()),
+            Ok(Some("UCI_Elo to 1800".to_string()))
+        );
+        assert_eq!(
+            search_master.set_option("UCI_Elo", "99999".to_string()),
+            Ok(Some("UCI_Elo to 2800".to_string()))
+        );
+    }
+
+    #[test]
+    fn large_spin_values_saturate() {
+        let mut search_master = SearchMaster::new(
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        assert_eq!(
+            search_master.set_option("LMRPVReduction", "300".to_string()),
+            Ok(Some(format!("LMRPVReduction to {}", Depth::MAX)))
+        );
+        assert_eq!(
+            search_master.set_option("LMRHistoryDivider", "4294967297".to_string()),
+            Ok(Some(format!("LMRHistoryDivider to {}", Value::MAX)))
+        );
+    }
+
+    #[test]
+    fn contempt_option() {
+        let mut search_master = SearchMaster::new(
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        assert_eq!(
+            search_master.set_option("Contempt", "-20".to_string()),
+            Ok(Some("Contempt to -20".to_string()))
+        );
+        assert_eq!(
+            search_master.set_option("Contempt", "500".to_string()),
+            Ok(Some("Contempt to 100".to_string()))
+        );
+        assert_eq!(
+            search_master.set_option("Contempt", "some".to_string()),
+            Err(())
+        );
+    }
+
+    #[test]
+    fn bestmove_none_on_terminal_positions() {
+        weiawaga::init();
+
+        let mut search_master = SearchMaster::new(
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        );
+        for threads in ["1", "2"] {
+            search_master
+                .set_option("Threads", threads.to_string())
+                .unwrap();
+            for fen in [
+                // Fool's mate.
+                "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3",
+                // Stalemate.
+                "k7/8/1Q6/8/8/8/8/7K b - - 0 1",
+            ] {
+                search_master.engine.set_position(Some(fen), &[]).unwrap();
+                assert_eq!(
+                    search_master.bestmove(TimeControl::FixedDepth(5), None, &[]),
+                    "bestmove (none)"
+                );
+                assert_eq!(
+                    search_master.bestmove(TimeControl::Infinite, None, &[]),
+                    "bestmove (none)"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn epd_best_score() {
+        weiawaga::init();
+
+        let mut search_master = SearchMaster::new(
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        );
+        let movetime = Duration::from_millis(100);
+        let mut best_score = |epd: &str| {
+            let position = EpdPosition::try_from(epd).unwrap();
+            search_master
+                .engine
+                .set_position(Some(&position.board.to_string()), &[])
+                .unwrap();
+            search_master.epd_best_score(&position, movetime)
         };
 
-        Ok(result)
+        // Taking the checking queen either way keeps it, while stepping
+        // out of check loses ours.
+        let kxd2 = best_score("4k3/8/8/8/8/8/3q4/3QK3 w - - bm Kxd2;");
+        let qxd2 = best_score("4k3/8/8/8/8/8/3q4/3QK3 w - - bm Qxd2;");
+        let kf1 = best_score("4k3/8/8/8/8/8/3q4/3QK3 w - - bm Kf1;");
+        assert!((kxd2 - qxd2).abs() < 100);
+        assert!(kf1 < kxd2 - 500);
+    }
+
+    #[test]
+    fn draw() {
+        let mut search_master = SearchMaster::new(
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        );
+        let moves = ["e2e4".to_string(), "c7c5".to_string(), "e1e2".to_string()];
+
+        for (fen, moves, expected_fen, ctm, castling) in [
+            (
+                None,
+                &[][..],
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                "White",
+                "KQkq",
+            ),
+            (
+                None,
+                &moves[..],
+                "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPPKPPP/RNBQ1BNR b kq - 1 2",
+                "Black",
+                "kq",
+            ),
+            (
+                Some("4k3/8/8/8/8/8/8/4K2R w K - 0 1"),
+                &[][..],
+                "4k3/8/8/8/8/8/8/4K2R w K - 0 1",
+                "White",
+                "K",
+            ),
+        ] {
+            search_master.engine.set_position(fen, moves).unwrap();
+            let board = search_master.engine.board();
+            let draw = search_master.draw();
+            let lines = draw.lines().collect::<Vec<_>>();
+
+            assert_eq!(lines.len(), 13);
+            assert_eq!(lines[..8].join("\n") + "\n", format!("{:?}", board));
+            assert_eq!(lines[8], format!("Fen: {}", expected_fen));
+            assert_eq!(lines[9], format!("Key: {:016X}", board.hash()));
+            assert_eq!(lines[10], format!("Side to move: {}", ctm));
+            assert_eq!(lines[11], format!("Castling: {}", castling));
+            assert_eq!(lines[12], format!("Eval: {}", board.eval()));
+        }
+    }
+
+    #[test]
+    fn chess960_option() {
+        weiawaga::init();
+
+        let fen = "qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1R1KR w HEhe - 1 9";
+        let moves = ["g1h1".to_string()];
+        let mut search_master = SearchMaster::new(
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        );
+        assert!(search_master
+            .engine
+            .set_position(Some(fen), &moves)
+            .is_err());
+
+        assert_eq!(
+            search_master.set_option("UCI_Chess960", "true".to_string()),
+            Ok(Some("UCI_Chess960 to true".to_string()))
+        );
+        search_master
+            .engine
+            .set_position(Some(fen), &moves)
+            .unwrap();
+        assert!(search_master.engine.board().chess960());
+        assert_eq!(
+            search_master.engine.board().to_string(),
+            "qnbnr1kr/ppp1b1pp/4p3/3p1p2/8/2NPP3/PPP1BPPP/QNB1RRK1 b he - 2 9"
+        );
+    }
+
+    #[test]
+    fn search_params_options() {
+        let mut search_master = SearchMaster::new(
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        for (name, value, result) in [
+            ("AspirationWindow", "40", "AspirationWindow to 40"),
+            ("RFPMarginMultiplier", "80", "RFPMarginMultiplier to 80"),
+            ("NullDepthDivider", "3", "NullDepthDivider to 3"),
+            ("LMRBaseReduction", "25", "LMRBaseReduction to 0.25"),
+            ("LMRMoveDivider", "200", "LMRMoveDivider to 2"),
+            (
+                "SingularExtensionDepthMargin",
+                "3",
+                "SingularExtensionDepthMargin to 3",
+            ),
+        ] {
+            assert_eq!(
+                search_master.set_option(name, value.to_string()),
+                Ok(Some(result.to_string()))
+            );
+        }
+        assert_eq!(
+            search_master.engine.search_options().params,
+            SearchParams {
+                aspiration_window: 40,
+                rfp_margin_multiplier: 80,
+                null_depth_divider: 3,
+                lmr_base_reduction: 0.25,
+                lmr_move_divider: 2.0,
+                sing_extend_depth_margin: 3,
+                ..SearchParams::default()
+            }
+        );
+        assert_eq!(
+            search_master.set_option("AspirationWindow", "wide".to_string()),
+            Err(())
+        );
     }
 }
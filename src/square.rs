@@ -83,7 +83,12 @@ impl Sub<Direction> for SQ {
 
 impl From<u8> for SQ {
     fn from(n: u8) -> Self {
-        unsafe { std::mem::transmute::<u8, Self>(n) }
+        ///////////////////////////////////////////////////////////////////
+        // Masking keeps the transmute sound if arithmetic ever produces
+        // an index past the board in a release build.
+        ///////////////////////////////////////////////////////////////////
+        debug_assert!((n as usize) < Self::N_SQUARES, "Square index out of range.");
+        unsafe { std::mem::transmute::<u8, Self>(n & 63) }
     }
 }
 
@@ -141,6 +146,7 @@ impl SQ {
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Debug)]
+#[repr(i8)]
 pub enum Direction {
     NorthNorth = 16,
     North = 8,
@@ -158,14 +164,23 @@ impl Direction {
     pub fn relative(self, c: Color) -> Direction {
         match c {
             Color::White => self,
-            Color::Black => Direction::from(-(self as i8)),
+            Color::Black => self.reverse(),
         }
     }
-}
 
-impl From<i8> for Direction {
-    fn from(n: i8) -> Self {
-        unsafe { std::mem::transmute::<i8, Self>(n) }
+    pub fn reverse(self) -> Direction {
+        match self {
+            Self::NorthNorth => Self::SouthSouth,
+            Self::North => Self::South,
+            Self::South => Self::North,
+            Self::SouthSouth => Self::NorthNorth,
+            Self::East => Self::West,
+            Self::West => Self::East,
+            Self::NorthEast => Self::SouthWest,
+            Self::NorthWest => Self::SouthEast,
+            Self::SouthEast => Self::NorthWest,
+            Self::SouthWest => Self::NorthEast,
+        }
     }
 }
 
@@ -198,6 +213,7 @@ impl Rank {
 
 impl From<u8> for Rank {
     fn from(n: u8) -> Self {
+        debug_assert!((n as usize) < Self::N_RANKS);
         unsafe { std::mem::transmute::<u8, Self>(n) }
     }
 }
@@ -247,6 +263,7 @@ impl File {
 
 impl From<u8> for File {
     fn from(n: u8) -> Self {
+        debug_assert!((n as usize) < Self::N_FILES);
         unsafe { std::mem::transmute::<u8, Self>(n) }
     }
 }
@@ -299,6 +316,7 @@ impl Diagonal {
 
 impl From<u8> for Diagonal {
     fn from(n: u8) -> Self {
+        debug_assert!((n as usize) < Self::N_DIAGONALS);
         unsafe { std::mem::transmute::<u8, Self>(n) }
     }
 }
@@ -352,6 +370,7 @@ impl AntiDiagonal {
 
 impl From<u8> for AntiDiagonal {
     fn from(n: u8) -> Self {
+        debug_assert!((n as usize) < Self::N_ANTIDIAGONALS);
         unsafe { std::mem::transmute::<u8, Self>(n) }
     }
 }
@@ -376,3 +395,52 @@ impl AntiDiagonal {
         B!(0x8000_0000_0000_0000),
     ]);
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::square::*;
+
+    #[test]
+    fn direction_relative() {
+        let directions = [
+            Direction::NorthNorth,
+            Direction::North,
+            Direction::South,
+            Direction::SouthSouth,
+            Direction::East,
+            Direction::West,
+            Direction::NorthEast,
+            Direction::NorthWest,
+            Direction::SouthEast,
+            Direction::SouthWest,
+        ];
+
+        for dir in directions {
+            assert_eq!(dir.relative(Color::White), dir);
+            assert_eq!(dir.relative(Color::Black) as i8, -(dir as i8));
+            assert_eq!(dir.relative(Color::Black).relative(Color::Black), dir);
+            assert_eq!(SQ::E4 + dir - dir, SQ::E4);
+        }
+
+        assert_eq!(SQ::E4 + Direction::North.relative(Color::Black), SQ::E3);
+        assert_eq!(SQ::E4 + Direction::NorthEast.relative(Color::Black), SQ::D3);
+        assert_eq!(
+            SQ::E4 + Direction::NorthNorth.relative(Color::Black),
+            SQ::E2
+        );
+    }
+
+    #[test]
+    fn square_from_index() {
+        assert_eq!(SQ::from(0), SQ::A1);
+        assert_eq!(SQ::from(63), SQ::H8);
+        assert!((0..64).all(|n| SQ::from(n) as u8 == n));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Square index out of range.")]
+    fn square_from_out_of_range() {
+        let _ = SQ::from(64);
+    }
+}
@@ -1,10 +1,10 @@
 use super::board::*;
-use super::moov::*;
 use super::piece::*;
 use super::types::*;
 use regex::{Match, Regex};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, LazyLock};
+use std::thread;
 use std::time::{Duration, Instant};
 
 // Some ideas taken from asymptote, which has a very elegant timer implementation.
@@ -14,6 +14,10 @@ pub enum TimeControl {
     FixedDuration(Duration),
     FixedDepth(Depth),
     FixedNodes(u64),
+    FixedDepthOrTime {
+        depth: Depth,
+        movetime: Duration,
+    },
     Variable {
         wtime: Duration,
         btime: Duration,
@@ -33,6 +37,28 @@ impl TimeControl {
         })
         .transpose()
     }
+
+    ///////////////////////////////////////////////////////////////////
+    // go mate N ends the search once it finds a mate in N moves or
+    // less. It's a limit on top of the time control rather than one of
+    // its own, and on its own it searches until the mate or a stop.
+    ///////////////////////////////////////////////////////////////////
+    pub fn parse_mate(line: &str) -> Result<Option<u32>, &'static str> {
+        let Some(re_captures) = GO_RE.captures(line) else {
+            return Ok(None);
+        };
+
+        re_captures
+            .name("mate")
+            .map(|m| {
+                m.as_str()
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&moves| moves > 0)
+                    .ok_or("Unable to parse mate.")
+            })
+            .transpose()
+    }
 }
 
 impl TryFrom<&str> for TimeControl {
@@ -45,16 +71,16 @@ impl TryFrom<&str> for TimeControl {
 
         let re_captures = GO_RE.captures(line).ok_or("Invalid go format.")?;
 
-        if re_captures.name("ponder").is_some()
-            || re_captures.name("searchmoves").is_some()
-            || re_captures.name("mate").is_some()
-        {
-            return Err("Feature is not implemented.");
-        }
+        let infinite = re_captures.name("infinite").is_some();
 
         let mut count = 0;
         let mut result = Err("Unable to parse go parameters.");
 
+        if infinite {
+            count += 1;
+            result = Ok(Self::Infinite);
+        }
+
         if let Some(m) = re_captures.name("nodes") {
             count += 1;
             result = m
@@ -64,20 +90,36 @@ impl TryFrom<&str> for TimeControl {
                 .map(Self::FixedNodes);
         }
 
-        if let Some(m) = re_captures.name("depth") {
+        let depth = re_captures
+            .name("depth")
+            .map(|m| {
+                m.as_str()
+                    .parse::<Depth>()
+                    .map_err(|_| "Unable to parse depth.")
+            })
+            .transpose()?;
+
+        if let Some(depth) = depth {
             count += 1;
-            result = m
-                .as_str()
-                .parse::<Depth>()
-                .map_err(|_| "Unable to parse depth.")
-                .map(Self::FixedDepth);
+            result = Ok(Self::FixedDepth(depth));
         }
 
-        if let Some(movetime) = Self::parse_duration(re_captures.name("movetime"))? {
+        let movetime = Self::parse_duration(re_captures.name("movetime"))?;
+
+        if let Some(movetime) = movetime {
             count += 1;
             result = Ok(Self::FixedDuration(movetime));
         }
 
+        ///////////////////////////////////////////////////////////////////
+        // Depth and movetime together mean search to the depth, but
+        // no longer than the movetime.
+        ///////////////////////////////////////////////////////////////////
+        if let (Some(depth), Some(movetime)) = (depth, movetime) {
+            count -= 1;
+            result = Ok(Self::FixedDepthOrTime { depth, movetime });
+        }
+
         let wtime = Self::parse_duration(re_captures.name("wtime"))?;
         let btime = Self::parse_duration(re_captures.name("btime"))?;
         let winc = Self::parse_duration(re_captures.name("winc"))?;
@@ -88,11 +130,11 @@ impl TryFrom<&str> for TimeControl {
         }
 
         let moves_to_go = re_captures
-            .name("moves_to_go")
+            .name("movestogo")
             .map(|m| {
                 m.as_str()
                     .parse::<u32>()
-                    .map_err(|_| "Unable to parse moves_to_go.")
+                    .map_err(|_| "Unable to parse movestogo.")
             })
             .transpose()?;
 
@@ -109,10 +151,20 @@ impl TryFrom<&str> for TimeControl {
 
         if count > 1 {
             return Err(
-                "Only one of depth, nodes, movetime, or time control parameters is allowed.",
+                "Only one of infinite, depth, nodes, movetime, or time control parameters is allowed.",
             );
         }
 
+        ///////////////////////////////////////////////////////////////////
+        // Mate and ponder don't limit the search themselves, so on their
+        // own they search until stopped, like a bare go.
+        ///////////////////////////////////////////////////////////////////
+        if count == 0
+            && (re_captures.name("mate").is_some() || re_captures.name("ponder").is_some())
+        {
+            result = Ok(Self::Infinite);
+        }
+
         result
     }
 }
@@ -130,25 +182,44 @@ static GO_RE: LazyLock<Regex> = LazyLock::new(|| {
                     \s+winc\s+(?P<winc>\d+) |
                     \s+binc\s+(?P<binc>\d+) |
                     \s+mate\s+(?P<mate>\d+) |
-                    \s+movestogo\s+(?P<movestogo>\d+)
+                    \s+movestogo\s+(?P<movestogo>\d+) |
+                    \s+(?P<infinite>infinite) |
+                    \s+(?P<ponder>ponder)
                 )*
             $",
     )
     .expect("Go regex should be valid.")
 });
 
+///////////////////////////////////////////////////////////////////
+// Limits from the user on top of the time control. The think time
+// bounds only apply when we manage our own clock, and a zero max
+// think time means no cap. A nonzero nodestime reads the clock as
+// nodes, that many per millisecond, so results don't depend on how
+// fast the machine happens to be.
+///////////////////////////////////////////////////////////////////
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimerOptions {
+    pub overhead: Duration,
+    pub min_think_time: Duration,
+    pub max_think_time: Duration,
+    pub nodestime: u64,
+}
+
 #[derive(Clone)]
 pub struct Timer {
     control: TimeControl,
     start_time: Instant,
     global_stop: Arc<AtomicBool>,
     local_stop: bool,
+    pondering: Arc<AtomicBool>,
+    local_pondering: bool,
     nodes: Arc<AtomicU64>,
     times_checked: u64,
     time_target: Duration,
     time_maximum: Duration,
-    overhead: Duration,
-    last_best_move: Option<Move>,
+    options: TimerOptions,
+    mate_limit: Option<u32>,
 }
 
 impl Timer {
@@ -157,29 +228,59 @@ impl Timer {
         control: TimeControl,
         stop: Arc<AtomicBool>,
         nodes: Arc<AtomicU64>,
-        overhead: Duration,
+        options: TimerOptions,
     ) -> Self {
-        let (time_target, time_maximum) = if let TimeControl::Variable { .. } = control {
-            Self::calculate_time(board, control)
+        let (time_target, time_maximum, options) = if let TimeControl::Variable { .. } = control {
+            Self::calculate_time(board, control, options)
         } else {
-            (Duration::ZERO, Duration::ZERO)
+            (Duration::ZERO, Duration::ZERO, options)
         };
 
         Self {
             start_time: Instant::now(),
             global_stop: stop,
             local_stop: false,
+            pondering: Arc::new(AtomicBool::new(false)),
+            local_pondering: false,
             nodes,
             control,
-            overhead,
+            options,
             time_target,
             time_maximum,
-            last_best_move: None,
             times_checked: 0,
+            mate_limit: None,
         }
     }
 
-    fn calculate_time(board: &Board, control: TimeControl) -> (Duration, Duration) {
+    pub fn with_mate_limit(mut self, mate_limit: Option<u32>) -> Self {
+        self.mate_limit = mate_limit;
+        self
+    }
+
+    pub fn with_pondering(mut self, pondering: Arc<AtomicBool>) -> Self {
+        self.local_pondering = pondering.load(Ordering::SeqCst);
+        self.pondering = pondering;
+        self
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Until the GUI sends ponderhit we search as if it were infinite.
+    // Then the clock starts, and the time control takes over from
+    // there with the time it was given.
+    ///////////////////////////////////////////////////////////////////
+    fn pondering(&mut self) -> bool {
+        if self.local_pondering && !self.pondering.load(Ordering::Relaxed) {
+            self.local_pondering = false;
+            self.start_time = Instant::now();
+        }
+        self.local_pondering
+    }
+
+    fn calculate_time(
+        board: &Board,
+        control: TimeControl,
+        options: TimerOptions,
+    ) -> (Duration, Duration, TimerOptions) {
         let TimeControl::Variable {
             wtime,
             btime,
@@ -196,15 +297,50 @@ impl Timer {
             Color::Black => (btime, binc),
         };
 
-        let mtg = moves_to_go.unwrap_or(40);
+        let mtg = moves_to_go.unwrap_or(40).max(1);
+
+        ///////////////////////////////////////////////////////////////////
+        // Keep a small buffer on the clock for the move that reaches the
+        // time control, then share the rest over the moves left. With
+        // only a move or two to go this spends most of the clock.
+        ///////////////////////////////////////////////////////////////////
+        let reserve = (time / 20).min(Self::MAX_RESERVE);
+        let available = time - reserve;
+
+        let time_target = available.min(available / mtg + inc.unwrap_or(Duration::ZERO));
+        let time_maximum = time_target + (available - time_target) / 4;
+
+        let (time_target, time_maximum) = if options.max_think_time > Duration::ZERO {
+            (
+                time_target.min(options.max_think_time),
+                time_maximum.min(options.max_think_time),
+            )
+        } else {
+            (time_target, time_maximum)
+        };
+
+        // Waiting out the min think time mustn't lose on time.
+        let options = TimerOptions {
+            min_think_time: options.min_think_time.min(time_maximum),
+            ..options
+        };
 
-        let time_target = time.min(time / mtg + inc.unwrap_or(Duration::ZERO));
-        let time_maximum = time_target + (time - time_target) / 4;
+        ///////////////////////////////////////////////////////////////////
+        // With nodestime the budget is kept in nodes from here on. They
+        // still live in a Duration, one node to the nanosecond, so the
+        // checks below don't need to care which one they're looking at.
+        ///////////////////////////////////////////////////////////////////
+        if options.nodestime > 0 {
+            let budget = |time: Duration| {
+                Duration::from_nanos((time.as_millis() as u64).saturating_mul(options.nodestime))
+            };
+            return (budget(time_target), budget(time_maximum), options);
+        }
 
-        (time_target, time_maximum)
+        (time_target, time_maximum, options)
     }
 
-    pub fn start_check(&mut self, depth: Depth) -> bool {
+    pub fn start_check(&mut self, depth: Depth, stability: u32) -> bool {
         if self.local_stop {
             return false;
         }
@@ -214,6 +350,10 @@ impl Timer {
             return false;
         }
 
+        if self.pondering() {
+            return true;
+        }
+
         // Always search to a depth of at least 1
         if depth <= 1 {
             return true;
@@ -221,10 +361,21 @@ impl Timer {
 
         let start = match self.control {
             TimeControl::Infinite => true,
-            TimeControl::FixedDuration(duration) => self.elapsed() + self.overhead <= duration,
+            TimeControl::FixedDuration(duration) => {
+                self.elapsed() + self.options.overhead <= duration
+            }
             TimeControl::FixedDepth(stop_depth) => depth <= stop_depth,
-            TimeControl::FixedNodes(_) => true,
-            TimeControl::Variable { .. } => self.elapsed() + self.overhead <= self.time_target / 2,
+            // The hard limit can only end an iteration part way through,
+            // so don't start one that it would cut off right away.
+            TimeControl::FixedNodes(stop_nodes) => self.nodes() < stop_nodes,
+            TimeControl::FixedDepthOrTime {
+                depth: stop_depth,
+                movetime,
+            } => depth <= stop_depth && self.elapsed() + self.options.overhead <= movetime,
+            TimeControl::Variable { .. } => {
+                self.elapsed() < self.options.min_think_time
+                    || self.used() <= self.soft_limit(stability) / 2
+            }
         };
 
         if !start {
@@ -253,12 +404,23 @@ impl Timer {
             return true;
         }
 
+        if self.pondering() {
+            return false;
+        }
+
         let stop = match self.control {
             TimeControl::Infinite => false,
-            TimeControl::FixedDuration(duration) => self.elapsed() + self.overhead >= duration,
-            TimeControl::Variable { .. } => self.elapsed() + self.overhead >= self.time_maximum,
+            TimeControl::FixedDuration(duration) => {
+                self.elapsed() + self.options.overhead >= duration
+            }
+            TimeControl::Variable { .. } => {
+                self.elapsed() >= self.options.min_think_time && self.used() >= self.time_maximum
+            }
             TimeControl::FixedDepth(_) => false,
             TimeControl::FixedNodes(stop_nodes) => nodes >= stop_nodes,
+            TimeControl::FixedDepthOrTime { movetime, .. } => {
+                self.elapsed() + self.options.overhead >= movetime
+            }
         };
 
         if stop {
@@ -270,6 +432,41 @@ impl Timer {
         stop
     }
 
+    ///////////////////////////////////////////////////////////////////
+    // Hold on to a result that came early, like a forced move, until the
+    // min think time is up. A ponder search has to wait for ponderhit
+    // first, and an infinite one for stop, however it ended, since UCI
+    // doesn't allow a bestmove before either. Only go mate may end on
+    // its own. A stop from the GUI still ends the wait.
+    ///////////////////////////////////////////////////////////////////
+    pub fn wait_min_think_time(&mut self) {
+        while (self.pondering()
+            || (matches!(self.control, TimeControl::Infinite) && self.mate_limit.is_none()))
+            && !self.global_stop.load(Ordering::Relaxed)
+        {
+            thread::sleep(Self::MIN_THINK_POLL);
+        }
+
+        if !matches!(self.control, TimeControl::Variable { .. }) {
+            return;
+        }
+
+        while self.elapsed() < self.options.min_think_time
+            && !self.global_stop.load(Ordering::Relaxed)
+        {
+            thread::sleep(
+                self.options
+                    .min_think_time
+                    .saturating_sub(self.elapsed())
+                    .min(Self::MIN_THINK_POLL),
+            );
+        }
+    }
+
+    pub fn mate_limit(&self) -> Option<u32> {
+        self.mate_limit
+    }
+
     pub fn stop(&mut self) {
         self.local_stop = true;
         self.global_stop.store(true, Ordering::SeqCst);
@@ -279,6 +476,19 @@ impl Timer {
         self.start_time.elapsed()
     }
 
+    ///////////////////////////////////////////////////////////////////
+    // How much of a managed clock is gone. Nodes reach the shared
+    // counter in batches of CHECK_FREQ, so with nodestime each thread
+    // can be up to a batch behind the others, and the search can overrun
+    // its budget by about that much per thread.
+    ///////////////////////////////////////////////////////////////////
+    fn used(&self) -> Duration {
+        if self.options.nodestime > 0 {
+            return Duration::from_nanos(self.nodes());
+        }
+        self.elapsed() + self.options.overhead
+    }
+
     pub fn nodes(&self) -> u64 {
         self.nodes.load(Ordering::Relaxed) + self.times_checked
     }
@@ -287,18 +497,383 @@ impl Timer {
         self.local_stop
     }
 
-    pub fn update(&mut self, best_move: Option<Move>) {
-        if self
-            .last_best_move
-            .is_some_and(|last_move| Some(last_move) != best_move)
-        {
-            self.time_target = self.time_maximum.min(self.time_target * 3 / 2);
-        }
+    fn soft_limit(&self, stability: u32) -> Duration {
+        self.time_maximum
+            .min(self.time_target * Self::stability_factor(stability) / 100)
+    }
 
-        self.last_best_move = best_move;
+    ///////////////////////////////////////////////////////////////////
+    // Percent of the time target to use, given how many iterations in
+    // a row the best move has stayed the same. A best move that just
+    // changed gets up to 50% more time, and one that has held for
+    // several iterations gets 25% less.
+    ///////////////////////////////////////////////////////////////////
+    pub fn stability_factor(stability: u32) -> u32 {
+        match stability {
+            0 => 150,
+            1 => 125,
+            2 => 110,
+            3 => 100,
+            4 | 5 => 90,
+            _ => 75,
+        }
     }
 }
 
 impl Timer {
     const CHECK_FREQ: u64 = 4096;
+    const MAX_RESERVE: Duration = Duration::from_secs(1);
+    const MIN_THINK_POLL: Duration = Duration::from_millis(5);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::bitboard::*;
+    use crate::magics::*;
+    use crate::timer::*;
+
+    fn timer(control: TimeControl) -> Timer {
+        Timer::new(
+            &Board::new(),
+            control,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicU64::new(0)),
+            TimerOptions::default(),
+        )
+    }
+
+    #[test]
+    fn parse_depth_and_movetime() {
+        assert_eq!(
+            TimeControl::try_from("go depth 12 movetime 5000"),
+            Ok(TimeControl::FixedDepthOrTime {
+                depth: 12,
+                movetime: Duration::from_millis(5000),
+            })
+        );
+        assert_eq!(
+            TimeControl::try_from("go movetime 5000 depth 12"),
+            TimeControl::try_from("go depth 12 movetime 5000")
+        );
+        assert!(TimeControl::try_from("go nodes 1000 movetime 5000").is_err());
+        assert!(TimeControl::try_from("go depth 12 movetime 5000 nodes 1000").is_err());
+    }
+
+    #[test]
+    fn parse_infinite() {
+        assert_eq!(TimeControl::try_from("go"), Ok(TimeControl::Infinite));
+        assert_eq!(
+            TimeControl::try_from("go infinite"),
+            Ok(TimeControl::Infinite)
+        );
+        assert_eq!(
+            TimeControl::try_from("go infinite ponder"),
+            Ok(TimeControl::Infinite)
+        );
+        assert_eq!(
+            TimeControl::try_from("go ponder infinite"),
+            Ok(TimeControl::Infinite)
+        );
+        assert_eq!(
+            TimeControl::try_from("go ponder"),
+            Ok(TimeControl::Infinite)
+        );
+        assert!(TimeControl::try_from("go infinite depth 10").is_err());
+        assert!(TimeControl::try_from("go infinite wtime 1000 btime 1000").is_err());
+        assert_eq!(
+            TimeControl::try_from("go depth 10"),
+            Ok(TimeControl::FixedDepth(10))
+        );
+    }
+
+    #[test]
+    fn parse_mate() {
+        // On its own, mate searches until it finds one.
+        assert_eq!(
+            TimeControl::try_from("go mate 3"),
+            Ok(TimeControl::Infinite)
+        );
+        assert_eq!(TimeControl::parse_mate("go mate 3"), Ok(Some(3)));
+        assert_eq!(TimeControl::parse_mate("go depth 10"), Ok(None));
+        assert!(TimeControl::parse_mate("go mate 0").is_err());
+
+        // Otherwise the time control keeps the budget.
+        for (line, control) in [
+            (
+                "go mate 3 movetime 5000",
+                TimeControl::FixedDuration(Duration::from_millis(5000)),
+            ),
+            ("go nodes 20000 mate 3", TimeControl::FixedNodes(20000)),
+            ("go mate 3 depth 10", TimeControl::FixedDepth(10)),
+            (
+                "go wtime 1000 btime 2000 mate 3",
+                TimeControl::Variable {
+                    wtime: Duration::from_millis(1000),
+                    btime: Duration::from_millis(2000),
+                    winc: None,
+                    binc: None,
+                    moves_to_go: None,
+                },
+            ),
+        ] {
+            assert_eq!(TimeControl::try_from(line), Ok(control), "{}", line);
+            assert_eq!(TimeControl::parse_mate(line), Ok(Some(3)), "{}", line);
+        }
+
+        let mut timer = timer(TimeControl::Infinite).with_mate_limit(Some(3));
+        assert_eq!(timer.mate_limit(), Some(3));
+        assert!(timer.start_check(Depth::MAX - 1, 0));
+        assert!(!timer.stop_check());
+
+        // A mate search isn't held for stop once it's found one.
+        timer.wait_min_think_time();
+    }
+
+    fn variable(time: u64, moves_to_go: Option<u32>) -> Timer {
+        timer(TimeControl::Variable {
+            wtime: Duration::from_millis(time),
+            btime: Duration::from_millis(time),
+            winc: None,
+            binc: None,
+            moves_to_go,
+        })
+    }
+
+    #[test]
+    fn parse_movestogo() {
+        assert_eq!(
+            TimeControl::try_from("go wtime 1000 btime 2000 movestogo 5"),
+            Ok(TimeControl::Variable {
+                wtime: Duration::from_millis(1000),
+                btime: Duration::from_millis(2000),
+                winc: None,
+                binc: None,
+                moves_to_go: Some(5),
+            })
+        );
+    }
+
+    #[test]
+    fn movestogo_time_allocation() {
+        init_magics();
+        init_bb();
+
+        let time = Duration::from_secs(10);
+
+        let last_move = variable(10_000, Some(1));
+        assert!(last_move.time_target >= time * 9 / 10);
+        assert!(last_move.time_maximum < time);
+
+        let moves_40 = variable(10_000, Some(40));
+        assert!(moves_40.time_target <= time / 40);
+        assert!(moves_40.time_maximum < time / 2);
+
+        let targets = [1, 2, 3, 10, 40].map(|mtg| variable(10_000, Some(mtg)).time_target);
+        assert!(targets.windows(2).all(|w| w[0] > w[1]));
+
+        assert_eq!(variable(10_000, None).time_target, moves_40.time_target);
+        assert!(variable(10_000, Some(0)).time_maximum < time);
+    }
+
+    #[test]
+    fn depth_or_time_stops_at_depth() {
+        init_magics();
+        init_bb();
+
+        let mut timer = timer(TimeControl::FixedDepthOrTime {
+            depth: 5,
+            movetime: Duration::from_secs(60),
+        });
+        assert!(timer.start_check(5, 0));
+        assert!(!(0..2 * Timer::CHECK_FREQ).any(|_| timer.stop_check()));
+        assert!(!timer.start_check(6, 0));
+    }
+
+    #[test]
+    fn depth_or_time_stops_at_time() {
+        init_magics();
+        init_bb();
+
+        let mut timer = timer(TimeControl::FixedDepthOrTime {
+            depth: 100,
+            movetime: Duration::from_millis(10),
+        });
+        assert!(timer.start_check(2, 0));
+        thread::sleep(Duration::from_millis(20));
+        assert!((0..2 * Timer::CHECK_FREQ).any(|_| timer.stop_check()));
+        assert!(!timer.start_check(3, 0));
+    }
+
+    fn with_options(control: TimeControl, options: TimerOptions) -> Timer {
+        Timer::new(
+            &Board::new(),
+            control,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicU64::new(0)),
+            options,
+        )
+    }
+
+    #[test]
+    fn max_think_time_caps_allocation() {
+        init_magics();
+        init_bb();
+
+        let control = TimeControl::Variable {
+            wtime: Duration::from_secs(600),
+            btime: Duration::from_secs(600),
+            winc: Some(Duration::from_secs(5)),
+            binc: Some(Duration::from_secs(5)),
+            moves_to_go: None,
+        };
+        let max_think_time = Duration::from_millis(500);
+        let capped = with_options(
+            control,
+            TimerOptions {
+                max_think_time,
+                ..Default::default()
+            },
+        );
+        assert!(timer(control).time_target > max_think_time);
+        assert!(capped.time_target <= max_think_time);
+        assert!(capped.time_maximum <= max_think_time);
+
+        // Other controls ignore the limits.
+        let mut fixed = with_options(
+            TimeControl::FixedDepth(3),
+            TimerOptions {
+                min_think_time: Duration::from_secs(60),
+                ..Default::default()
+            },
+        );
+        assert!(!fixed.start_check(4, 0));
+        fixed.wait_min_think_time();
+        assert!(fixed.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn min_think_time_waits() {
+        init_magics();
+        init_bb();
+
+        let clock = |ms| TimeControl::Variable {
+            wtime: Duration::from_millis(ms),
+            btime: Duration::from_millis(ms),
+            winc: None,
+            binc: None,
+            moves_to_go: None,
+        };
+        let options = TimerOptions {
+            min_think_time: Duration::from_millis(200),
+            ..Default::default()
+        };
+
+        let mut timer = with_options(clock(60_000), options);
+        assert_eq!(timer.options.min_think_time, options.min_think_time);
+        assert!(timer.start_check(20, 0));
+        assert!(!(0..2 * Timer::CHECK_FREQ).any(|_| timer.stop_check()));
+        timer.wait_min_think_time();
+        assert!(timer.elapsed() >= options.min_think_time);
+
+        // It can't outlast the clock, which here is shorter than the min
+        // think time.
+        let mut timer = with_options(clock(100), options);
+        assert_eq!(timer.options.min_think_time, timer.time_maximum);
+        assert!(timer.time_maximum < Duration::from_millis(100));
+        timer.wait_min_think_time();
+        assert!(timer.elapsed() >= timer.time_maximum);
+        assert!(timer.elapsed() < Duration::from_millis(100));
+
+        // A stop from the GUI cuts the wait short.
+        let mut timer = with_options(clock(60_000), options);
+        timer.global_stop.store(true, Ordering::SeqCst);
+        timer.wait_min_think_time();
+        assert!(timer.elapsed() < options.min_think_time);
+    }
+
+    #[test]
+    fn nodestime_budgets_nodes() {
+        init_magics();
+        init_bb();
+
+        let control = TimeControl::Variable {
+            wtime: Duration::from_millis(1000),
+            btime: Duration::from_millis(1000),
+            winc: Some(Duration::from_millis(10)),
+            binc: Some(Duration::from_millis(10)),
+            moves_to_go: None,
+        };
+        let options = TimerOptions {
+            nodestime: 100,
+            ..Default::default()
+        };
+        let clock = timer(control);
+        let mut timer = with_options(control, options);
+        assert_eq!(
+            timer.time_target,
+            Duration::from_nanos(clock.time_target.as_millis() as u64 * 100)
+        );
+        assert_eq!(
+            timer.time_maximum,
+            Duration::from_nanos(clock.time_maximum.as_millis() as u64 * 100)
+        );
+
+        // The wall clock doesn't count, only the nodes searched.
+        thread::sleep(clock.time_maximum);
+        assert!(timer.start_check(20, 0));
+        assert!(!(0..2 * Timer::CHECK_FREQ).any(|_| timer.stop_check()));
+        timer
+            .nodes
+            .store(timer.time_maximum.as_nanos() as u64, Ordering::SeqCst);
+        assert!((0..2 * Timer::CHECK_FREQ).any(|_| timer.stop_check()));
+    }
+
+    #[test]
+    fn nodes_stop_between_iterations() {
+        init_magics();
+        init_bb();
+
+        let mut timer = timer(TimeControl::FixedNodes(10_000));
+        assert!(timer.start_check(2, 0));
+        timer.nodes.store(9_999, Ordering::SeqCst);
+        assert!(timer.start_check(3, 0));
+        timer.nodes.store(10_000, Ordering::SeqCst);
+        assert!(!timer.start_check(4, 0));
+        assert!(timer.global_stop.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn ponder_waits_for_ponderhit() {
+        init_magics();
+        init_bb();
+
+        let pondering = Arc::new(AtomicBool::new(true));
+        let mut pondered = timer(TimeControl::FixedDuration(Duration::from_millis(10)))
+            .with_pondering(pondering.clone());
+        thread::sleep(Duration::from_millis(20));
+        assert!(pondered.start_check(20, 0));
+        assert!(!(0..2 * Timer::CHECK_FREQ).any(|_| pondered.stop_check()));
+
+        // The clock starts over on ponderhit.
+        pondering.store(false, Ordering::SeqCst);
+        assert!(pondered.start_check(20, 0));
+        assert!(pondered.elapsed() < Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(20));
+        assert!((0..2 * Timer::CHECK_FREQ).any(|_| pondered.stop_check()));
+        assert!(!pondered.start_check(21, 0));
+
+        // Without a ponderhit the result is held until the GUI stops us.
+        let pondering = Arc::new(AtomicBool::new(true));
+        let mut held = timer(TimeControl::FixedDepth(1)).with_pondering(pondering.clone());
+        let stop = held.global_stop.clone();
+        let waiter = thread::spawn(move || {
+            held.wait_min_think_time();
+            held.elapsed()
+        });
+        thread::sleep(Duration::from_millis(20));
+        stop.store(true, Ordering::SeqCst);
+        assert!(waiter.join().unwrap() >= Duration::from_millis(20));
+    }
 }
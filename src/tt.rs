@@ -1,6 +1,7 @@
 #[cfg(target_arch = "x86_64")]
 use core::arch::x86_64;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
 
 use super::board::*;
 use super::moov::*;
@@ -11,21 +12,31 @@ use super::types::*;
 // Transposition Table Entry
 ///////////////////////////////////////////////////////////////////
 
-#[derive(Eq, PartialEq, Copy, Clone)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub struct TTEntry {
     value: Value,
     best_move: Option<Move>,
     depth: Depth,
     flag: Bound,
+    static_eval: Value,
+    generation: u8,
 }
 
 impl TTEntry {
-    pub fn new(value: Value, best_move: Option<Move>, depth: Depth, flag: Bound) -> Self {
+    pub fn new(
+        value: Value,
+        best_move: Option<Move>,
+        depth: Depth,
+        flag: Bound,
+        static_eval: Value,
+    ) -> Self {
         TTEntry {
             best_move,
             depth,
             value,
             flag,
+            static_eval,
+            generation: 0,
         }
     }
 
@@ -44,28 +55,55 @@ impl TTEntry {
     pub fn flag(&self) -> Bound {
         self.flag
     }
+
+    // The uncorrected evaluation of the position.
+    pub fn static_eval(&self) -> Value {
+        self.static_eval
+    }
 }
 
 impl Default for TTEntry {
     fn default() -> Self {
-        Self {
-            best_move: None,
-            depth: 0,
-            value: 0,
-            flag: Bound::Exact,
-        }
+        Self::new(0, None, 0, Bound::Exact, 0)
     }
 }
 
+///////////////////////////////////////////////////////////////////
+// Packed as move (16 bits), value (16), static eval (16), depth (8),
+// then the bound in the low 2 bits of the last byte and the generation
+// above it. Scores never get past the mate value, so 16 bits hold them.
+///////////////////////////////////////////////////////////////////
 impl From<Hash> for TTEntry {
     fn from(value: Hash) -> Self {
-        unsafe { std::mem::transmute(value) }
+        let flag = match (value >> 56) & 0b11 {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        Self {
+            best_move: Some(value as MoveInt).filter(|&m| m != 0).map(Move::from),
+            value: (value >> 16) as i16 as Value,
+            static_eval: (value >> 32) as i16 as Value,
+            depth: (value >> 48) as u8 as Depth,
+            flag,
+            generation: (value >> 58) as u8,
+        }
     }
 }
 
 impl From<TTEntry> for Hash {
     fn from(value: TTEntry) -> Self {
-        unsafe { std::mem::transmute(value) }
+        let flag = match value.flag {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        value.best_move.map_or(0, |m| m.move_int()) as Hash
+            | (value.value as i16 as u16 as Hash) << 16
+            | (value.static_eval as i16 as u16 as Hash) << 32
+            | (value.depth as u8 as Hash) << 48
+            | flag << 56
+            | ((value.generation & TT::GENERATION_MASK) as Hash) << 58
     }
 }
 
@@ -74,27 +112,45 @@ impl From<TTEntry> for Hash {
 ///////////////////////////////////////////////////////////////////
 
 pub struct TT {
-    table: Vec<AtomicEntry>,
+    table: Vec<Cluster>,
     bitmask: Hash,
+    generation: u8,
 }
 
 impl TT {
     pub fn new(mb_size: usize) -> Self {
-        assert_eq!(size_of::<TTEntry>(), 8);
-        let upper_limit = mb_size * 1024 * 1024 / size_of::<AtomicEntry>() + 1;
-        let count = upper_limit.next_power_of_two() / 2;
+        let count = Self::cluster_count(mb_size);
         let mut table = Vec::with_capacity(count);
 
         for _ in 0..count {
-            table.push(AtomicEntry::default());
+            table.push(Cluster::default());
         }
 
         TT {
             table,
             bitmask: count as Hash - 1,
+            generation: 0,
         }
     }
 
+    // The table is only reallocated when the size actually changes.
+    pub fn resize(&mut self, mb_size: usize) {
+        if Self::cluster_count(mb_size) != self.table.len() {
+            *self = Self::new(mb_size);
+        }
+    }
+
+    // Rounded down to a power of two so indexing is a mask.
+    fn cluster_count(mb_size: usize) -> usize {
+        let upper_limit = mb_size * 1024 * 1024 / size_of::<Cluster>() + 1;
+        upper_limit.next_power_of_two() / 2
+    }
+
+    // Called once per go, so entries from earlier searches can be told apart.
+    pub fn new_search(&mut self) {
+        self.generation = self.generation.wrapping_add(1) & Self::GENERATION_MASK;
+    }
+
     pub fn insert(
         &self,
         board: &Board,
@@ -102,58 +158,134 @@ impl TT {
         value: Value,
         best_move: Option<Move>,
         flag: Bound,
+        static_eval: Value,
     ) {
-        unsafe {
-            self.table
-                .get_unchecked(self.index(board))
-                .write(board.hash(), TTEntry::new(value, best_move, depth, flag))
+        self.store(
+            board.hash(),
+            TTEntry::new(value, best_move, depth, flag, static_eval),
+        );
+    }
+
+    fn store(&self, hash: Hash, entry: TTEntry) {
+        let cluster = unsafe { self.table.get_unchecked(self.index(hash)) };
+        let entry = TTEntry {
+            generation: self.generation,
+            ..entry
+        };
+        cluster.0[self.replacement(cluster, hash)].write(hash, entry);
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // An entry for the same position is always overwritten, and so is an
+    // empty one. Otherwise the least valuable entry goes, with every
+    // search an entry has sat through counting against its depth.
+    ///////////////////////////////////////////////////////////////////
+    fn replacement(&self, cluster: &Cluster, hash: Hash) -> usize {
+        let mut replace_idx = 0;
+        let mut replace_worth = i32::MAX;
+
+        for (i, atomic_entry) in cluster.0.iter().enumerate() {
+            let Some((entry_hash, entry)) = atomic_entry.load() else {
+                return i;
+            };
+            if entry_hash == hash {
+                return i;
+            }
+
+            let worth = entry.depth as i32 - Self::AGE_WEIGHT * self.age(entry) as i32;
+            if worth < replace_worth {
+                replace_idx = i;
+                replace_worth = worth;
+            }
         }
+        replace_idx
     }
 
     pub fn probe(&self, board: &Board) -> Option<TTEntry> {
-        unsafe {
-            self.table
-                .get_unchecked(self.index(board))
-                .read(board.hash())
-        }
+        self.load(board.hash())
     }
 
+    fn load(&self, hash: Hash) -> Option<TTEntry> {
+        let cluster = unsafe { self.table.get_unchecked(self.index(hash)) };
+        cluster
+            .0
+            .iter()
+            .find_map(|atomic_entry| atomic_entry.read(hash))
+    }
+
+    ///////////////////////////////////////////////////////////////////
+    // Large tables are split between threads, since touching every
+    // cluster takes a noticeable amount of time on its own.
+    ///////////////////////////////////////////////////////////////////
     pub fn clear(&mut self) {
-        self.table
-            .iter_mut()
-            .for_each(|entry| *entry = AtomicEntry::default());
+        let n_threads = if self.mb_size() >= Self::PARALLEL_CLEAR_MB {
+            thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            1
+        };
+        let chunk_size = self.table.len().div_ceil(n_threads);
+
+        thread::scope(|s| {
+            for chunk in self.table.chunks_mut(chunk_size) {
+                s.spawn(|| chunk.fill_with(Cluster::default));
+            }
+        });
+        self.generation = 0;
+    }
+
+    fn index(&self, hash: Hash) -> usize {
+        (hash & self.bitmask) as usize
     }
 
-    fn index(&self, board: &Board) -> usize {
-        (board.hash() & self.bitmask) as usize
+    // How many searches ago the entry was written.
+    fn age(&self, entry: TTEntry) -> u8 {
+        self.generation.wrapping_sub(entry.generation) & Self::GENERATION_MASK
     }
 
     pub fn mb_size(&self) -> usize {
-        self.table.len() * size_of::<AtomicEntry>() / 1024 / 1024
+        self.table.len() * size_of::<Cluster>() / 1024 / 1024
     }
 
     pub fn hashfull(&self) -> usize {
-        // Sample the first 1000 entries to estimate how full the table is.
+        // Sample the first 1000 entries to estimate how full the table is with this search.
         self.table
             .iter()
+            .flat_map(|cluster| cluster.0.iter())
             .take(1000)
-            .filter(|&entry| entry.is_used())
+            .filter_map(|atomic_entry| atomic_entry.load())
+            .filter(|&(_, entry)| self.age(entry) == 0)
             .count()
     }
 
     #[allow(unused_variables)]
-    pub fn prefetch(&self, board: &Board) {
+    pub fn prefetch(&self, hash: Hash) {
         #[cfg(target_arch = "x86_64")]
         unsafe {
-            let ptr =
-                self.table.get_unchecked(self.index(board)) as *const AtomicEntry as *const i8;
+            let ptr = self.table.get_unchecked(self.index(hash)) as *const Cluster as *const i8;
             x86_64::_mm_prefetch(ptr, x86_64::_MM_HINT_T0);
         }
     }
 }
 
+impl TT {
+    const CLUSTER_SIZE: usize = 4;
+    const GENERATION_MASK: u8 = 0b111111;
+    const AGE_WEIGHT: i32 = 4;
+    const PARALLEL_CLEAR_MB: usize = 64;
+}
+
+///////////////////////////////////////////////////////////////////
+// The entries one hash maps to, sharing a cache line.
+///////////////////////////////////////////////////////////////////
+
+#[derive(Default)]
+#[repr(align(64))]
+struct Cluster([AtomicEntry; TT::CLUSTER_SIZE]);
+
 ///////////////////////////////////////////////////////////////////
-// Atomic value for storage.
+// Atomic value for storage. The key is stored xored with the data,
+// so an entry torn by two threads writing at once fails the check
+// like any other collision.
 ///////////////////////////////////////////////////////////////////
 
 #[derive(Default)]
@@ -164,14 +296,20 @@ struct AtomicEntry {
 
 impl AtomicEntry {
     fn read(&self, hash: Hash) -> Option<TTEntry> {
+        self.load()
+            .filter(|&(entry_hash, _)| entry_hash == hash)
+            .map(|(_, entry)| entry)
+    }
+
+    fn load(&self) -> Option<(Hash, TTEntry)> {
         let (checksum, data) = (
             self.checksum.load(Ordering::Relaxed),
             self.data.load(Ordering::Relaxed),
         );
-        if checksum ^ data == hash {
-            Some(TTEntry::from(data))
-        } else {
+        if checksum == Hash::default() {
             None
+        } else {
+            Some((checksum ^ data, TTEntry::from(data)))
         }
     }
 
@@ -180,8 +318,138 @@ impl AtomicEntry {
         self.checksum.store(hash ^ data, Ordering::Relaxed);
         self.data.store(data, Ordering::Relaxed);
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bitboard::*;
+    use crate::magics::*;
+    use crate::square::*;
+    use crate::tt::*;
+
+    #[test]
+    fn entry_packing() {
+        for (value, best_move, depth, flag, static_eval) in [
+            (0, None, 0, Bound::Exact, 0),
+            (
+                -31999,
+                Some(Move::new(SQ::E2, SQ::E4, MoveFlags::DoublePush)),
+                1,
+                Bound::Upper,
+                -1,
+            ),
+            (
+                25,
+                Some(Move::new(SQ::A7, SQ::B8, MoveFlags::PcQueen)),
+                -3,
+                Bound::Lower,
+                -15999,
+            ),
+            (32000, None, Depth::MAX, Bound::Lower, 15999),
+        ] {
+            for generation in [0, 1, TT::GENERATION_MASK] {
+                let entry = TTEntry {
+                    generation,
+                    ..TTEntry::new(value, best_move, depth, flag, static_eval)
+                };
+                assert_eq!(TTEntry::from(Hash::from(entry)), entry);
+            }
+        }
+    }
+
+    #[test]
+    fn probe_checks_key() {
+        init_magics();
+        init_bb();
+
+        let tt = TT::new(1);
+        let board = Board::new();
+        let mut other = Board::new();
+        other.push_str("e2e4").unwrap();
+
+        tt.insert(&board, 5, 30, None, Bound::Exact, -12);
+        assert_eq!(
+            tt.probe(&board),
+            Some(TTEntry::new(30, None, 5, Bound::Exact, -12))
+        );
+        assert_eq!(tt.probe(&other), None);
+
+        // A key that lands in the same cluster doesn't match either.
+        assert_eq!(tt.load(board.hash() ^ (tt.bitmask + 1)), None);
+    }
+
+    #[test]
+    fn resize_and_clear() {
+        let mut tt = TT::new(3);
+        assert_eq!(tt.mb_size(), 2);
+        assert_eq!(tt.table.len(), (tt.bitmask + 1) as usize);
+
+        tt.store(1, TTEntry::new(10, None, 3, Bound::Lower, 0));
+        tt.resize(2);
+        assert!(tt.load(1).is_some());
+        tt.resize(4);
+        assert_eq!(tt.mb_size(), 4);
+        assert!(tt.load(1).is_none());
+
+        let mut tt = TT::new(TT::PARALLEL_CLEAR_MB);
+        tt.new_search();
+        for i in (0..tt.table.len() as Hash * 4).step_by(997) {
+            tt.store(i, TTEntry::new(10, None, 3, Bound::Lower, 0));
+        }
+        tt.clear();
+        assert_eq!(tt.generation, 0);
+        assert!(tt.table.iter().all(|cluster| cluster
+            .0
+            .iter()
+            .all(|atomic_entry| atomic_entry.load().is_none())));
+    }
 
-    fn is_used(&self) -> bool {
-        self.checksum.load(Ordering::Relaxed) != Hash::default()
+    #[test]
+    fn aging() {
+        let mut tt = TT::new(1);
+        let clusters = tt.bitmask + 1;
+        let key = |i: Hash| 7 + i * clusters;
+        let entry = |depth| TTEntry::new(0, None, depth, Bound::Exact, 0);
+
+        // Within a search the shallowest entry goes.
+        for (i, depth) in [10, 2, 10, 10].into_iter().enumerate() {
+            tt.store(key(i as Hash), entry(depth));
+        }
+        tt.store(key(4), entry(1));
+        assert!(tt.load(key(1)).is_none());
+        assert!([0, 2, 3, 4].iter().all(|&i| tt.load(key(i)).is_some()));
+
+        // Enough searches later a deeper entry goes before fresh ones.
+        tt.clear();
+        tt.store(key(0), entry(20));
+        for _ in 0..6 {
+            tt.new_search();
+        }
+        for i in 1..4 {
+            tt.store(key(i), entry(5));
+        }
+        tt.store(key(4), entry(1));
+        assert!(tt.load(key(0)).is_none());
+        assert!((1..5).all(|i| tt.load(key(i)).is_some()));
+
+        // Refilling the table with shallow entries pushes out deep ones, once
+        // they're old enough.
+        tt.clear();
+        let entries = clusters * TT::CLUSTER_SIZE as Hash;
+        for i in 0..entries {
+            tt.store(i, entry(30));
+        }
+        assert_eq!(tt.hashfull(), 1000);
+        tt.new_search();
+        assert_eq!(tt.hashfull(), 0);
+        for _ in 0..7 {
+            tt.new_search();
+        }
+        for i in entries..2 * entries {
+            tt.store(i, entry(1));
+        }
+        assert_eq!(tt.hashfull(), 1000);
+        assert!((0..entries).all(|i| tt.load(i).is_none()));
+        assert!((entries..2 * entries).all(|i| tt.load(i).is_some()));
     }
 }
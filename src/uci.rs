@@ -1,26 +1,28 @@
 use super::search_master::*;
-use super::timer::*;
-use super::types::*;
 use regex::Regex;
 use std::io::BufRead;
 use std::sync::LazyLock;
 use std::{io, sync, thread};
+use weiawaga::{Board, Depth, TimeControl, Value, BENCH_DEPTH};
 // A lot of this nice uci implementation was inspired by Asymptote.
 
 pub struct UCI {
     _main_thread: thread::JoinHandle<()>,
     main_tx: sync::mpsc::Sender<UCICommand>,
     stop: sync::Arc<sync::atomic::AtomicBool>,
+    pondering: sync::Arc<sync::atomic::AtomicBool>,
 }
 
 impl UCI {
     pub fn new() -> Self {
         let (main_tx, main_rx) = sync::mpsc::channel();
         let stop = sync::Arc::new(sync::atomic::AtomicBool::new(false));
+        let pondering = sync::Arc::new(sync::atomic::AtomicBool::new(false));
         Self {
             main_tx,
             stop: stop.clone(),
-            _main_thread: thread::spawn(move || SearchMaster::new(stop).run(main_rx)),
+            pondering: pondering.clone(),
+            _main_thread: thread::spawn(move || SearchMaster::new(stop, pondering).run(main_rx)),
         }
     }
 
@@ -38,11 +40,21 @@ impl UCI {
             match UCICommand::try_from(line.as_str()) {
                 Ok(cmd) => match cmd {
                     UCICommand::Quit => return,
+                    UCICommand::Register => (),
                     UCICommand::Stop => self.stop.store(true, sync::atomic::Ordering::SeqCst),
-                    _ => self
-                        .main_tx
-                        .send(cmd)
-                        .expect("Unable to communicate with main thread."),
+                    UCICommand::PonderHit => {
+                        self.pondering.store(false, sync::atomic::Ordering::SeqCst)
+                    }
+                    _ => {
+                        // Set here rather than when the search starts, so a
+                        // ponderhit that arrives first isn't lost.
+                        if let UCICommand::Go { ponder, .. } = cmd {
+                            self.pondering.store(ponder, sync::atomic::Ordering::SeqCst);
+                        }
+                        self.main_tx
+                            .send(cmd)
+                            .expect("Unable to communicate with main thread.")
+                    }
                 },
                 Err(e) => {
                     eprintln!("{}", e);
@@ -60,16 +72,39 @@ pub enum UCICommand {
         fen: Option<String>,
         moves: Vec<String>,
     },
-    Go(TimeControl),
+    Go {
+        time_control: TimeControl,
+        mate_limit: Option<u32>,
+        search_moves: Vec<String>,
+        ponder: bool,
+    },
     Quit,
     Stop,
-    Perft(Depth),
+    PonderHit,
+    Perft {
+        depth: Depth,
+        hashed: bool,
+        divide: bool,
+        board: Option<Box<Board>>,
+    },
     Option {
         name: String,
         value: String,
     },
     Eval,
     Fen,
+    Pgn,
+    Draw,
+    Register,
+    Bench {
+        depth: Depth,
+        signature: bool,
+    },
+    Epd {
+        path: String,
+        movetime_ms: u64,
+        margin: Option<Value>,
+    },
 }
 
 impl TryFrom<&str> for UCICommand {
@@ -81,9 +116,12 @@ impl TryFrom<&str> for UCICommand {
         let command = match line {
             "ucinewgame" => Self::UCINewGame,
             "stop" => Self::Stop,
+            "ponderhit" => Self::PonderHit,
             "uci" => Self::UCI,
             "eval" => Self::Eval,
             "fen" => Self::Fen,
+            "pgn" => Self::Pgn,
+            "d" | "board" => Self::Draw,
             "quit" => Self::Quit,
             "isready" => Self::IsReady,
             _ => {
@@ -93,8 +131,14 @@ impl TryFrom<&str> for UCICommand {
                     Self::parse_position(line)?
                 } else if line.starts_with("perft") {
                     Self::parse_perft(line)?
+                } else if line.starts_with("bench") {
+                    Self::parse_bench(line)?
+                } else if line.starts_with("epd") {
+                    Self::parse_epd(line)?
                 } else if line.starts_with("setoption") {
                     Self::parse_option(line)?
+                } else if line.starts_with("register") {
+                    Self::Register
                 } else {
                     return Err("Unknown command.");
                 }
@@ -105,9 +149,29 @@ impl TryFrom<&str> for UCICommand {
 }
 
 impl UCICommand {
+    ///////////////////////////////////////////////////////////////////
+    // Everything after searchmoves is a move, so it's split off before
+    // the time control is parsed.
+    ///////////////////////////////////////////////////////////////////
     fn parse_go(line: &str) -> Result<Self, &'static str> {
+        let (line, search_moves) = match line.split_once("searchmoves") {
+            Some((line, moves)) => (
+                line.trim_end(),
+                moves
+                    .split_whitespace()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<String>>(),
+            ),
+            None => (line, Vec::new()),
+        };
+
         let time_control = TimeControl::try_from(line)?;
-        Ok(Self::Go(time_control))
+        Ok(Self::Go {
+            time_control,
+            mate_limit: TimeControl::parse_mate(line)?,
+            search_moves,
+            ponder: line.split_whitespace().any(|token| token == "ponder"),
+        })
     }
 
     fn parse_position(line: &str) -> Result<Self, &'static str> {
@@ -121,6 +185,7 @@ impl UCICommand {
             .then(|| {
                 re_captures
                     .name("fen")
+                    .or_else(|| re_captures.name("bare_fen"))
                     .map(|m| m.as_str().to_string())
                     .ok_or("Missing starting position.")
             })
@@ -149,10 +214,10 @@ impl UCICommand {
             .map(|m| m.as_str().to_string())
             .ok_or("Invalid name in option.")?;
 
+        // Buttons don't have a value.
         let value = caps
             .name("value")
-            .map(|m| m.as_str().to_string())
-            .ok_or("Invalid value in option.")?;
+            .map_or_else(String::new, |m| m.as_str().to_string());
 
         Ok(Self::Option { name, value })
     }
@@ -160,13 +225,74 @@ impl UCICommand {
     fn parse_perft(line: &str) -> Result<Self, &'static str> {
         let re_captures = PERFT_RE.captures(line).ok_or("Invalid perft format.")?;
 
-        re_captures
+        let depth = re_captures
             .name("depth")
             .ok_or("Invalid perft format.")?
             .as_str()
             .parse::<Depth>()
-            .map_err(|_| "Invalid depth.")
-            .map(Self::Perft)
+            .map_err(|_| "Invalid depth.")?;
+
+        let board = re_captures
+            .name("fen")
+            .map(|m| {
+                Board::try_from(m.as_str())
+                    .map(Box::new)
+                    .map_err(|_| "Invalid perft fen.")
+            })
+            .transpose()?;
+
+        let hashed = re_captures.name("hash").is_some();
+        let divide = re_captures.name("divide").is_some();
+
+        Ok(Self::Perft {
+            depth,
+            hashed,
+            divide,
+            board,
+        })
+    }
+
+    fn parse_bench(line: &str) -> Result<Self, &'static str> {
+        let re_captures = BENCH_RE.captures(line).ok_or("Invalid bench format.")?;
+
+        let depth = re_captures
+            .name("depth")
+            .map(|m| m.as_str().parse::<Depth>().map_err(|_| "Invalid depth."))
+            .transpose()?
+            .unwrap_or(BENCH_DEPTH);
+
+        Ok(Self::Bench {
+            depth,
+            signature: re_captures.name("signature").is_some(),
+        })
+    }
+
+    fn parse_epd(line: &str) -> Result<Self, &'static str> {
+        let re_captures = EPD_RE.captures(line).ok_or("Invalid epd format.")?;
+
+        let path = re_captures
+            .name("path")
+            .map(|m| m.as_str().to_string())
+            .ok_or("Invalid epd format.")?;
+
+        let movetime_ms = re_captures
+            .name("movetime")
+            .ok_or("Invalid epd format.")?
+            .as_str()
+            .parse::<u64>()
+            .map_err(|_| "Invalid movetime.")?;
+
+        let margin = re_captures
+            .name("margin")
+            .map(|m| m.as_str().parse::<Value>())
+            .transpose()
+            .map_err(|_| "Invalid margin.")?;
+
+        Ok(Self::Epd {
+            path,
+            movetime_ms,
+            margin,
+        })
     }
 }
 
@@ -174,7 +300,11 @@ static POSITION_RE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(
         r"(?x)^
                 position\s+
-                (?:(?P<startpos>startpos)|fen\s+(?P<fen>.+?))
+                (?:
+                    (?P<startpos>startpos) |
+                    fen\s+(?P<fen>.+?) |
+                    (?P<bare_fen>[pnbrqkPNBRQK1-8]+(?:/[pnbrqkPNBRQK1-8]+){7}(?:\s+.+?)?)
+                )
                 (\s+moves\s+(?P<moves>(?:.+?)+))?
             $",
     )
@@ -185,8 +315,8 @@ static OPTION_RE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(
         r"(?x)^
                 setoption\s+
-                name\s+(?P<name>.*?)\s+
-                value\s+(?P<value>.+)
+                name\s+(?P<name>.*?)
+                (\s+value\s+(?P<value>.+))?
             $",
     )
     .expect("Failed to compile option regex.")
@@ -196,8 +326,310 @@ static PERFT_RE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(
         r"(?x)^
                 perft\s+
-                (?P<depth>.*?)
+                (?P<divide>divide\s+)?
+                (?P<depth>\S+)
+                (\s+(?P<hash>hash))?
+                (\s+fen\s+(?P<fen>.+))?
             $",
     )
     .expect("Failed to compile perft regex.")
 });
+
+static BENCH_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?x)^
+                bench
+                (\s+(?P<depth>\d+))?
+                (\s+(?P<signature>signature))?
+            $",
+    )
+    .expect("Failed to compile bench regex.")
+});
+
+// The path can contain spaces, so the movetime and margin come last.
+static EPD_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?x)^
+                epd\s+
+                (?P<path>.+?)\s+
+                (?P<movetime>\d+)
+                (\s+margin\s+(?P<margin>\d+))?
+            $",
+    )
+    .expect("Failed to compile epd regex.")
+});
+
+#[cfg(test)]
+mod tests {
+    use crate::uci::*;
+
+    fn parse_position(line: &str) -> (Option<String>, Vec<String>) {
+        match UCICommand::try_from(line) {
+            Ok(UCICommand::Position { fen, moves }) => (fen, moves),
+            _ => panic!("Expected a position command."),
+        }
+    }
+
+    #[test]
+    fn position() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+        assert_eq!(parse_position("position startpos"), (None, vec![]));
+        assert_eq!(
+            parse_position("position startpos moves e2e4 e7e5"),
+            (None, vec!["e2e4".to_string(), "e7e5".to_string()])
+        );
+        assert_eq!(
+            parse_position(&format!("position fen {}", kiwipete)),
+            (Some(kiwipete.to_string()), vec![])
+        );
+        assert_eq!(
+            parse_position(&format!("position fen {} moves e1g1", kiwipete)),
+            (Some(kiwipete.to_string()), vec!["e1g1".to_string()])
+        );
+        assert_eq!(
+            parse_position(&format!("position {}", kiwipete)),
+            (Some(kiwipete.to_string()), vec![])
+        );
+        assert_eq!(
+            parse_position(&format!("position {} moves e1g1 a8b8", kiwipete)),
+            (
+                Some(kiwipete.to_string()),
+                vec!["e1g1".to_string(), "a8b8".to_string()]
+            )
+        );
+
+        assert!(UCICommand::try_from("position").is_err());
+        assert!(UCICommand::try_from("position e2e4 moves e7e5").is_err());
+    }
+
+    #[test]
+    fn bench() {
+        assert!(matches!(
+            UCICommand::try_from("bench"),
+            Ok(UCICommand::Bench {
+                depth: BENCH_DEPTH,
+                signature: false
+            })
+        ));
+        assert!(matches!(
+            UCICommand::try_from("bench signature"),
+            Ok(UCICommand::Bench {
+                depth: BENCH_DEPTH,
+                signature: true
+            })
+        ));
+        assert!(matches!(
+            UCICommand::try_from("bench 13"),
+            Ok(UCICommand::Bench {
+                depth: 13,
+                signature: false
+            })
+        ));
+        assert!(matches!(
+            UCICommand::try_from("bench 6 signature"),
+            Ok(UCICommand::Bench {
+                depth: 6,
+                signature: true
+            })
+        ));
+        assert!(UCICommand::try_from("bench sig").is_err());
+        assert!(UCICommand::try_from("bench 1000").is_err());
+        assert!(UCICommand::try_from("benchmark").is_err());
+    }
+
+    #[test]
+    fn epd() {
+        assert!(matches!(
+            UCICommand::try_from("epd wac.epd 1000"),
+            Ok(UCICommand::Epd { path, movetime_ms: 1000, margin: None }) if path == "wac.epd"
+        ));
+        assert!(matches!(
+            UCICommand::try_from("epd test suites/ecm.epd 250"),
+            Ok(UCICommand::Epd { path, movetime_ms: 250, margin: None })
+                if path == "test suites/ecm.epd"
+        ));
+        assert!(matches!(
+            UCICommand::try_from("epd wac.epd 1000 margin 50"),
+            Ok(UCICommand::Epd { path, movetime_ms: 1000, margin: Some(50) }) if path == "wac.epd"
+        ));
+        assert!(matches!(
+            UCICommand::try_from("epd margin tests.epd 500 margin 0"),
+            Ok(UCICommand::Epd { path, movetime_ms: 500, margin: Some(0) })
+                if path == "margin tests.epd"
+        ));
+        assert!(UCICommand::try_from("epd wac.epd 1000 margin").is_err());
+        assert!(UCICommand::try_from("epd wac.epd 1000 margin -50").is_err());
+        assert!(UCICommand::try_from("epd wac.epd").is_err());
+        assert!(UCICommand::try_from("epd 1000").is_err());
+        assert!(UCICommand::try_from("epd wac.epd fast").is_err());
+    }
+
+    #[test]
+    fn go_searchmoves() {
+        let parse_go = |line| match UCICommand::try_from(line) {
+            Ok(UCICommand::Go {
+                time_control,
+                search_moves,
+                ..
+            }) => (time_control, search_moves),
+            _ => panic!("Expected a go command."),
+        };
+
+        assert_eq!(parse_go("go depth 6"), (TimeControl::FixedDepth(6), vec![]));
+        assert_eq!(
+            parse_go("go searchmoves e2e4 d2d4"),
+            (
+                TimeControl::Infinite,
+                vec!["e2e4".to_string(), "d2d4".to_string()]
+            )
+        );
+        assert_eq!(
+            parse_go("go wtime 1000 btime 2000 winc 10 binc 20 searchmoves g1f3"),
+            (
+                TimeControl::Variable {
+                    wtime: std::time::Duration::from_millis(1000),
+                    btime: std::time::Duration::from_millis(2000),
+                    winc: Some(std::time::Duration::from_millis(10)),
+                    binc: Some(std::time::Duration::from_millis(20)),
+                    moves_to_go: None,
+                },
+                vec!["g1f3".to_string()]
+            )
+        );
+        assert!(UCICommand::try_from("go depth searchmoves e2e4").is_err());
+    }
+
+    #[test]
+    fn go_mate() {
+        let parse_go = |line| match UCICommand::try_from(line) {
+            Ok(UCICommand::Go {
+                time_control,
+                mate_limit,
+                ..
+            }) => (time_control, mate_limit),
+            _ => panic!("Expected a go command."),
+        };
+
+        assert_eq!(parse_go("go mate 4"), (TimeControl::Infinite, Some(4)));
+        assert_eq!(
+            parse_go("go movetime 500 mate 2 searchmoves e2e4"),
+            (
+                TimeControl::FixedDuration(std::time::Duration::from_millis(500)),
+                Some(2)
+            )
+        );
+        assert_eq!(parse_go("go depth 6"), (TimeControl::FixedDepth(6), None));
+        assert!(UCICommand::try_from("go mate 0").is_err());
+    }
+
+    #[test]
+    fn ponder() {
+        let ponder = |line| match UCICommand::try_from(line) {
+            Ok(UCICommand::Go { ponder, .. }) => ponder,
+            _ => panic!("Expected a go command."),
+        };
+
+        assert!(ponder("go ponder wtime 1000 btime 1000"));
+        assert!(ponder("go ponder infinite searchmoves e2e4"));
+        assert!(ponder("go ponder"));
+        assert!(ponder("go ponder searchmoves e2e4"));
+        assert!(matches!(
+            UCICommand::try_from("go ponder searchmoves e2e4 d2d4"),
+            Ok(UCICommand::Go { time_control: TimeControl::Infinite, search_moves, .. })
+                if search_moves == ["e2e4", "d2d4"]
+        ));
+        assert!(!ponder("go wtime 1000 btime 1000"));
+        assert!(!ponder("go infinite"));
+        assert!(matches!(
+            UCICommand::try_from("ponderhit"),
+            Ok(UCICommand::PonderHit)
+        ));
+    }
+
+    #[test]
+    fn pgn_and_draw() {
+        assert!(matches!(UCICommand::try_from("pgn"), Ok(UCICommand::Pgn)));
+        assert!(UCICommand::try_from("pgn full").is_err());
+        assert!(matches!(UCICommand::try_from("d"), Ok(UCICommand::Draw)));
+        assert!(matches!(
+            UCICommand::try_from("board"),
+            Ok(UCICommand::Draw)
+        ));
+    }
+
+    #[test]
+    fn register() {
+        assert!(matches!(
+            UCICommand::try_from("register later"),
+            Ok(UCICommand::Register)
+        ));
+        assert!(matches!(
+            UCICommand::try_from("register name Someone code 1234"),
+            Ok(UCICommand::Register)
+        ));
+        assert!(matches!(
+            UCICommand::try_from("setoption name UCI_Chess960 value false"),
+            Ok(UCICommand::Option { name, value }) if name == "UCI_Chess960" && value == "false"
+        ));
+        assert!(matches!(
+            UCICommand::try_from("setoption name Clear Hash"),
+            Ok(UCICommand::Option { name, value }) if name == "Clear Hash" && value.is_empty()
+        ));
+    }
+
+    #[test]
+    fn perft() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+        assert!(matches!(
+            UCICommand::try_from("perft 5"),
+            Ok(UCICommand::Perft {
+                depth: 5,
+                hashed: false,
+                divide: false,
+                board: None
+            })
+        ));
+        assert!(matches!(
+            UCICommand::try_from(format!("perft 3 fen {}", kiwipete).as_str()),
+            Ok(UCICommand::Perft { depth: 3, hashed: false, divide: false, board: Some(board) })
+                if board.to_string() == kiwipete
+        ));
+        assert!(matches!(
+            UCICommand::try_from("perft 6 hash"),
+            Ok(UCICommand::Perft {
+                depth: 6,
+                hashed: true,
+                divide: false,
+                board: None
+            })
+        ));
+        assert!(matches!(
+            UCICommand::try_from(format!("perft 3 hash fen {}", kiwipete).as_str()),
+            Ok(UCICommand::Perft { depth: 3, hashed: true, divide: false, board: Some(board) })
+                if board.to_string() == kiwipete
+        ));
+
+        assert!(matches!(
+            UCICommand::try_from("perft divide 4"),
+            Ok(UCICommand::Perft {
+                depth: 4,
+                hashed: false,
+                divide: true,
+                board: None
+            })
+        ));
+        assert!(matches!(
+            UCICommand::try_from(format!("perft divide 3 hash fen {}", kiwipete).as_str()),
+            Ok(UCICommand::Perft { depth: 3, hashed: true, divide: true, board: Some(board) })
+                if board.to_string() == kiwipete
+        ));
+
+        assert!(UCICommand::try_from("perft").is_err());
+        assert!(UCICommand::try_from("perft five").is_err());
+        assert!(UCICommand::try_from("perft divide").is_err());
+        assert!(UCICommand::try_from("perft 3 fen").is_err());
+        assert!(UCICommand::try_from("perft 3 fen 8/8/8 w - - 0 1").is_err());
+    }
+}
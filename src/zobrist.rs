@@ -1,3 +1,4 @@
+use super::bitboard::*;
 use super::piece::*;
 use super::square::*;
 use super::types::*;
@@ -9,9 +10,12 @@ use rand::{RngCore, SeedableRng};
 pub struct Hasher {
     hash: Hash,
     material_hash: Hash,
+    pawn_hash: Hash,
     zobrist_table: PieceMap<SQMap<Hash>>,
     zobrist_ep: FileMap<Hash>,
+    zobrist_castling: [Hash; 4],
     zobrist_color: Hash,
+    castling_masks: [Bitboard; 4],
 }
 
 impl Hasher {
@@ -32,12 +36,17 @@ impl Hasher {
             .iter_mut()
             .for_each(|hash| *hash = rng.next_u64());
 
+        let zobrist_castling = std::array::from_fn(|_| rng.next_u64());
+
         Self {
             hash: 0,
             material_hash: 0,
+            pawn_hash: 0,
             zobrist_table,
             zobrist_ep,
+            zobrist_castling,
             zobrist_color,
+            castling_masks: [Bitboard::ZERO; 4],
         }
     }
 
@@ -45,25 +54,66 @@ impl Hasher {
         let update = self.zobrist_table[pc][from_sq] ^ self.zobrist_table[pc][to_sq];
         self.hash ^= update;
         self.material_hash ^= update;
+        if pc.type_of() == PieceType::Pawn {
+            self.pawn_hash ^= update;
+        }
     }
 
     pub fn update_piece(&mut self, pc: Piece, sq: SQ) {
         let update = self.zobrist_table[pc][sq];
         self.hash ^= update;
         self.material_hash ^= update;
+        if pc.type_of() == PieceType::Pawn {
+            self.pawn_hash ^= update;
+        }
     }
 
     pub fn update_ep(&mut self, file: File) {
-        self.hash ^= self.zobrist_ep[file];
+        self.hash ^= self.ep_key(file);
+    }
+
+    // Castling rights are tracked by the history entry bitboard, where a right is lost
+    // once its king or rook square has been touched. Toggle the key of every right whose
+    // availability differs between the two entries. The masks come from the position,
+    // since the rooks can start anywhere in chess960, and are empty for missing rights.
+    pub fn update_castling(&mut self, before: Bitboard, after: Bitboard) {
+        self.hash ^= self.castling_key(before, after);
     }
 
     pub fn update_color(&mut self) {
-        self.hash ^= self.zobrist_color;
+        self.hash ^= self.color_key();
+    }
+
+    pub fn piece_key(&self, pc: Piece, sq: SQ) -> Hash {
+        self.zobrist_table[pc][sq]
+    }
+
+    pub fn ep_key(&self, file: File) -> Hash {
+        self.zobrist_ep[file]
+    }
+
+    pub fn castling_key(&self, before: Bitboard, after: Bitboard) -> Hash {
+        self.castling_masks
+            .into_iter()
+            .zip(self.zobrist_castling)
+            .filter(|&(mask, _)| {
+                (before & mask == Bitboard::ZERO) != (after & mask == Bitboard::ZERO)
+            })
+            .fold(0, |key, (_, hash)| key ^ hash)
+    }
+
+    pub fn color_key(&self) -> Hash {
+        self.zobrist_color
+    }
+
+    pub fn set_castling_masks(&mut self, castling_masks: [Bitboard; 4]) {
+        self.castling_masks = castling_masks;
     }
 
     pub fn clear(&mut self) {
         self.hash = 0;
         self.material_hash = 0;
+        self.pawn_hash = 0;
     }
 
     pub fn hash(&self) -> Hash {
@@ -73,4 +123,8 @@ impl Hasher {
     pub fn material_hash(&self) -> Hash {
         self.material_hash
     }
+
+    pub fn pawn_hash(&self) -> Hash {
+        self.pawn_hash
+    }
 }